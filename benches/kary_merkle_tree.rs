@@ -0,0 +1,74 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use plonky2::{iop::witness::PartialWitness, plonk::{circuit_builder::CircuitBuilder, circuit_data::CircuitConfig}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+use plonky2_merkle_trees::{
+  config_profile::ConfigProfile,
+  simple_merkle_tree::{
+    circuit::{add_verify_merkle_proof, verify_merkle_proof_circuit},
+    kary_merkle_tree::KaryMerkleTree,
+    kary_plonky2_verifier::{add_verify_kary_merkle_proof, verify_kary_merkle_proof_circuit},
+    simple_merkle_tree::MerkleTree
+  }
+};
+
+// 2^12 leaves, which is a whole number of levels for arity 2, 4 and 8
+const LOG_NR_LEAVES: usize = 12;
+
+// Number of gates of the proof constraints only, before building adds the gates for public inputs and constants
+fn print_gate_counts() {
+  let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+  add_verify_merkle_proof(&mut builder, LOG_NR_LEAVES);
+  println!("binary Merkle proof, depth {}: {} gates", LOG_NR_LEAVES, builder.num_gates());
+  for arity in [2, 4, 8] {
+    let depth = LOG_NR_LEAVES / arity.trailing_zeros() as usize;
+    let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+    add_verify_kary_merkle_proof(&mut builder, arity, depth);
+    println!("{}-ary Merkle proof, depth {}: {} gates", arity, depth, builder.num_gates());
+  }
+}
+
+// Building trees of arity 2, 4 and 8 and proving a Merkle proof in a circuit, against the binary MerkleTree
+fn bench_kary_merkle_tree(c: &mut Criterion) {
+  print_gate_counts();
+
+  let mut group = c.benchmark_group("kary_merkle_tree");
+  group.sample_size(10);
+  let nr_leaves: u64 = 1 << LOG_NR_LEAVES;
+  let leaves: Vec<GoldilocksField> = (0..nr_leaves).map(GoldilocksField::from_canonical_u64).collect();
+  let leaf_index = 1234;
+
+  group.bench_with_input(BenchmarkId::new("build", "binary"), &leaves, |b, leaves| {
+    b.iter(|| MerkleTree::build(leaves.clone()))
+  });
+  let tree = MerkleTree::build(leaves.clone());
+  let (circuit_data, targets) = verify_merkle_proof_circuit(tree.count_levels, false, &ConfigProfile::default());
+  let proof = tree.get_merkle_proof(leaf_index);
+  group.bench_function(BenchmarkId::new("prove", "binary"), |b| {
+    b.iter(|| {
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, leaves[leaf_index], leaf_index, &proof);
+      circuit_data.prove(pw).unwrap()
+    })
+  });
+
+  for arity in [2, 4, 8] {
+    group.bench_with_input(BenchmarkId::new("build", arity), &leaves, |b, leaves| {
+      b.iter(|| KaryMerkleTree::build(leaves.clone(), arity))
+    });
+
+    let tree = KaryMerkleTree::build(leaves.clone(), arity);
+    let (circuit_data, targets) = verify_kary_merkle_proof_circuit(arity, tree.depth, false, &ConfigProfile::default());
+    let proof = tree.get_merkle_proof(leaf_index);
+    group.bench_function(BenchmarkId::new("prove", arity), |b| {
+      b.iter(|| {
+        let mut pw = PartialWitness::new();
+        targets.set_witness(&mut pw, leaves[leaf_index], leaf_index, &proof);
+        circuit_data.prove(pw).unwrap()
+      })
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_kary_merkle_tree);
+criterion_main!(benches);
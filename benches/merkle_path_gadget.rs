@@ -0,0 +1,82 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use plonky2::{hash::{hash_types::{HashOut, HashOutTarget}, poseidon::PoseidonHash}, iop::{target::BoolTarget, witness::{PartialWitness, WitnessWrite}}, plonk::{circuit_builder::CircuitBuilder, circuit_data::{CircuitConfig, CircuitData}, config::PoseidonGoldilocksConfig}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+use plonky2_merkle_trees::{gadgets::MerkleGadgets, mmr::common::pick_hash};
+
+const DEPTHS: [usize; 4] = [4, 8, 16, 32];
+
+// The path step before swap-then-hash: hash both orders and pick one, which takes 2 Poseidon permutations per level
+fn two_hashes_path(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  leaf_hash: HashOutTarget,
+  siblings: &[HashOutTarget],
+  siblings_on_left: &[BoolTarget]) -> HashOutTarget {
+  let mut next_hash = leaf_hash;
+  for (sibling, on_left) in siblings.iter().zip(siblings_on_left) {
+    let option1 = builder.hash_or_noop::<PoseidonHash>([sibling.elements.to_vec(), next_hash.elements.to_vec()].concat());
+    let option2 = builder.hash_or_noop::<PoseidonHash>([next_hash.elements.to_vec(), sibling.elements.to_vec()].concat());
+    next_hash = pick_hash(builder, option1, option2, *on_left);
+  }
+  next_hash
+}
+
+struct PathCircuit {
+  circuit_data: CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+  leaf_hash: HashOutTarget,
+  siblings: Vec<HashOutTarget>,
+  siblings_on_left: Vec<BoolTarget>,
+  // Number of gates of the path only, before building adds the gates for public inputs and constants
+  nr_path_gates: usize
+}
+
+fn build_path_circuit(depth: usize, swap_then_hash: bool) -> PathCircuit {
+  let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+  let leaf_hash = builder.add_virtual_hash();
+  let siblings = builder.add_virtual_hashes(depth);
+  let siblings_on_left: Vec<BoolTarget> = (0..depth).map(|_| builder.add_virtual_bool_target_safe()).collect();
+  let root = if swap_then_hash {
+    builder.verify_merkle_path(leaf_hash, &siblings, &siblings_on_left)
+  } else {
+    two_hashes_path(&mut builder, leaf_hash, &siblings, &siblings_on_left)
+  };
+  let nr_path_gates = builder.num_gates();
+  builder.register_public_inputs(&root.elements);
+  PathCircuit {
+    circuit_data: builder.build::<PoseidonGoldilocksConfig>(),
+    leaf_hash: leaf_hash,
+    siblings: siblings,
+    siblings_on_left: siblings_on_left,
+    nr_path_gates: nr_path_gates
+  }
+}
+
+fn set_witness(pw: &mut PartialWitness<GoldilocksField>, circuit: &PathCircuit) {
+  pw.set_hash_target(circuit.leaf_hash, HashOut::from_partial(&[GoldilocksField::ONE]));
+  for (i, (sibling, on_left)) in circuit.siblings.iter().zip(&circuit.siblings_on_left).enumerate() {
+    pw.set_hash_target(*sibling, HashOut::from_partial(&[GoldilocksField::from_canonical_usize(i)]));
+    pw.set_bool_target(*on_left, i % 3 == 0);
+  }
+}
+
+// Proving a Merkle path of depth 4 to 32 with the 2-hash step against the swap-then-hash step of verify_merkle_path
+fn bench_merkle_path_gadget(c: &mut Criterion) {
+  let mut group = c.benchmark_group("merkle_path_gadget");
+  group.sample_size(10);
+  for depth in DEPTHS {
+    for (name, swap_then_hash) in [("two_hashes", false), ("swap_then_hash", true)] {
+      let circuit = build_path_circuit(depth, swap_then_hash);
+      println!("{}, depth {}: {} gates, degree 2^{}", name, depth, circuit.nr_path_gates, circuit.circuit_data.common.degree_bits());
+      group.bench_function(BenchmarkId::new(name, depth), |b| {
+        b.iter(|| {
+          let mut pw = PartialWitness::new();
+          set_witness(&mut pw, &circuit);
+          circuit.circuit_data.prove(pw).unwrap()
+        })
+      });
+    }
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_merkle_path_gadget);
+criterion_main!(benches);
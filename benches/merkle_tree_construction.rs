@@ -0,0 +1,29 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+use plonky2_merkle_trees::simple_merkle_tree::simple_merkle_tree::MerkleTree;
+
+// Building a Merkle Tree, and extracting proofs from it, for up to 2^20 leaves
+fn bench_merkle_tree_construction(c: &mut Criterion) {
+  let mut group = c.benchmark_group("merkle_tree_construction");
+  group.sample_size(10);
+
+  for log_nr_leaves in [12, 16, 20] {
+    let nr_leaves: u64 = 1 << log_nr_leaves;
+    let leaves: Vec<GoldilocksField> = (0..nr_leaves).map(GoldilocksField::from_canonical_u64).collect();
+
+    group.bench_with_input(BenchmarkId::new("build", nr_leaves), &leaves, |b, leaves| {
+      b.iter(|| MerkleTree::build(leaves.clone()))
+    });
+
+    let tree = MerkleTree::build(leaves.clone());
+    group.bench_with_input(BenchmarkId::new("get_merkle_proof_x1000", nr_leaves), &tree, |b, tree| {
+      b.iter(|| {
+        (0..1000).map(|i| tree.get_merkle_proof((i * 7919) % tree.nr_leaves)).collect::<Vec<_>>()
+      })
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_merkle_tree_construction);
+criterion_main!(benches);
@@ -0,0 +1,33 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+use plonky2_merkle_trees::mmr::merkle_mountain_ranges::MMR;
+
+// Compares building an MMR leaf by leaf with building it from the whole batch at once
+fn bench_mmr_construction(c: &mut Criterion) {
+  let mut group = c.benchmark_group("mmr_construction");
+  group.sample_size(10);
+
+  for log_nr_leaves in [10, 14, 17] {
+    // Not a power of 2, so the MMR ends up with several peaks
+    let nr_leaves: u64 = (1 << log_nr_leaves) + 37;
+    let leaves: Vec<GoldilocksField> = (0..nr_leaves).map(GoldilocksField::from_canonical_u64).collect();
+
+    group.bench_with_input(BenchmarkId::new("add_leaf", nr_leaves), &leaves, |b, leaves| {
+      b.iter(|| {
+        let mut mmr = MMR::new();
+        for leaf in leaves {
+          mmr.add_leaf(*leaf);
+        }
+        mmr
+      })
+    });
+
+    group.bench_with_input(BenchmarkId::new("from_leaves", nr_leaves), &leaves, |b, leaves| {
+      b.iter(|| MMR::from_leaves(leaves))
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_mmr_construction);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use plonky2::{hash::hash_types::{HashOut, HashOutTarget}, iop::{target::Target, witness::{PartialWitness, WitnessWrite}}, plonk::{circuit_builder::CircuitBuilder, circuit_data::{CircuitConfig, CircuitData}, config::PoseidonGoldilocksConfig}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+use plonky2_merkle_trees::gadgets::MerkleGadgets;
+
+const NR_PEAKS: [usize; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+struct PeakCircuit {
+  circuit_data: CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+  subtree_root: HashOutTarget,
+  peaks: Vec<HashOutTarget>,
+  peak_index: Target,
+  // Number of gates of the peak check only, before building adds the gates for constants
+  nr_check_gates: usize
+}
+
+fn build_peak_circuit(nr_peaks: usize, random_access: bool) -> PeakCircuit {
+  let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+  let subtree_root = builder.add_virtual_hash();
+  let peaks = builder.add_virtual_hashes(nr_peaks);
+  let peak_index = builder.add_virtual_target();
+  if random_access {
+    builder.assert_hash_at_index(subtree_root, &peaks, peak_index);
+  } else {
+    builder.assert_hash_in_list(subtree_root, &peaks);
+  }
+  let nr_check_gates = builder.num_gates();
+  PeakCircuit {
+    circuit_data: builder.build::<PoseidonGoldilocksConfig>(),
+    subtree_root: subtree_root,
+    peaks: peaks,
+    peak_index: peak_index,
+    nr_check_gates: nr_check_gates
+  }
+}
+
+// The subtree root is the last peak, which is the peak of the smallest subtree
+fn set_witness(pw: &mut PartialWitness<GoldilocksField>, circuit: &PeakCircuit) {
+  let nr_peaks = circuit.peaks.len();
+  for (i, peak) in circuit.peaks.iter().enumerate() {
+    pw.set_hash_target(*peak, HashOut::from_partial(&[GoldilocksField::from_canonical_usize(i)]));
+  }
+  pw.set_hash_target(circuit.subtree_root, HashOut::from_partial(&[GoldilocksField::from_canonical_usize(nr_peaks - 1)]));
+  pw.set_target(circuit.peak_index, GoldilocksField::from_canonical_usize(nr_peaks - 1));
+}
+
+// Checking a subtree root is one of 1 to 64 peaks: comparing against every peak against a random access at the peak index
+fn bench_peak_selection(c: &mut Criterion) {
+  let mut group = c.benchmark_group("peak_selection");
+  group.sample_size(10);
+  for nr_peaks in NR_PEAKS {
+    for (name, random_access) in [("compare_all", false), ("random_access", true)] {
+      let circuit = build_peak_circuit(nr_peaks, random_access);
+      println!("{}, {} peaks: {} gates", name, nr_peaks, circuit.nr_check_gates);
+      group.bench_function(BenchmarkId::new(name, nr_peaks), |b| {
+        b.iter(|| {
+          let mut pw = PartialWitness::new();
+          set_witness(&mut pw, &circuit);
+          circuit.circuit_data.prove(pw).unwrap()
+        })
+      });
+    }
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_peak_selection);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use plonky2::plonk::{circuit_data::CircuitData, config::PoseidonGoldilocksConfig, proof::ProofWithPublicInputs};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+use plonky2_merkle_trees::{
+  compression::{compression_report, CompressionPreset, Compressor},
+  mmr::{merkle_mountain_ranges::MMR, mmr_plonky2_verifier::{membership_circuit_data, prove_membership}}
+};
+
+// Proof of prove_membership for a leaf in an MMR of 1000 leaves
+fn mmr_proof() -> (&'static CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) {
+  let leaves: Vec<GoldilocksField> = (0..1000).map(GoldilocksField::from_canonical_u64).collect();
+  let mmr = MMR::from_leaves(&leaves);
+  (membership_circuit_data(), prove_membership(&mmr, 100).unwrap())
+}
+
+// Prints the proof size per preset and benchmarks the proving time of the wrapper
+fn bench_proof_compression(c: &mut Criterion) {
+  let (circuit_data, proof) = mmr_proof();
+  for report in compression_report(circuit_data, &proof).unwrap() {
+    println!("{:?}: {} -> {} bytes, build {:?}, prove {:?}",
+      report.preset, report.original_size, report.compressed_size, report.build_time, report.proving_time);
+  }
+
+  let mut group = c.benchmark_group("proof_compression");
+  group.sample_size(10);
+  for preset in CompressionPreset::all() {
+    let compressor = Compressor::for_circuit(circuit_data, preset);
+    group.bench_with_input(BenchmarkId::new("compress", format!("{:?}", preset)), &proof, |b, proof| {
+      b.iter(|| compressor.compress(proof).unwrap())
+    });
+  }
+  group.finish();
+}
+
+criterion_group!(benches, bench_proof_compression);
+criterion_main!(benches);
@@ -2,6 +2,7 @@ use plonky2::{plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit
 
 /**
  * zkp for veryfing merkle proof
+ * This circuit is built for a fixed leaf index. See simple_merkle_tree::circuit for a circuit that works for any leaf
  */
 
 // Returns the cricuit data for verifying the Merkle Proof + the target for witness (non-public) input data
@@ -92,7 +93,7 @@ use rand::Rng;
       // Test tree, 4 leaves
       let tree: MerkleTree = get_test_tree(4);
 
-      let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+      let merkle_proof_leaf0 = tree.get_merkle_proof(0);
       // [
       // other leaf: HashOut { elements: [156728478, 0, 0, 0] }, 
       // other node: HashOut { elements: [6698018865469624861, 12486244005715193285, 11330639022572315007, 6059804404595156248] }
@@ -124,7 +125,7 @@ use rand::Rng;
       
       let mut pw = plonky2::iop::witness::PartialWitness::new();
       // non-public inputs to witness: leaf and elements of merkle proof
-      pw.set_hash_target(targets[0], tree.tree[0][0]); // leaf index 0
+      pw.set_hash_target(targets[0], tree.level(0)[0]); // leaf index 0
       pw.set_hash_target(targets[1], merkle_proof_leaf0[0]);
       pw.set_hash_target(targets[2], merkle_proof_leaf0[1]);
 
@@ -145,7 +146,7 @@ use rand::Rng;
   #[test]
   fn test_tree_4_leaves_index3() -> Result<()> {
       let tree: MerkleTree = get_test_tree(4);
-      let merkle_proof_leaf0 = tree.clone().get_merkle_proof(3);
+      let merkle_proof_leaf0 = tree.get_merkle_proof(3);
       // println!("{:?}", merkle_proof_leaf0);
 
       // [HashOut { elements: [2876514289, 0, 0, 0] }, 
@@ -177,7 +178,7 @@ use rand::Rng;
       
       let mut pw = plonky2::iop::witness::PartialWitness::new();
       // non-public inputs to witness: leaf and elements of merkle proof
-      pw.set_hash_target(targets[0], tree.tree[0][3]); // leaf index 3
+      pw.set_hash_target(targets[0], tree.level(0)[3]); // leaf index 3
 
       pw.set_hash_target(targets[1], merkle_proof_leaf0[0]);
       pw.set_hash_target(targets[2], merkle_proof_leaf0[1]);
@@ -200,7 +201,7 @@ use rand::Rng;
   fn test_tree_16_leaves_index_0() -> Result<()> {
       // Test tree, 16 leaves
       let tree: MerkleTree = get_test_tree(16);
-      let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+      let merkle_proof_leaf0 = tree.get_merkle_proof(0);
       println!("{:?}", merkle_proof_leaf0);
 
       let (circuit_data, targets) = verify_merkle_proof_circuit(0, 4);
@@ -214,7 +215,7 @@ use rand::Rng;
       
       let mut pw = plonky2::iop::witness::PartialWitness::new();
       // non-public inputs to witness: leaf and elements of merkle proof
-      pw.set_hash_target(targets[0], tree.tree[0][0]); // leaf index 0
+      pw.set_hash_target(targets[0], tree.level(0)[0]); // leaf index 0
 
       pw.set_hash_target(targets[1], merkle_proof_leaf0[0]);
       pw.set_hash_target(targets[2], merkle_proof_leaf0[1]);
@@ -239,7 +240,7 @@ use rand::Rng;
   fn test_tree_16_leaves_index_7() -> Result<()> {
       // Test tree, 16 leaves
       let tree: MerkleTree = get_test_tree(16);
-      let merkle_proof_leaf7 = tree.clone().get_merkle_proof(7);
+      let merkle_proof_leaf7 = tree.get_merkle_proof(7);
       println!("{:?}", merkle_proof_leaf7);
 
       let (circuit_data, targets) = verify_merkle_proof_circuit(7, 4);
@@ -253,7 +254,7 @@ use rand::Rng;
       
       let mut pw = plonky2::iop::witness::PartialWitness::new();
       // non-piblic input: leaf hash and merkle proof elements
-      pw.set_hash_target(targets[0], tree.tree[0][7]);
+      pw.set_hash_target(targets[0], tree.level(0)[7]);
       pw.set_hash_target(targets[1], merkle_proof_leaf7[0]);
       pw.set_hash_target(targets[2], merkle_proof_leaf7[1]);
       pw.set_hash_target(targets[3], merkle_proof_leaf7[2]);
@@ -8,6 +8,7 @@ use plonky2::{plonk::{config::{PoseidonGoldilocksConfig, GenericConfig, Hasher},
  * There is a proof for each layer of the merkle tree.
  * For each next layer, the proof for the previous layer is incorporated and proven. 
  * This repeatedly until getting to the root and ending up with 1 proof that verifies the complete merkle proof for a leaf. 
+ * See simple_merkle_tree::recursive_merkle_prover for a version that reuses a single circuit for any path.
  * 
  */
 
@@ -220,13 +221,13 @@ use rand::Rng;
     // Test tree, 4 leaves
     let tree: MerkleTree = get_test_tree(4);
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
 
     let (initial_circuit_data, targets) = initial_proof_circuit();
     let initial_circuit_verifier_data = &initial_circuit_data.verifier_only.clone();
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
-    pw.set_hash_target(targets[0], tree.tree[0][0]); // leaf index 0
+    pw.set_hash_target(targets[0], tree.level(0)[0]); // leaf index 0
     pw.set_hash_target(targets[1], merkle_proof_leaf0[0]); // leaf index 1 (first elm of merkle proof)
 
     // // Test if level 1 works
@@ -274,13 +275,13 @@ use rand::Rng;
 
     // Test tree, 4 leaves
     let tree: MerkleTree = get_test_tree(4);
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
 
     let (initial_circuit_data, targets) = initial_proof_circuit();
     let initial_circuit_verifier_data = &initial_circuit_data.verifier_only.clone();
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
-    pw.set_hash_target(targets[0], tree.tree[0][0]); // leaf index 0
+    pw.set_hash_target(targets[0], tree.level(0)[0]); // leaf index 0
     pw.set_hash_target(targets[1], merkle_proof_leaf0[0]); // leaf index 1 (first elm of merkle proof)
 
     let proof = initial_circuit_data.prove(pw.clone()).unwrap();
@@ -301,7 +302,7 @@ use rand::Rng;
 
     for i in 0..4 {
       // This is the wrong value; should be root value and is the initial leaf value. Thus proof must fail
-      pw_new.set_target(rec_expected_public_inputs[i], tree.tree[0][0].elements[i]);
+      pw_new.set_target(rec_expected_public_inputs[i], tree.level(0)[0].elements[i]);
     }
 
     // Set the targets wrt to the previous proof
@@ -324,13 +325,13 @@ use rand::Rng;
     // Test tree, 4 leaves
     let tree: MerkleTree = get_test_tree(4);
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
 
     let (initial_circuit_data, targets) = initial_proof_circuit();
     let initial_circuit_verifier_data = &initial_circuit_data.verifier_only.clone();
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
-    pw.set_hash_target(targets[0], tree.tree[0][0]); // leaf index 0
+    pw.set_hash_target(targets[0], tree.level(0)[0]); // leaf index 0
     pw.set_hash_target(targets[1], merkle_proof_leaf0[0]); // leaf index 1 (first elm of merkle proof)
 
     // // Test if level 1 works
@@ -379,14 +380,14 @@ use rand::Rng;
     // Test tree, 4 leaves
     let tree: MerkleTree = get_test_tree(4);
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
 
     let (initial_circuit_data, targets) = initial_proof_circuit();
     let initial_circuit_verifier_data = &initial_circuit_data.verifier_only.clone();
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
-    pw.set_hash_target(targets[0], tree.tree[0][0]); // leaf index 0
-    pw.set_hash_target(targets[1],tree.tree[0][0]); // WRONG INFO: this is leaf index 0 again, while it should be the first elm of the Merkle proof
+    pw.set_hash_target(targets[0], tree.level(0)[0]); // leaf index 0
+    pw.set_hash_target(targets[1],tree.level(0)[0]); // WRONG INFO: this is leaf index 0 again, while it should be the first elm of the Merkle proof
 
     // // Test if level 1 works
     let proof = initial_circuit_data.prove(pw.clone()).unwrap();
@@ -431,7 +432,7 @@ use rand::Rng;
     // Test tree, 4 leaves
     let tree: MerkleTree = get_test_tree(4);
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(3);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(3);
 
     let (initial_circuit_data, targets) = initial_proof_circuit();
     let initial_circuit_verifier_data = &initial_circuit_data.verifier_only.clone();
@@ -439,7 +440,7 @@ use rand::Rng;
     let mut pw = plonky2::iop::witness::PartialWitness::new();
     // Leaf with index 3 is on the left side of the pair, so notice the order
     pw.set_hash_target(targets[0], merkle_proof_leaf0[0]); // leaf index 3
-    pw.set_hash_target(targets[1], tree.tree[0][3]); // leaf index 1 (first elm of merkle proof)
+    pw.set_hash_target(targets[1], tree.level(0)[3]); // leaf index 1 (first elm of merkle proof)
 
     // // Test if level 1 works
     let proof = initial_circuit_data.prove(pw.clone())?;
@@ -484,15 +485,15 @@ use rand::Rng;
     // Test tree, 4 leaves
     let tree: MerkleTree = get_test_tree(4);
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
 
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(0);
+    let in_between_hashes = tree.get_in_between_hashes(0);
 
     let (circuit, recursive_proof) = 
       verify_merkle_proof_circuit_and_proof(
         0, 
-        tree.clone().tree[0][0], 
+        tree.level(0)[0], 
         merkle_proof_leaf0, 
         in_between_hashes);
     circuit.verify(recursive_proof)
@@ -506,14 +507,14 @@ use rand::Rng;
     let tree: MerkleTree = get_test_tree(4);
     println!("{:?}", tree);
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
     println!("{:?}", merkle_proof_leaf0);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(0);
+    let in_between_hashes = tree.get_in_between_hashes(0);
 
     // The merkle proof is for leaf 0, but the leaf value passed in is index 1
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(1, tree.tree[0][1], merkle_proof_leaf0, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(1, tree.level(0)[1], merkle_proof_leaf0, in_between_hashes);
     circuit.verify(recursive_proof);
   }
 
@@ -525,14 +526,14 @@ use rand::Rng;
     let tree: MerkleTree = get_test_tree(4);
     println!("{:?}", tree);
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
     println!("{:?}", merkle_proof_leaf0);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(0);
+    let in_between_hashes = tree.get_in_between_hashes(0);
 
     // The merkle proof is for leaf 0, but the leaf index is 1
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(1, tree.tree[0][0], merkle_proof_leaf0, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(1, tree.level(0)[0], merkle_proof_leaf0, in_between_hashes);
     circuit.verify(recursive_proof);
   }
 
@@ -541,12 +542,12 @@ use rand::Rng;
     // Test tree, 4 leaves
     let tree: MerkleTree = get_test_tree(4);
 
-    let merkle_proof_leaf1 = tree.clone().get_merkle_proof(1);
+    let merkle_proof_leaf1 = tree.get_merkle_proof(1);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(1);
+    let in_between_hashes = tree.get_in_between_hashes(1);
 
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(1, tree.tree[0][1], merkle_proof_leaf1, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(1, tree.level(0)[1], merkle_proof_leaf1, in_between_hashes);
     circuit.verify(recursive_proof)
   }
 
@@ -555,12 +556,12 @@ use rand::Rng;
     // Test tree, 16 leaves
     let tree: MerkleTree = get_test_tree(16);
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(0);
+    let in_between_hashes = tree.get_in_between_hashes(0);
 
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(0, tree.tree[0][0], merkle_proof_leaf0, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(0, tree.level(0)[0], merkle_proof_leaf0, in_between_hashes);
     circuit.verify(recursive_proof)
   }
 
@@ -570,13 +571,13 @@ use rand::Rng;
     // Passes wrong merkle_proof
     // Test tree, 16 leaves
     let tree: MerkleTree = get_test_tree(16);
-    let merkle_proof_leaf1 = tree.clone().get_merkle_proof(1);
+    let merkle_proof_leaf1 = tree.get_merkle_proof(1);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(1);
+    let in_between_hashes = tree.get_in_between_hashes(1);
 
     // The merkle proof is for leaf 0, but the proof passed is for leaf 1
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(0, tree.tree[0][0], merkle_proof_leaf1, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(0, tree.level(0)[0], merkle_proof_leaf1, in_between_hashes);
     circuit.verify(recursive_proof);
   }
 
@@ -585,12 +586,12 @@ use rand::Rng;
     // Test tree, 16 leaves
     let tree: MerkleTree = get_test_tree(16);
 
-    let merkle_proof_leaf13 = tree.clone().get_merkle_proof(13);
+    let merkle_proof_leaf13 = tree.get_merkle_proof(13);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(13);
+    let in_between_hashes = tree.get_in_between_hashes(13);
 
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(13, tree.tree[0][13], merkle_proof_leaf13, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(13, tree.level(0)[13], merkle_proof_leaf13, in_between_hashes);
     circuit.verify(recursive_proof)
   }
 
@@ -600,13 +601,13 @@ use rand::Rng;
     // Passes wrong merkle_proof
     // Test tree, 16 leaves
     let tree: MerkleTree = get_test_tree(16);
-    let merkle_proof_leaf13 = tree.clone().get_merkle_proof(13);
+    let merkle_proof_leaf13 = tree.get_merkle_proof(13);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(13);
+    let in_between_hashes = tree.get_in_between_hashes(13);
 
     // The merkle proof is for leaf 13, but the index passed is 0
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(0, tree.tree[0][13], merkle_proof_leaf13, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(0, tree.level(0)[13], merkle_proof_leaf13, in_between_hashes);
     circuit.verify(recursive_proof);
   }
 
@@ -615,12 +616,12 @@ use rand::Rng;
     // Test tree, 32 leaves
     let tree: MerkleTree = get_test_tree(32);
 
-    let merkle_proof_leaf13 = tree.clone().get_merkle_proof(13);
+    let merkle_proof_leaf13 = tree.get_merkle_proof(13);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(13);
+    let in_between_hashes = tree.get_in_between_hashes(13);
 
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(13, tree.tree[0][13], merkle_proof_leaf13, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(13, tree.level(0)[13], merkle_proof_leaf13, in_between_hashes);
     circuit.verify(recursive_proof)
   }
 
@@ -629,12 +630,12 @@ use rand::Rng;
     // Test tree, 128 leaves
     let tree: MerkleTree = get_test_tree(128);
 
-    let merkle_proof_leaf111 = tree.clone().get_merkle_proof(111);
+    let merkle_proof_leaf111 = tree.get_merkle_proof(111);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(111);
+    let in_between_hashes = tree.get_in_between_hashes(111);
 
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(111, tree.tree[0][111], merkle_proof_leaf111, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(111, tree.level(0)[111], merkle_proof_leaf111, in_between_hashes);
     circuit.verify(recursive_proof)
   }
 
@@ -644,13 +645,13 @@ use rand::Rng;
     // Passes wrong merkle_proof
     // Test tree, 128 leaves
     let tree: MerkleTree = get_test_tree(128);
-    let merkle_proof_leaf80 = tree.clone().get_merkle_proof(80);
+    let merkle_proof_leaf80 = tree.get_merkle_proof(80);
     //These are used for public input
-    let in_between_hashes = tree.clone().get_in_between_hashes(80);
+    let in_between_hashes = tree.get_in_between_hashes(80);
 
     // The merkle proof is for leaf 80, but the index and leaf value passed are for 79
     let (circuit, recursive_proof) = 
-      verify_merkle_proof_circuit_and_proof(79, tree.tree[0][79], merkle_proof_leaf80, in_between_hashes);
+      verify_merkle_proof_circuit_and_proof(79, tree.level(0)[79], merkle_proof_leaf80, in_between_hashes);
     circuit.verify(recursive_proof);
   }
 }
\ No newline at end of file
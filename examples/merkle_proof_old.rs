@@ -23,7 +23,7 @@ fn main() -> Result<()> {
     let tree: MerkleTree  = MerkleTree::build(leaves.clone());
     // For tree with 4 leaves and thus 2 levels, the proof consists of 2 elements
 
-    let res_leaf_2 = tree.clone().get_merkle_proof(2);
+    let res_leaf_2 = tree.get_merkle_proof(2);
     // Input into circuit is hashed leaf (H_2) we're proving is part of the tree
     // Step 1: hash H_2 with H_3. H_3 is the first hash in the proof (res_leaf_2)
     // Input into circuit
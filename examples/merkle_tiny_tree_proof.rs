@@ -63,7 +63,7 @@ fn main() -> Result<()> {
       ].to_vec();
     let tree: MerkleTree  = MerkleTree::build(leaves.clone());
 
-    let merkle_proof_leaf0 = tree.clone().get_merkle_proof(0);
+    let merkle_proof_leaf0 = tree.get_merkle_proof(0);
     println!("{:?}", merkle_proof_leaf0);
     // [
     // other leaf: HashOut { elements: [156728478, 0, 0, 0] }, 
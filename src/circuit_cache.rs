@@ -0,0 +1,340 @@
+use std::{collections::HashMap, fs, path::PathBuf, sync::Mutex};
+
+use anyhow::{anyhow, Result};
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, plonk::{config::PoseidonGoldilocksConfig, circuit_data::{CircuitData, CircuitConfig}}, util::serialization::{Buffer, DefaultGateSerializer, DefaultGeneratorSerializer, IoResult, Read, Write}};
+use plonky2_field::goldilocks_field::GoldilocksField;
+use sha2::{Digest, Sha256};
+
+use crate::{config_profile::ConfigProfile, mmr::{mmr_plonky2_verifier::{verify_mmr_proof_circuit, MmrProofTargets}, naive_mmr_plonky2_verifier::{verify_naive_mmr_proof_circuit, NaiveMmrProofTargets}}};
+
+/**
+ * On-disk cache of built circuits, so a circuit of a given shape is only built once per cache.
+ *
+ * A circuit is stored under its key: the kind of circuit, the parameters that determine its shape and its config.
+ * The fingerprint of a circuit is its circuit digest, which commits to the common data, the constants and the wiring,
+ * so any change to the circuit changes it. The file holds the circuit digest, the targets the circuit function returns
+ * and the CircuitData, serialized with plonky2's default gate and generator serializers.
+ * A file is only loaded if its digest equals the digest of the circuit as the code builds it now. That digest comes
+ * from building the circuit of the key the first time the cache is asked for it, and is kept for the lifetime of the
+ * cache. A file that can't be read or doesn't match is rebuilt and overwritten.
+ */
+
+type C = PoseidonGoldilocksConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CircuitKind {
+  // verify_mmr_proof_circuit
+  MmrProof,
+  // verify_naive_mmr_proof_circuit, for the leaf at relative_leaf_index within its subtree
+  NaiveMmrProof { relative_leaf_index: usize }
+}
+
+// Identifies a circuit in the cache
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitKey {
+  pub kind: CircuitKind,
+  pub depth: usize,
+  pub peaks: usize,
+  pub config: CircuitConfig
+}
+
+impl CircuitKey {
+  // Returns the name of the file of this circuit in the cache, from its shape and a hash of its config
+  pub fn file_name(&self) -> String {
+    let config_hash = Sha256::digest(format!("{:?}", self.config).as_bytes());
+    let config_hash: String = config_hash[0..8].iter().map(|byte| format!("{:02x}", byte)).collect();
+    match self.kind {
+      CircuitKind::MmrProof =>
+        format!("mmr_proof_depth{}_peaks{}_{}.bin", self.depth, self.peaks, config_hash),
+      CircuitKind::NaiveMmrProof { relative_leaf_index } =>
+        format!("naive_mmr_proof_index{}_depth{}_peaks{}_{}.bin", relative_leaf_index, self.depth, self.peaks, config_hash)
+    }
+  }
+}
+
+fn write_hashes(bytes: &mut Vec<u8>, hashes: &[HashOutTarget]) -> IoResult<()> {
+  bytes.write_usize(hashes.len())?;
+  for hash in hashes {
+    bytes.write_target_hash(hash)?;
+  }
+  Ok(())
+}
+
+fn read_hashes(buffer: &mut Buffer) -> IoResult<Vec<HashOutTarget>> {
+  let nr_hashes = buffer.read_usize()?;
+  (0..nr_hashes).map(|_| buffer.read_target_hash()).collect()
+}
+
+pub struct CircuitCache {
+  pub dir: PathBuf,
+  // The circuit digests of the circuits built by this cache, by file name
+  digests: Mutex<HashMap<String, HashOut<GoldilocksField>>>
+}
+
+impl Default for CircuitCache {
+  // The directory in the CIRCUIT_CACHE_DIR environment variable, or circuit_cache in the target directory
+  fn default() -> Self {
+    let dir = std::env::var("CIRCUIT_CACHE_DIR")
+      .map(PathBuf::from)
+      .unwrap_or_else(|_| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("circuit_cache"));
+    CircuitCache::new(dir)
+  }
+}
+
+impl CircuitCache {
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    CircuitCache { dir: dir.into(), digests: Mutex::new(HashMap::new()) }
+  }
+
+  // Same as verify_mmr_proof_circuit, loaded from the cache when it was built before
+  pub fn mmr_proof_circuit(&self, nr_merkle_proof_elms: usize, nr_peaks: usize, profile: &ConfigProfile)
+    -> Result<(CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MmrProofTargets)> {
+    let key = CircuitKey {
+      kind: CircuitKind::MmrProof,
+      depth: nr_merkle_proof_elms,
+      peaks: nr_peaks,
+      config: profile.circuit_config()
+    };
+    self.get_or_build(
+      &key,
+      || verify_mmr_proof_circuit(nr_merkle_proof_elms, nr_peaks, profile),
+      |targets, bytes| {
+        bytes.write_target(targets.leaf)?;
+        bytes.write_usize(targets.merkle_proof.len())?;
+        for (hash, on_left) in &targets.merkle_proof {
+          bytes.write_target_hash(hash)?;
+          bytes.write_target_bool(*on_left)?;
+        }
+        write_hashes(bytes, &targets.peaks)?;
+        bytes.write_target(targets.peak_index)
+      },
+      |buffer| {
+        let leaf = buffer.read_target()?;
+        let nr_proof_elms = buffer.read_usize()?;
+        let mut merkle_proof = Vec::new();
+        for _ in 0..nr_proof_elms {
+          merkle_proof.push((buffer.read_target_hash()?, buffer.read_target_bool()?));
+        }
+        Ok(MmrProofTargets {
+          leaf: leaf,
+          merkle_proof: merkle_proof,
+          peaks: read_hashes(buffer)?,
+          peak_index: buffer.read_target()?
+        })
+      })
+  }
+
+  // Same as verify_naive_mmr_proof_circuit, loaded from the cache when it was built before
+  pub fn naive_mmr_proof_circuit(&self, relative_leaf_index: usize, nr_proof_elms: usize, nr_peaks: usize, profile: &ConfigProfile)
+    -> Result<(CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, NaiveMmrProofTargets)> {
+    let key = CircuitKey {
+      kind: CircuitKind::NaiveMmrProof { relative_leaf_index: relative_leaf_index },
+      depth: nr_proof_elms,
+      peaks: nr_peaks,
+      config: profile.circuit_config()
+    };
+    self.get_or_build(
+      &key,
+      || verify_naive_mmr_proof_circuit(relative_leaf_index, nr_proof_elms, nr_peaks, profile),
+      |targets, bytes| {
+        bytes.write_target_hash(&targets.leaf)?;
+        write_hashes(bytes, &targets.merkle_proof)?;
+        write_hashes(bytes, &targets.peaks)?;
+        bytes.write_target(targets.peak_index)
+      },
+      |buffer| {
+        Ok(NaiveMmrProofTargets {
+          leaf: buffer.read_target_hash()?,
+          merkle_proof: read_hashes(buffer)?,
+          peaks: read_hashes(buffer)?,
+          peak_index: buffer.read_target()?
+        })
+      })
+  }
+
+  // Returns whether the circuit of the key is in the cache
+  pub fn contains(&self, key: &CircuitKey) -> bool {
+    self.dir.join(key.file_name()).exists()
+  }
+
+  fn get_or_build<T>(
+    &self,
+    key: &CircuitKey,
+    build: impl FnOnce() -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, T),
+    write_targets: impl Fn(&T, &mut Vec<u8>) -> IoResult<()>,
+    read_targets: impl Fn(&mut Buffer) -> IoResult<T>) -> Result<(CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, T)> {
+    let file_name = key.file_name();
+    let path = self.dir.join(&file_name);
+    let expected_digest = self.digests.lock().unwrap().get(&file_name).copied();
+    if let (Some(expected_digest), Ok(bytes)) = (expected_digest, fs::read(&path)) {
+      if let Some(loaded) = Self::load(&bytes, key, expected_digest, &read_targets) {
+        return Ok(loaded);
+      }
+    }
+
+    // Building the circuit gives the digest to check the file against from now on
+    let (circuit_data, targets) = build();
+    self.digests.lock().unwrap().insert(file_name.clone(), circuit_data.verifier_only.circuit_digest);
+    let bytes = Self::store(&circuit_data, &targets, &write_targets).map_err(|_| anyhow!("failed to serialize circuit {}", file_name))?;
+    fs::create_dir_all(&self.dir)?;
+    // Write to a temporary file first, so a concurrent reader never sees a partial file
+    let tmp_path = self.dir.join(format!("{}.{}.tmp", file_name, rand::random::<u64>()));
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(&tmp_path, &path)?;
+    Ok((circuit_data, targets))
+  }
+
+  fn store<T>(
+    circuit_data: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+    targets: &T,
+    write_targets: &impl Fn(&T, &mut Vec<u8>) -> IoResult<()>) -> IoResult<Vec<u8>> {
+    let gate_serializer = DefaultGateSerializer;
+    let generator_serializer = DefaultGeneratorSerializer::<C, 2> { _phantom: Default::default() };
+
+    let mut bytes = Vec::new();
+    bytes.write_hash::<GoldilocksField, PoseidonHash>(circuit_data.verifier_only.circuit_digest)?;
+    let mut target_bytes = Vec::new();
+    write_targets(targets, &mut target_bytes)?;
+    bytes.write_usize(target_bytes.len())?;
+    bytes.write_all(&target_bytes)?;
+    bytes.write_all(&circuit_data.to_bytes(&gate_serializer, &generator_serializer)?)?;
+    Ok(bytes)
+  }
+
+  // Returns None if the bytes can't be read or don't match the key and the digest of the circuit as it's built now
+  fn load<T>(
+    bytes: &[u8],
+    key: &CircuitKey,
+    expected_digest: HashOut<GoldilocksField>,
+    read_targets: &impl Fn(&mut Buffer) -> IoResult<T>) -> Option<(CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, T)> {
+    let gate_serializer = DefaultGateSerializer;
+    let generator_serializer = DefaultGeneratorSerializer::<C, 2> { _phantom: Default::default() };
+
+    // The circuit digest, followed by the number of bytes of the targets, the targets and the CircuitData
+    let mut buffer = Buffer::new(bytes);
+    let circuit_digest: HashOut<GoldilocksField> = buffer.read_hash::<GoldilocksField, PoseidonHash>().ok()?;
+    let nr_target_bytes = buffer.read_usize().ok()?;
+    let targets_start = buffer.pos();
+    let targets_end = targets_start.checked_add(nr_target_bytes)?;
+    if bytes.len() < targets_end {
+      return None;
+    }
+    let targets = read_targets(&mut Buffer::new(&bytes[targets_start..targets_end])).ok()?;
+    let circuit_data = CircuitData::<GoldilocksField, PoseidonGoldilocksConfig, 2>::from_bytes(&bytes[targets_end..], &gate_serializer, &generator_serializer).ok()?;
+    if circuit_digest != expected_digest
+      || circuit_data.verifier_only.circuit_digest != expected_digest
+      || circuit_data.common.config != key.config {
+      return None;
+    }
+    Some((circuit_data, targets))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{iop::witness::PartialWitness, plonk::circuit_data::CircuitConfig};
+  use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::MMR}};
+  use super::{CircuitCache, CircuitKey, CircuitKind};
+
+  fn test_cache() -> CircuitCache {
+    let mut rng = rand::thread_rng();
+    CircuitCache::new(std::env::temp_dir().join(format!("circuit_cache_test_{}", rng.gen::<u64>())))
+  }
+
+  #[test]
+  fn test_reload_mmr_proof_circuit() -> Result<()> {
+    let cache = test_cache();
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<GoldilocksField> = (0..11).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    let root = mmr.clone().bagging_the_peaks();
+    let proof = mmr.get_proof_normal_index(9);
+
+    let key = CircuitKey {
+      kind: CircuitKind::MmrProof,
+      depth: proof.merkle_proof.len(),
+      peaks: proof.peaks.len(),
+      config: CircuitConfig::standard_recursion_config()
+    };
+    assert!(!cache.contains(&key));
+    let (built_data, _) = cache.mmr_proof_circuit(proof.merkle_proof.len(), proof.peaks.len(), &ConfigProfile::default())?;
+    assert!(cache.contains(&key));
+
+    // The second time it comes from disk, and can still prove
+    let (circuit_data, targets) = cache.mmr_proof_circuit(proof.merkle_proof.len(), proof.peaks.len(), &ConfigProfile::default())?;
+    assert!(circuit_data.verifier_only.circuit_digest == built_data.verifier_only.circuit_digest);
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &proof, leaves[9]);
+    let plonky2_proof = circuit_data.prove(pw)?;
+    assert!(plonky2_proof.public_inputs == root.elements);
+    // A proof of the reloaded circuit verifies with the circuit that was built
+    built_data.verify(plonky2_proof)?;
+    std::fs::remove_dir_all(&cache.dir)?;
+    Ok(())
+  }
+
+  #[test]
+  fn test_file_name_follows_the_shape() {
+    let key = |depth: usize, peaks: usize, config: CircuitConfig| CircuitKey {
+      kind: CircuitKind::MmrProof,
+      depth: depth,
+      peaks: peaks,
+      config: config
+    };
+    let standard = CircuitConfig::standard_recursion_config();
+    assert!(key(3, 2, standard.clone()).file_name() == key(3, 2, standard.clone()).file_name());
+    // A different shape or config gives another circuit, and another file
+    assert!(key(3, 2, standard.clone()).file_name() != key(4, 2, standard.clone()).file_name());
+    assert!(key(3, 2, standard.clone()).file_name() != key(3, 3, standard.clone()).file_name());
+    assert!(key(3, 2, standard).file_name() != key(3, 2, CircuitConfig::standard_recursion_zk_config()).file_name());
+  }
+
+  // A file with another circuit than the one the code builds for its key, such as one written by an older version of
+  // the circuit, isn't loaded
+  #[test]
+  fn test_stale_file_is_rebuilt() -> Result<()> {
+    let cache = test_cache();
+    let key = |depth: usize| CircuitKey {
+      kind: CircuitKind::MmrProof,
+      depth: depth,
+      peaks: 2,
+      config: CircuitConfig::standard_recursion_config()
+    };
+    let (built_data, _) = cache.mmr_proof_circuit(2, 2, &ConfigProfile::default())?;
+    let (other_data, _) = cache.mmr_proof_circuit(3, 2, &ConfigProfile::default())?;
+    std::fs::copy(cache.dir.join(key(3).file_name()), cache.dir.join(key(2).file_name()))?;
+
+    let (circuit_data, targets) = cache.mmr_proof_circuit(2, 2, &ConfigProfile::default())?;
+    assert!(circuit_data.verifier_only.circuit_digest == built_data.verifier_only.circuit_digest);
+    assert!(circuit_data.verifier_only.circuit_digest != other_data.verifier_only.circuit_digest);
+    assert!(targets.merkle_proof.len() == 2);
+    std::fs::remove_dir_all(&cache.dir)?;
+    Ok(())
+  }
+
+  #[test]
+  fn test_corrupted_file_is_rebuilt() -> Result<()> {
+    let cache = test_cache();
+    let key = CircuitKey {
+      kind: CircuitKind::NaiveMmrProof { relative_leaf_index: 1 },
+      depth: 1,
+      peaks: 2,
+      config: CircuitConfig::standard_recursion_config()
+    };
+    let (built_data, _) = cache.naive_mmr_proof_circuit(1, 1, 2, &ConfigProfile::default())?;
+    let path = cache.dir.join(key.file_name());
+    let mut bytes = std::fs::read(&path)?;
+    bytes.truncate(bytes.len() / 2);
+    std::fs::write(&path, bytes)?;
+
+    let (circuit_data, targets) = cache.naive_mmr_proof_circuit(1, 1, 2, &ConfigProfile::default())?;
+    assert!(circuit_data.verifier_only.circuit_digest == built_data.verifier_only.circuit_digest);
+    assert!(targets.merkle_proof.len() == 1 && targets.peaks.len() == 2);
+    std::fs::remove_dir_all(&cache.dir)?;
+    Ok(())
+  }
+}
@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use plonky2::{fri::{reduction_strategies::FriReductionStrategy, FriConfig}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::{CircuitData, CircuitConfig, CommonCircuitData, VerifierOnlyCircuitData, VerifierCircuitTarget}, circuit_builder::CircuitBuilder, proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget}}, iop::witness::{PartialWitness, WitnessWrite}, util::serialization::Write};
+use plonky2_field::goldilocks_field::GoldilocksField;
+
+/**
+ * Shrinking proofs by wrapping them in a circuit with a cheaper FRI config.
+ *
+ * Most of a proof made with standard_recursion_config consists of the FRI query rounds: 28 queries, each with Merkle
+ * paths into every committed oracle. With a higher rate (a larger blowup of the LDE) fewer queries give the same
+ * conjectured security of rate_bits * num_query_rounds + proof_of_work_bits ~ 100 bits, so the proof gets smaller,
+ * at the cost of proving over a larger domain.
+ *
+ * A compressor verifies a proof of any circuit against that circuit's verifier data, which is a constant in the
+ * wrapper, and registers the public inputs of the inner proof as its own. So a compressed proof has the same public
+ * inputs as the original and is only accepted for proofs of the inner circuit.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompressionPreset {
+  // rate 1/16, 21 queries
+  Rate4,
+  // rate 1/64, 14 queries
+  Rate6,
+  // rate 1/256, 11 queries; smallest proofs, slowest proving
+  Rate8
+}
+
+impl CompressionPreset {
+  pub fn all() -> [CompressionPreset; 3] {
+    [CompressionPreset::Rate4, CompressionPreset::Rate6, CompressionPreset::Rate8]
+  }
+
+  // Only the FRI parameters differ from standard_recursion_config, so the wrapper has the gates to verify recursively
+  pub fn config(&self) -> CircuitConfig {
+    let (rate_bits, num_query_rounds) = match self {
+      CompressionPreset::Rate4 => (4, 21),
+      CompressionPreset::Rate6 => (6, 14),
+      CompressionPreset::Rate8 => (8, 11)
+    };
+    let standard = CircuitConfig::standard_recursion_config();
+    CircuitConfig {
+      fri_config: FriConfig {
+        rate_bits: rate_bits,
+        cap_height: standard.fri_config.cap_height,
+        proof_of_work_bits: standard.fri_config.proof_of_work_bits,
+        reduction_strategy: FriReductionStrategy::ConstantArityBits(4, 5),
+        num_query_rounds: num_query_rounds
+      },
+      ..standard
+    }
+  }
+}
+
+// Circuit that verifies a proof of a fixed inner circuit, with the FRI config of a preset
+pub struct Compressor {
+  pub preset: CompressionPreset,
+  pub circuit_data: CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+  inner_proof: ProofWithPublicInputsTarget<2>
+}
+
+impl Compressor {
+  pub fn new(
+    inner_verifier: &VerifierOnlyCircuitData<PoseidonGoldilocksConfig, 2>,
+    inner_common: &CommonCircuitData<GoldilocksField, 2>,
+    preset: CompressionPreset) -> Self {
+    const D: usize = 2;
+    type C = PoseidonGoldilocksConfig;
+    type F = <C as GenericConfig<D>>::F;
+
+    let mut builder = CircuitBuilder::<F, D>::new(preset.config());
+    let inner_proof = builder.add_virtual_proof_with_pis(inner_common);
+    // The verifier data is a constant, so only proofs of the inner circuit are accepted
+    let verifier_data = VerifierCircuitTarget {
+      constants_sigmas_cap: builder.constant_merkle_cap(&inner_verifier.constants_sigmas_cap),
+      circuit_digest: builder.constant_hash(inner_verifier.circuit_digest)
+    };
+    builder.verify_proof::<C>(&inner_proof, &verifier_data, inner_common);
+    builder.register_public_inputs(&inner_proof.public_inputs);
+
+    Compressor {
+      preset: preset,
+      circuit_data: builder.build::<C>(),
+      inner_proof: inner_proof
+    }
+  }
+
+  // Compressor for proofs of the given circuit
+  pub fn for_circuit(inner: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, preset: CompressionPreset) -> Self {
+    Compressor::new(&inner.verifier_only, &inner.common, preset)
+  }
+
+  // Returns a proof with the same public inputs, that the inner proof is valid
+  pub fn compress(&self, proof: &ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>)
+    -> Result<ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>> {
+    let mut pw = PartialWitness::new();
+    pw.set_proof_with_pis_target(&self.inner_proof, proof);
+    self.circuit_data.prove(pw)
+  }
+
+  pub fn verify(&self, proof: ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> Result<()> {
+    self.circuit_data.verify(proof)
+  }
+}
+
+// Size in bytes of a serialized proof, including its public inputs
+pub fn proof_size(proof: &ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> Result<usize> {
+  let mut bytes = Vec::new();
+  bytes.write_proof_with_public_inputs(proof).map_err(|e| anyhow!("serializing proof: {:?}", e))?;
+  Ok(bytes.len())
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressionReport {
+  pub preset: CompressionPreset,
+  pub original_size: usize,
+  pub compressed_size: usize,
+  // Building the wrapper circuit only happens once per inner circuit, proving happens for every proof
+  pub build_time: Duration,
+  pub proving_time: Duration
+}
+
+// Compresses the proof with every preset and reports the sizes and timings
+pub fn compression_report(
+  inner: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+  proof: &ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> Result<Vec<CompressionReport>> {
+  let original_size = proof_size(proof)?;
+  let mut reports = Vec::new();
+  for preset in CompressionPreset::all() {
+    let start = Instant::now();
+    let compressor = Compressor::for_circuit(inner, preset);
+    let build_time = start.elapsed();
+
+    let start = Instant::now();
+    let compressed = compressor.compress(proof)?;
+    let proving_time = start.elapsed();
+
+    reports.push(CompressionReport {
+      preset: preset,
+      original_size: original_size,
+      compressed_size: proof_size(&compressed)?,
+      build_time: build_time,
+      proving_time: proving_time
+    });
+    compressor.verify(compressed)?;
+  }
+  Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::plonk::{circuit_data::CircuitData, config::PoseidonGoldilocksConfig, proof::ProofWithPublicInputs};
+  use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+  use rand::Rng;
+
+  use crate::{compression::{proof_size, CompressionPreset, Compressor}, mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::MMR, mmr_plonky2_verifier::{membership_circuit_data, prove_membership}}};
+
+  // Circuit and proof of prove_membership for a leaf in an MMR of 11 leaves
+  fn mmr_proof() -> (&'static CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) {
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<GoldilocksField> = (0..11).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    (membership_circuit_data(), prove_membership(&mmr, 5).unwrap())
+  }
+
+  #[test]
+  fn test_compress_keeps_public_inputs() -> Result<()> {
+    let (circuit_data, proof) = mmr_proof();
+    let compressor = Compressor::for_circuit(circuit_data, CompressionPreset::Rate4);
+    let compressed = compressor.compress(&proof)?;
+
+    assert!(compressed.public_inputs == proof.public_inputs);
+    compressor.verify(compressed)
+  }
+
+  #[test]
+  fn test_compressed_proof_is_smaller() -> Result<()> {
+    let (circuit_data, proof) = mmr_proof();
+    let compressor = Compressor::for_circuit(circuit_data, CompressionPreset::Rate6);
+    let compressed = compressor.compress(&proof)?;
+
+    assert!(proof_size(&compressed)? < proof_size(&proof)?);
+    compressor.verify(compressed)
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_compress_rejects_changed_public_inputs() {
+    let (circuit_data, mut proof) = mmr_proof();
+    let compressor = Compressor::for_circuit(circuit_data, CompressionPreset::Rate4);
+    proof.public_inputs[0] = proof.public_inputs[0] + GoldilocksField::ONE;
+    compressor.compress(&proof).unwrap();
+  }
+}
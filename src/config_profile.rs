@@ -0,0 +1,172 @@
+use plonky2::{fri::{reduction_strategies::FriReductionStrategy, FriConfig}, plonk::circuit_data::CircuitConfig};
+
+/**
+ * Profiles for the CircuitConfig the circuit builders use.
+ *
+ * Only the FRI parameters and blinding differ between the predefined profiles; they keep the wires of
+ * standard_recursion_config, so the gates of every circuit fit and proofs of any profile can be verified
+ * recursively. A circuit built with another profile has the same constraints and public inputs, only its proofs
+ * are encoded differently. Every profile has a conjectured security of
+ * rate_bits * num_query_rounds + proof_of_work_bits ~ 100 bits.
+ *
+ * rate_bits must be at least 3, so the rate is at most 1/8: the Poseidon gate has degree 7, which needs a quotient
+ * degree factor of 8. Lower rates, like the 1/64 of SmallProof, are fine.
+ * The hash config is always PoseidonGoldilocksConfig with D = 2.
+ */
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigProfile {
+  /// rate 1/8 with 34 queries and no proof of work, so the prover doesn't grind; proofs are larger
+  FastProver,
+  /// rate 1/64 with 14 queries; proving is slower, since the LDE is 8 times as large
+  SmallProof,
+  /// standard_recursion_config: rate 1/8 with 28 queries and 16 bits of proof of work
+  RecursionFriendly,
+  /// standard_recursion_zk_config: RecursionFriendly with blinding, so a proof reveals nothing but its public inputs.
+  /// The membership circuits only make the root public, so the leaf, its position and the path stay hidden:
+  ///
+  /// ```
+  /// use plonky2::{iop::witness::PartialWitness, plonk::proof::ProofWithPublicInputs};
+  /// use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+  /// use plonky2_merkle_trees::{config_profile::ConfigProfile, simple_merkle_tree::{circuit::verify_merkle_proof_circuit, simple_merkle_tree::MerkleTree}};
+  ///
+  /// let leaves: Vec<GoldilocksField> = (0..8).map(GoldilocksField::from_canonical_u64).collect();
+  /// let tree = MerkleTree::build(leaves.clone());
+  /// let (circuit_data, targets) = verify_merkle_proof_circuit(tree.count_levels, false, &ConfigProfile::ZeroKnowledge);
+  /// let mut pw = PartialWitness::new();
+  /// targets.set_witness(&mut pw, leaves[3], 3, &tree.get_merkle_proof(3));
+  /// let proof = circuit_data.prove(pw).unwrap();
+  ///
+  /// // The verifier only needs the root
+  /// let public_proof = ProofWithPublicInputs { proof: proof.proof, public_inputs: tree.root.elements.to_vec() };
+  /// circuit_data.verifier_data().verify(public_proof).unwrap();
+  /// ```
+  ///
+  /// The same holds for MMR proofs:
+  ///
+  /// ```
+  /// use plonky2::{iop::witness::PartialWitness, plonk::proof::ProofWithPublicInputs};
+  /// use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+  /// use plonky2_merkle_trees::{config_profile::ConfigProfile, mmr::{merkle_mountain_ranges::MMR, mmr_plonky2_verifier::verify_mmr_proof_circuit}};
+  ///
+  /// let leaves: Vec<GoldilocksField> = (0..11).map(GoldilocksField::from_canonical_u64).collect();
+  /// let mmr = MMR::from_leaves(&leaves);
+  /// let root = mmr.clone().bagging_the_peaks();
+  /// let mmr_proof = mmr.get_proof_normal_index(6);
+  /// let (circuit_data, targets) =
+  ///   verify_mmr_proof_circuit(mmr_proof.merkle_proof.len(), mmr_proof.peaks.len(), &ConfigProfile::ZeroKnowledge);
+  /// let mut pw = PartialWitness::new();
+  /// targets.set_witness(&mut pw, &mmr_proof, leaves[6]);
+  /// let proof = circuit_data.prove(pw).unwrap();
+  ///
+  /// let public_proof = ProofWithPublicInputs { proof: proof.proof, public_inputs: root.elements.to_vec() };
+  /// circuit_data.verifier_data().verify(public_proof).unwrap();
+  /// ```
+  ZeroKnowledge,
+  /// Any other config. Recursive verifiers need a config wide enough for the recursive verification gates
+  Custom(CircuitConfig)
+}
+
+impl Default for ConfigProfile {
+  fn default() -> Self {
+    ConfigProfile::RecursionFriendly
+  }
+}
+
+impl ConfigProfile {
+  pub fn circuit_config(&self) -> CircuitConfig {
+    match self {
+      ConfigProfile::FastProver => with_fri_params(3, 34, 0),
+      ConfigProfile::SmallProof => with_fri_params(6, 14, 16),
+      ConfigProfile::RecursionFriendly => CircuitConfig::standard_recursion_config(),
+      ConfigProfile::ZeroKnowledge => CircuitConfig::standard_recursion_zk_config(),
+      ConfigProfile::Custom(config) => config.clone()
+    }
+  }
+}
+
+fn with_fri_params(rate_bits: usize, num_query_rounds: usize, proof_of_work_bits: u32) -> CircuitConfig {
+  let standard = CircuitConfig::standard_recursion_config();
+  CircuitConfig {
+    fri_config: FriConfig {
+      rate_bits: rate_bits,
+      cap_height: standard.fri_config.cap_height,
+      proof_of_work_bits: proof_of_work_bits,
+      reduction_strategy: FriReductionStrategy::ConstantArityBits(4, 5),
+      num_query_rounds: num_query_rounds
+    },
+    ..standard
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{iop::witness::PartialWitness, plonk::circuit_data::CircuitConfig};
+  use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::MMR, mmr_plonky2_verifier::verify_mmr_proof_circuit, mmr_plonky2_verifier_1_recursion::{complete_verification_circuit_with_inner_proof, verify_inner_merkle_proof_circuit}}};
+
+  #[test]
+  fn test_profiles_have_same_public_inputs() -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<GoldilocksField> = (0..13).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    let root = mmr.clone().bagging_the_peaks();
+    let proof = mmr.get_proof_normal_index(10);
+
+    let profiles = [
+      ConfigProfile::FastProver,
+      ConfigProfile::SmallProof,
+      ConfigProfile::RecursionFriendly,
+      ConfigProfile::ZeroKnowledge,
+      ConfigProfile::Custom(CircuitConfig::standard_recursion_config())
+    ];
+    for profile in profiles {
+      let (circuit_data, targets) = verify_mmr_proof_circuit(proof.merkle_proof.len(), proof.peaks.len(), &profile);
+      assert!(circuit_data.common.config == profile.circuit_config());
+
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, &proof, leaves[10]);
+      let plonky2_proof = circuit_data.prove(pw)?;
+      assert!(plonky2_proof.public_inputs == root.elements);
+      circuit_data.verify(plonky2_proof)?;
+    }
+    Ok(())
+  }
+
+  // Proves an MMR proof with the 1 recursion verifier, where the inner and outer circuit can have different profiles
+  fn prove_with_1_recursion(inner_profile: &ConfigProfile, outer_profile: &ConfigProfile) -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<GoldilocksField> = (0..6).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    let root = mmr.clone().bagging_the_peaks();
+    let proof = mmr.get_proof_normal_index(1);
+
+    let (inner_data, inner_targets) =
+      verify_inner_merkle_proof_circuit(proof.merkle_proof.len(), proof.peaks.len(), inner_profile);
+    let mut pw = PartialWitness::new();
+    inner_targets.set_witness(&mut pw, &proof, leaves[1]);
+    let inner_proof = inner_data.prove(pw)?;
+
+    let (outer_data, outer_targets) =
+      complete_verification_circuit_with_inner_proof(&inner_data, proof.peaks.len(), outer_profile);
+    let mut pw = PartialWitness::new();
+    outer_targets.set_witness(&mut pw, &proof, &inner_proof);
+    let outer_proof = outer_data.prove(pw)?;
+    assert!(outer_proof.public_inputs == root.elements);
+    outer_data.verify(outer_proof)
+  }
+
+  #[test]
+  fn test_recursion_across_profiles() -> Result<()> {
+    prove_with_1_recursion(&ConfigProfile::FastProver, &ConfigProfile::SmallProof)
+  }
+
+  // Only the root is public in the outer proof, and the inner proof is hidden in its witness
+  #[test]
+  fn test_zero_knowledge_recursion() -> Result<()> {
+    prove_with_1_recursion(&ConfigProfile::ZeroKnowledge, &ConfigProfile::ZeroKnowledge)
+  }
+}
@@ -0,0 +1,264 @@
+use plonky2::{field::{extension::Extendable, goldilocks_field::GoldilocksField}, hash::{hash_types::{HashOutTarget, RichField}, poseidon::PoseidonHash}, iop::target::{BoolTarget, Target}, plonk::{circuit_builder::CircuitBuilder, circuit_data::CircuitData, config::PoseidonGoldilocksConfig}};
+
+/**
+ * Merkle Tree and MMR gadgets as methods on CircuitBuilder, so other circuits can embed a membership check in one call.
+ *
+ * The hashing is the same as in MerkleTree and MMR: a parent is the Poseidon hash of its left and right child, and the
+ * root of an MMR with more than 1 peak is the hash of all peaks (see MMR::bagging_the_peaks).
+ * A direction bit is true if the sibling is on the left, which is the same as a set bit in the index of the node.
+ */
+pub trait MerkleGadgets<F: RichField + Extendable<D>, const D: usize> {
+  // Returns (second, first) if swap is true and (first, second) otherwise
+  fn conditional_swap_hash(&mut self, swap: BoolTarget, first: HashOutTarget, second: HashOutTarget) -> (HashOutTarget, HashOutTarget);
+
+  // Returns whether all 4 elements of the hashes are equal
+  fn is_equal_hash(&mut self, first: HashOutTarget, second: HashOutTarget) -> BoolTarget;
+
+  // Hashes leaf_hash up along the siblings, where siblings_on_left holds the direction at every level. Returns the root
+  fn verify_merkle_path(&mut self, leaf_hash: HashOutTarget, siblings: &[HashOutTarget], siblings_on_left: &[BoolTarget]) -> HashOutTarget;
+
+  // Returns the root of an MMR with the given peaks
+  fn bag_peaks(&mut self, peaks: &[HashOutTarget]) -> HashOutTarget;
+
+  // Checks hash equals one of the hashes in the list
+  fn assert_hash_in_list(&mut self, hash: HashOutTarget, list: &[HashOutTarget]);
+
+  // Checks hash equals list[index]. Costs a random access per element of the hashes instead of a comparison per item
+  // The list can hold at most 64 hashes
+  fn assert_hash_at_index(&mut self, hash: HashOutTarget, list: &[HashOutTarget], index: Target);
+
+  // Checks the Merkle path leads from leaf_hash to the peak at peak_index. Returns the root of the MMR
+  fn verify_mmr_membership(
+    &mut self,
+    leaf_hash: HashOutTarget,
+    siblings: &[HashOutTarget],
+    siblings_on_left: &[BoolTarget],
+    peaks: &[HashOutTarget],
+    peak_index: Target) -> HashOutTarget;
+}
+
+impl<F: RichField + Extendable<D>, const D: usize> MerkleGadgets<F, D> for CircuitBuilder<F, D> {
+  fn conditional_swap_hash(&mut self, swap: BoolTarget, first: HashOutTarget, second: HashOutTarget) -> (HashOutTarget, HashOutTarget) {
+    let swapped_first = self.select_hash(swap, second, first);
+    let swapped_second = self.select_hash(swap, first, second);
+    (swapped_first, swapped_second)
+  }
+
+  fn is_equal_hash(&mut self, first: HashOutTarget, second: HashOutTarget) -> BoolTarget {
+    let mut result = self._true();
+    for i in 0..4 {
+      let element_equal = self.is_equal(first.elements[i], second.elements[i]);
+      result = self.and(result, element_equal);
+    }
+    result
+  }
+
+  fn verify_merkle_path(&mut self, leaf_hash: HashOutTarget, siblings: &[HashOutTarget], siblings_on_left: &[BoolTarget]) -> HashOutTarget {
+    assert!(siblings.len() == siblings_on_left.len());
+    let mut next_hash = leaf_hash;
+    for (sibling, on_left) in siblings.iter().zip(siblings_on_left) {
+      // Swap the inputs instead of hashing both orders, so every level takes a single Poseidon permutation
+      let (left, right) = self.conditional_swap_hash(*on_left, next_hash, *sibling);
+      next_hash = self.hash_or_noop::<PoseidonHash>([
+        left.elements.to_vec(),
+        right.elements.to_vec()
+      ].concat());
+    }
+    next_hash
+  }
+
+  fn bag_peaks(&mut self, peaks: &[HashOutTarget]) -> HashOutTarget {
+    assert!(!peaks.is_empty());
+    if peaks.len() == 1 {
+      // If there's only 1 peak, the root is equal to that peak
+      return peaks[0];
+    }
+    self.hash_n_to_hash_no_pad::<PoseidonHash>(peaks.iter().flat_map(|peak| peak.elements).collect())
+  }
+
+  fn assert_hash_in_list(&mut self, hash: HashOutTarget, list: &[HashOutTarget]) {
+    assert!(!list.is_empty());
+    let mut in_list = self._false();
+    for item in list {
+      let equal = self.is_equal_hash(hash, *item);
+      in_list = self.or(in_list, equal);
+    }
+    self.assert_one(in_list.target);
+  }
+
+  fn assert_hash_at_index(&mut self, hash: HashOutTarget, list: &[HashOutTarget], index: Target) {
+    assert!(!list.is_empty() && list.len() <= 64);
+    // Random access needs a power of 2 items. The padding repeats the first item, so any index picks an item of the list
+    let mut padded = list.to_vec();
+    padded.resize(list.len().next_power_of_two(), list[0]);
+    let selected = self.random_access_hash(index, padded);
+    self.connect_hashes(hash, selected);
+  }
+
+  fn verify_mmr_membership(
+    &mut self,
+    leaf_hash: HashOutTarget,
+    siblings: &[HashOutTarget],
+    siblings_on_left: &[BoolTarget],
+    peaks: &[HashOutTarget],
+    peak_index: Target) -> HashOutTarget {
+    let subtree_root = self.verify_merkle_path(leaf_hash, siblings, siblings_on_left);
+    self.assert_hash_at_index(subtree_root, peaks, peak_index);
+    self.bag_peaks(peaks)
+  }
+}
+
+// Returns the number of Poseidon permutations in the circuit. Every permutation is a PoseidonGate with its own generator
+pub fn count_poseidon_permutations(circuit_data: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> usize {
+  circuit_data.prover_only.generators.iter().filter(|generator| generator.0.id() == "PoseidonGenerator").count()
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{hash::{hash_types::HashOut, poseidon::PoseidonHash}, iop::witness::{PartialWitness, WitnessWrite}, plonk::{circuit_builder::CircuitBuilder, circuit_data::CircuitConfig, config::{Hasher, PoseidonGoldilocksConfig}}};
+  use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+  use rand::Rng;
+
+  use crate::{gadgets::MerkleGadgets, mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::MMR}};
+
+  fn random_hash() -> HashOut<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    HashOut::from_partial(&(0..4).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect::<Vec<_>>())
+  }
+
+  // Proves the membership of every leaf with the same circuit shape as the native proof
+  #[test]
+  fn test_verify_mmr_membership() -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<GoldilocksField> = (0..11).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    let root = mmr.clone().bagging_the_peaks();
+
+    for leaf_index in [0, 7, 9, 10] {
+      let proof = mmr.clone().get_proof_normal_index(leaf_index);
+      let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+      let leaf = builder.add_virtual_target();
+      let leaf_hash = builder.hash_or_noop::<PoseidonHash>([leaf].to_vec());
+      let siblings = builder.add_virtual_hashes(proof.merkle_proof.len());
+      let siblings_on_left: Vec<_> = (0..proof.merkle_proof.len()).map(|_| builder.add_virtual_bool_target_safe()).collect();
+      let peaks = builder.add_virtual_hashes(proof.peaks.len());
+      let peak_index = builder.add_virtual_target();
+      let computed_root = builder.verify_mmr_membership(leaf_hash, &siblings, &siblings_on_left, &peaks, peak_index);
+      builder.register_public_inputs(&computed_root.elements);
+      let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+
+      let mut pw = PartialWitness::new();
+      pw.set_target(leaf, leaves[leaf_index]);
+      for i in 0..proof.merkle_proof.len() {
+        pw.set_hash_target(siblings[i], proof.merkle_proof[i].0);
+        pw.set_bool_target(siblings_on_left[i], proof.merkle_proof[i].1);
+      }
+      for i in 0..proof.peaks.len() {
+        pw.set_hash_target(peaks[i], proof.peaks[i]);
+      }
+      pw.set_target(peak_index, GoldilocksField::from_canonical_usize(proof.peak_index()));
+      let plonky2_proof = circuit_data.prove(pw)?;
+      assert!(plonky2_proof.public_inputs == root.elements);
+      circuit_data.verify(plonky2_proof)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_conditional_swap_hash() -> Result<()> {
+    let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+    let first = builder.add_virtual_hash();
+    let second = builder.add_virtual_hash();
+    let swap = builder.add_virtual_bool_target_safe();
+    let (left, right) = builder.conditional_swap_hash(swap, first, second);
+    builder.register_public_inputs(&left.elements);
+    builder.register_public_inputs(&right.elements);
+    let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+
+    let (a, b) = (random_hash(), random_hash());
+    for do_swap in [false, true] {
+      let mut pw = PartialWitness::new();
+      pw.set_hash_target(first, a);
+      pw.set_hash_target(second, b);
+      pw.set_bool_target(swap, do_swap);
+      let proof = circuit_data.prove(pw)?;
+      let expected = if do_swap { [b.elements, a.elements].concat() } else { [a.elements, b.elements].concat() };
+      assert!(proof.public_inputs == expected);
+    }
+    Ok(())
+  }
+
+  // A hash that only matches a peak in some of its elements is not in the list
+  #[test]
+  #[should_panic]
+  fn test_hash_in_list_partial_match() {
+    let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+    let hash = builder.add_virtual_hash();
+    let list = builder.add_virtual_hashes(2);
+    builder.assert_hash_in_list(hash, &list);
+    let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+
+    let peak = random_hash();
+    let mut partial_match = random_hash();
+    partial_match.elements[0] = peak.elements[0];
+    let mut pw = PartialWitness::new();
+    pw.set_hash_target(hash, partial_match);
+    pw.set_hash_target(list[0], peak);
+    pw.set_hash_target(list[1], random_hash());
+    circuit_data.prove(pw).unwrap();
+  }
+
+  // The list isn't a power of 2 long, so it is padded for the random access
+  fn prove_hash_at_index(hash_index: usize, index: usize) -> Result<()> {
+    let list: Vec<HashOut<GoldilocksField>> = (0..5).map(|_| random_hash()).collect();
+    let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+    let hash_target = builder.add_virtual_hash();
+    let list_targets = builder.add_virtual_hashes(list.len());
+    let index_target = builder.add_virtual_target();
+    builder.assert_hash_at_index(hash_target, &list_targets, index_target);
+    let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+
+    let mut pw = PartialWitness::new();
+    pw.set_hash_target(hash_target, list[hash_index]);
+    for i in 0..list.len() {
+      pw.set_hash_target(list_targets[i], list[i]);
+    }
+    pw.set_target(index_target, GoldilocksField::from_canonical_usize(index));
+    let proof = circuit_data.prove(pw)?;
+    circuit_data.verify(proof)
+  }
+
+  #[test]
+  fn test_hash_at_index() -> Result<()> {
+    for i in 0..5 {
+      prove_hash_at_index(i, i)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_hash_at_wrong_index() {
+    prove_hash_at_index(3, 4).unwrap();
+  }
+
+  #[test]
+  fn test_bag_peaks() -> Result<()> {
+    let peaks: Vec<HashOut<GoldilocksField>> = (0..3).map(|_| random_hash()).collect();
+    let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+    let peak_targets = builder.add_virtual_hashes(3);
+    let root = builder.bag_peaks(&peak_targets);
+    builder.register_public_inputs(&root.elements);
+    let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+
+    let mut pw = PartialWitness::new();
+    for i in 0..3 {
+      pw.set_hash_target(peak_targets[i], peaks[i]);
+    }
+    let proof = circuit_data.prove(pw)?;
+    let expected = PoseidonHash::hash_no_pad(&peaks.iter().flat_map(|peak| peak.elements).collect::<Vec<_>>());
+    assert!(proof.public_inputs == expected.elements);
+    Ok(())
+  }
+}
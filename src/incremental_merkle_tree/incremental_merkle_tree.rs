@@ -0,0 +1,199 @@
+// Incremental Merkle Tree impl
+
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Result};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+// Depth of the tree of a deposit contract
+pub const DEPOSIT_TREE_DEPTH: usize = 32;
+
+/**
+ * Fixed-depth Merkle Tree that only supports appending leaves, like the tree of a deposit contract.
+ *
+ * Instead of all nodes, only the frontier is stored: per level the last node that is a left child.
+ * Together with the precomputed hashes of empty subtrees this is enough to append a leaf and compute the new root,
+ * so the memory is O(depth) no matter how many leaves are appended.
+ *
+ * Empty leaves have the zero hash, which is also the hash of a leaf with value zero. This gives the same root as
+ * MerkleTree::build over the leaves padded with zeros up to 2^depth leaves, and the same Merkle proofs.
+ *
+ * Since old leaves are not stored, the Merkle proof of a leaf is only available if the leaf was tracked when it was
+ * appended. The proofs of tracked leaves are kept up to date with every append.
+ */
+#[derive(Debug, Clone)]
+pub struct IncrementalMerkleTree {
+  pub depth: usize,
+  // zero_hashes[i] is the root of an empty subtree of height i
+  pub zero_hashes: Vec<HashOut<GoldilocksField>>,
+  // frontier[i] is the last node at level i that is a left child
+  frontier: Vec<HashOut<GoldilocksField>>,
+  pub nr_leaves: usize,
+  pub root: HashOut<GoldilocksField>,
+  // Merkle proofs of the tracked leaves, by leaf index
+  witnesses: BTreeMap<usize, Vec<HashOut<GoldilocksField>>>
+}
+
+impl IncrementalMerkleTree {
+  pub fn new(depth: usize) -> Self {
+    assert!(depth > 0 && depth < 64);
+    let mut zero_hashes = [HashOut::ZERO].to_vec();
+    for i in 0..depth {
+      zero_hashes.push(PoseidonHash::two_to_one(zero_hashes[i], zero_hashes[i]));
+    }
+    IncrementalMerkleTree {
+      depth: depth,
+      frontier: zero_hashes[0..depth].to_vec(),
+      root: zero_hashes[depth],
+      zero_hashes: zero_hashes,
+      nr_leaves: 0,
+      witnesses: BTreeMap::new()
+    }
+  }
+
+  // Appends the leaf and returns its index
+  pub fn append(&mut self, value: GoldilocksField) -> Result<usize> {
+    self.append_leaf(value, false)
+  }
+
+  // Appends the leaf and keeps its Merkle proof up to date from now on. Returns the index of the leaf
+  pub fn append_and_track(&mut self, value: GoldilocksField) -> Result<usize> {
+    self.append_leaf(value, true)
+  }
+
+  fn append_leaf(&mut self, value: GoldilocksField, track: bool) -> Result<usize> {
+    ensure!(self.nr_leaves < 1 << self.depth, "tree is full");
+    let leaf_index = self.nr_leaves;
+    if track {
+      // The nodes to the right are still empty
+      self.witnesses.insert(leaf_index, self.zero_hashes[0..self.depth].to_vec());
+    }
+
+    // Hash up to the root. The node at every level is the current value of the subtree that holds the new leaf
+    let mut next_hash: HashOut<GoldilocksField> = PoseidonHash::hash_or_noop(&[value]);
+    let mut updated_index = leaf_index;
+    for level in 0..self.depth {
+      // Every tracked leaf that has this node as sibling gets the new value
+      for (tracked_index, siblings) in self.witnesses.iter_mut() {
+        if (tracked_index >> level) ^ 1 == updated_index {
+          siblings[level] = next_hash;
+        }
+      }
+
+      if updated_index % 2 == 0 {
+        self.frontier[level] = next_hash;
+        next_hash = PoseidonHash::two_to_one(next_hash, self.zero_hashes[level]);
+      } else {
+        if track {
+          self.witnesses.get_mut(&leaf_index).unwrap()[level] = self.frontier[level];
+        }
+        next_hash = PoseidonHash::two_to_one(self.frontier[level], next_hash);
+      }
+      updated_index = updated_index / 2;
+    }
+
+    self.root = next_hash;
+    self.nr_leaves += 1;
+    Ok(leaf_index)
+  }
+
+  // Returns the Merkle proof of a tracked leaf against the current root, in the same format as MerkleTree::get_merkle_proof
+  pub fn get_merkle_proof(&self, leaf_index: usize) -> Option<Vec<HashOut<GoldilocksField>>> {
+    self.witnesses.get(&leaf_index).cloned()
+  }
+
+  pub fn is_tracked(&self, leaf_index: usize) -> bool {
+    self.witnesses.contains_key(&leaf_index)
+  }
+
+  // Stops keeping the Merkle proof of the leaf up to date
+  pub fn untrack(&mut self, leaf_index: usize) {
+    self.witnesses.remove(&leaf_index);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use plonky2::field::{goldilocks_field::GoldilocksField, types::Field};
+  use rand::Rng;
+
+  use crate::{mmr::common::GOLDILOCKS_FIELD_ORDER, simple_merkle_tree::simple_merkle_tree::{MerkleTree, verify_merkle_proof}};
+  use super::{IncrementalMerkleTree, DEPOSIT_TREE_DEPTH};
+
+  fn random_element() -> GoldilocksField {
+    let mut rng = rand::thread_rng();
+    GoldilocksField::from_canonical_u64(rng.gen_range(1..GOLDILOCKS_FIELD_ORDER))
+  }
+
+  #[test]
+  fn test_same_root_as_merkle_tree() {
+    let depth = 5;
+    let mut tree = IncrementalMerkleTree::new(depth);
+    let mut leaves = Vec::new();
+    for _ in 0..(1 << depth) {
+      let mut padded_leaves = leaves.clone();
+      padded_leaves.resize(1 << depth, GoldilocksField::ZERO);
+      assert!(tree.root == MerkleTree::build(padded_leaves).root);
+
+      let value = random_element();
+      tree.append(value).unwrap();
+      leaves.push(value);
+    }
+    assert!(tree.root == MerkleTree::build(leaves).root);
+    // The tree is full
+    assert!(tree.append(random_element()).is_err());
+  }
+
+  #[test]
+  fn test_tracked_witnesses() {
+    let depth = 4;
+    let mut tree = IncrementalMerkleTree::new(depth);
+    let mut leaves = Vec::new();
+    let tracked = [0, 5, 6, 15];
+    for i in 0..(1 << depth) {
+      let value = random_element();
+      if tracked.contains(&i) {
+        tree.append_and_track(value).unwrap();
+      } else {
+        tree.append(value).unwrap();
+      }
+      leaves.push(value);
+
+      // All tracked proofs are the same as those of the full tree, at every point in time
+      let mut padded_leaves = leaves.clone();
+      padded_leaves.resize(1 << depth, GoldilocksField::ZERO);
+      let full_tree = MerkleTree::build(padded_leaves);
+      for index in tracked.iter().filter(|index| **index <= i) {
+        let proof = tree.get_merkle_proof(*index).unwrap();
+        assert!(proof == full_tree.get_merkle_proof(*index));
+        assert!(verify_merkle_proof(leaves[*index], *index, tree.root, proof));
+      }
+    }
+    assert!(tree.get_merkle_proof(1).is_none());
+    tree.untrack(5);
+    assert!(!tree.is_tracked(5));
+  }
+
+  #[test]
+  fn test_deposit_tree_depth() {
+    let mut tree = IncrementalMerkleTree::new(DEPOSIT_TREE_DEPTH);
+    let empty_root = tree.root;
+    let values: Vec<GoldilocksField> = (0..100).map(|_| random_element()).collect();
+    for (i, value) in values.iter().enumerate() {
+      if i % 10 == 0 {
+        tree.append_and_track(*value).unwrap();
+      } else {
+        tree.append(*value).unwrap();
+      }
+    }
+    assert!(tree.root != empty_root);
+    for i in (0..100).step_by(10) {
+      let proof = tree.get_merkle_proof(i).unwrap();
+      assert!(proof.len() == DEPOSIT_TREE_DEPTH);
+      assert!(verify_merkle_proof(values[i], i, tree.root, proof));
+    }
+  }
+}
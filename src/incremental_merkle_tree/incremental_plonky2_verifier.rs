@@ -0,0 +1,41 @@
+use plonky2::plonk::{config::PoseidonGoldilocksConfig, circuit_data::CircuitData};
+use plonky2_field::goldilocks_field::GoldilocksField;
+
+use crate::{config_profile::ConfigProfile, simple_merkle_tree::circuit::{MerkleProofTargets, verify_merkle_proof_circuit}};
+
+// Returns a circuit that verifies the Merkle proof of a leaf in an incremental Merkle tree of the given depth
+// Proofs have the same format as those of the simple Merkle Tree, so this is the same circuit
+// The circuit is built with the CircuitConfig of [profile]
+// Public inputs are the root, followed by the leaf index
+pub fn verify_incremental_merkle_proof_circuit(depth: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MerkleProofTargets) {
+  verify_merkle_proof_circuit(depth, true, profile)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, iop::witness::PartialWitness};
+
+  use crate::{config_profile::ConfigProfile, incremental_merkle_tree::incremental_merkle_tree::{IncrementalMerkleTree, DEPOSIT_TREE_DEPTH}};
+  use super::verify_incremental_merkle_proof_circuit;
+
+  #[test]
+  fn test_deposit_tree_proof() -> Result<()> {
+    let mut tree = IncrementalMerkleTree::new(DEPOSIT_TREE_DEPTH);
+    for i in 0..20 {
+      tree.append(GoldilocksField::from_canonical_u64(100 + i))?;
+    }
+    let value = GoldilocksField::from_canonical_u64(12345);
+    let leaf_index = tree.append_and_track(value)?;
+    for i in 0..5 {
+      tree.append(GoldilocksField::from_canonical_u64(200 + i))?;
+    }
+
+    let (circuit_data, targets) = verify_incremental_merkle_proof_circuit(DEPOSIT_TREE_DEPTH, &ConfigProfile::default());
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, value, leaf_index, &tree.get_merkle_proof(leaf_index).unwrap());
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs == [tree.root.elements.to_vec(), [GoldilocksField::from_canonical_usize(leaf_index)].to_vec()].concat());
+    circuit_data.verify(proof)
+  }
+}
@@ -0,0 +1,2 @@
+pub mod incremental_merkle_tree;
+pub mod incremental_plonky2_verifier;
@@ -0,0 +1,292 @@
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, indexed_merkle_tree::indexed_merkle_tree::{IndexedLeaf, NonMembershipProof, InsertionProof}};
+
+// Targets for the 3 fields of a leaf
+#[derive(Clone, Copy)]
+pub struct IndexedLeafTarget {
+  pub value: Target,
+  pub next_index: Target,
+  pub next_value: Target
+}
+
+// Targets of a non-membership proof that need to be set in the witness
+pub struct NonMembershipTargets {
+  pub value: Target,
+  pub low_leaf: IndexedLeafTarget,
+  pub low_leaf_index: Target,
+  pub low_leaf_siblings: Vec<HashOutTarget>
+}
+
+// Targets of an insertion proof that need to be set in the witness
+pub struct InsertionTargets {
+  pub non_membership: NonMembershipTargets,
+  pub new_leaf_index: Target,
+  pub new_leaf_siblings: Vec<HashOutTarget>
+}
+
+impl IndexedLeafTarget {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, leaf: &IndexedLeaf) {
+    pw.set_target(self.value, leaf.value);
+    pw.set_target(self.next_index, GoldilocksField::from_canonical_usize(leaf.next_index));
+    pw.set_target(self.next_value, leaf.next_value);
+  }
+}
+
+impl NonMembershipTargets {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &NonMembershipProof) {
+    pw.set_target(self.value, proof.value);
+    self.low_leaf.set_witness(pw, &proof.low_leaf);
+    pw.set_target(self.low_leaf_index, GoldilocksField::from_canonical_usize(proof.low_leaf_index));
+    for i in 0..self.low_leaf_siblings.len() {
+      pw.set_hash_target(self.low_leaf_siblings[i], proof.low_leaf_siblings[i]);
+    }
+  }
+}
+
+impl InsertionTargets {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &InsertionProof) {
+    pw.set_target(self.non_membership.value, proof.value);
+    self.non_membership.low_leaf.set_witness(pw, &proof.low_leaf);
+    pw.set_target(self.non_membership.low_leaf_index, GoldilocksField::from_canonical_usize(proof.low_leaf_index));
+    for i in 0..self.non_membership.low_leaf_siblings.len() {
+      pw.set_hash_target(self.non_membership.low_leaf_siblings[i], proof.low_leaf_siblings[i]);
+    }
+    pw.set_target(self.new_leaf_index, GoldilocksField::from_canonical_usize(proof.new_leaf_index));
+    for i in 0..self.new_leaf_siblings.len() {
+      pw.set_hash_target(self.new_leaf_siblings[i], proof.new_leaf_siblings[i]);
+    }
+  }
+}
+
+// Splits x in a low and high part of 32 bits each
+// Since 2^64 is larger than the field order, this also checks the split is canonical:
+//  if the high part has all bits set, the low part must be zero
+pub fn split_canonical_u64(builder: &mut CircuitBuilder<GoldilocksField, 2>, x: Target) -> (Target, Target) {
+  let (low, high) = builder.split_low_high(x, 32, 64);
+  let max_high = builder.constant(GoldilocksField::from_canonical_u64(u32::MAX as u64));
+  let high_is_max = builder.is_equal(high, max_high);
+  let should_be_zero = builder.mul(high_is_max.target, low);
+  builder.assert_zero(should_be_zero);
+  (low, high)
+}
+
+// Returns whether a < b, for a and b that are known to fit in 32 bits
+// b - a - 1 + 2^32 fits in 33 bits, and bit 32 is set exactly when a < b
+fn less_than_u32(builder: &mut CircuitBuilder<GoldilocksField, 2>, a: Target, b: Target) -> BoolTarget {
+  let offset = builder.constant(GoldilocksField::from_canonical_u64((1 << 32) - 1));
+  let diff = builder.sub(b, a);
+  let shifted = builder.add(diff, offset);
+  let bits = builder.split_le(shifted, 33);
+  bits[32]
+}
+
+// Returns whether a < b, comparing both as canonical u64
+pub fn less_than(builder: &mut CircuitBuilder<GoldilocksField, 2>, a: Target, b: Target) -> BoolTarget {
+  let (a_low, a_high) = split_canonical_u64(builder, a);
+  let (b_low, b_high) = split_canonical_u64(builder, b);
+  let high_lt = less_than_u32(builder, a_high, b_high);
+  let high_eq = builder.is_equal(a_high, b_high);
+  let low_lt = less_than_u32(builder, a_low, b_low);
+  let high_eq_and_low_lt = builder.and(high_eq, low_lt);
+  builder.or(high_lt, high_eq_and_low_lt)
+}
+
+pub fn indexed_leaf_hash_circuit(builder: &mut CircuitBuilder<GoldilocksField, 2>, leaf: IndexedLeafTarget) -> HashOutTarget {
+  builder.hash_n_to_hash_no_pad::<PoseidonHash>([leaf.value, leaf.next_index, leaf.next_value].to_vec())
+}
+
+// Hashes the leaf up to the root. The leaf index is a target, of which the bits determine the direction at every level
+pub fn compute_root_circuit(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  leaf_hash: HashOutTarget,
+  leaf_index: Target,
+  siblings: &[HashOutTarget]) -> HashOutTarget {
+  let index_bits = builder.split_le(leaf_index, siblings.len());
+  builder.verify_merkle_path(leaf_hash, siblings, &index_bits)
+}
+
+fn add_leaf_target(builder: &mut CircuitBuilder<GoldilocksField, 2>) -> IndexedLeafTarget {
+  IndexedLeafTarget {
+    value: builder.add_virtual_target(),
+    next_index: builder.add_virtual_target(),
+    next_value: builder.add_virtual_target()
+  }
+}
+
+// Adds the constraints for a non-membership proof to the builder and returns the targets and the root it leads to
+fn add_non_membership(builder: &mut CircuitBuilder<GoldilocksField, 2>, depth: usize) -> (NonMembershipTargets, HashOutTarget) {
+  let targets = NonMembershipTargets {
+    value: builder.add_virtual_target(),
+    low_leaf: add_leaf_target(builder),
+    low_leaf_index: builder.add_virtual_target(),
+    low_leaf_siblings: builder.add_virtual_hashes(depth)
+  };
+  let one = builder.one();
+  let zero = builder.zero();
+
+  // low_leaf.value < value
+  let low_lt_value = less_than(builder, targets.low_leaf.value, targets.value);
+  builder.connect(low_lt_value.target, one);
+
+  // value < low_leaf.next_value, unless the low leaf is the last one in the list
+  let value_lt_next = less_than(builder, targets.value, targets.low_leaf.next_value);
+  let is_last = builder.is_equal(targets.low_leaf.next_value, zero);
+  let next_ok = builder.or(value_lt_next, is_last);
+  builder.connect(next_ok.target, one);
+
+  let low_leaf_hash = indexed_leaf_hash_circuit(builder, targets.low_leaf);
+  let root = compute_root_circuit(builder, low_leaf_hash, targets.low_leaf_index, &targets.low_leaf_siblings);
+  (targets, root)
+}
+
+// Returns a circuit that verifies that a value is not in an indexed Merkle tree of the given depth
+// The circuit is built with the CircuitConfig of [profile]
+// Public inputs are the root, followed by the value
+pub fn verify_imt_non_membership_circuit(depth: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, NonMembershipTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let (targets, root) = add_non_membership(&mut builder, depth);
+  builder.register_public_inputs(&root.elements);
+  builder.register_public_input(targets.value);
+
+  (builder.build::<C>(), targets)
+}
+
+// Returns a circuit that verifies the insertion of a value in an indexed Merkle tree of the given depth
+// The circuit is built with the CircuitConfig of [profile]
+// Public inputs are the old root, the new root, the inserted value and the index it was inserted at
+pub fn verify_imt_insertion_circuit(depth: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, InsertionTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+
+  // 1. The value is not in the old tree
+  let (non_membership, old_root) = add_non_membership(&mut builder, depth);
+  let new_leaf_index = builder.add_virtual_target();
+  let new_leaf_siblings = builder.add_virtual_hashes(depth);
+
+  // 2. Update the low leaf to point to the new leaf
+  let updated_low_leaf = IndexedLeafTarget {
+    value: non_membership.low_leaf.value,
+    next_index: new_leaf_index,
+    next_value: non_membership.value
+  };
+  let updated_low_leaf_hash = indexed_leaf_hash_circuit(&mut builder, updated_low_leaf);
+  let intermediate_root = compute_root_circuit(&mut builder, updated_low_leaf_hash, non_membership.low_leaf_index, &non_membership.low_leaf_siblings);
+
+  // 3. The slot of the new leaf is empty in the intermediate tree
+  let empty_leaf = builder.constant_hash(HashOut::ZERO);
+  let empty_slot_root = compute_root_circuit(&mut builder, empty_leaf, new_leaf_index, &new_leaf_siblings);
+  builder.connect_hashes(empty_slot_root, intermediate_root);
+
+  // 4. Write the new leaf, which takes over the pointer of the low leaf
+  let new_leaf = IndexedLeafTarget {
+    value: non_membership.value,
+    next_index: non_membership.low_leaf.next_index,
+    next_value: non_membership.low_leaf.next_value
+  };
+  let new_leaf_hash = indexed_leaf_hash_circuit(&mut builder, new_leaf);
+  let new_root = compute_root_circuit(&mut builder, new_leaf_hash, new_leaf_index, &new_leaf_siblings);
+
+  builder.register_public_inputs(&old_root.elements);
+  builder.register_public_inputs(&new_root.elements);
+  builder.register_public_input(non_membership.value);
+  builder.register_public_input(new_leaf_index);
+
+  let targets = InsertionTargets {
+    non_membership: non_membership,
+    new_leaf_index: new_leaf_index,
+    new_leaf_siblings: new_leaf_siblings
+  };
+  (builder.build::<C>(), targets)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, iop::witness::PartialWitness};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::common::GOLDILOCKS_FIELD_ORDER, indexed_merkle_tree::indexed_merkle_tree::IndexedMerkleTree};
+  use super::{verify_imt_non_membership_circuit, verify_imt_insertion_circuit};
+
+  fn test_tree(depth: usize, nr_values: usize) -> IndexedMerkleTree {
+    let mut rng = rand::thread_rng();
+    let mut imt = IndexedMerkleTree::new(depth);
+    for _ in 0..nr_values {
+      imt.insert(GoldilocksField::from_canonical_u64(rng.gen_range(1..GOLDILOCKS_FIELD_ORDER))).unwrap();
+    }
+    imt
+  }
+
+  #[test]
+  fn test_non_membership_circuit() -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let depth = 4;
+    let imt = test_tree(depth, 10);
+    let (circuit_data, targets) = verify_imt_non_membership_circuit(depth, &ConfigProfile::default());
+
+    // Also include the largest possible value, which has the last leaf of the list as low leaf
+    let values = [
+      GoldilocksField::from_canonical_u64(rng.gen_range(1..GOLDILOCKS_FIELD_ORDER)),
+      GoldilocksField::from_canonical_u64(GOLDILOCKS_FIELD_ORDER - 1)
+    ];
+    for value in values {
+      let non_membership = imt.get_non_membership_proof(value)?;
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, &non_membership);
+      let proof = circuit_data.prove(pw)?;
+      assert!(proof.public_inputs == [imt.root().elements.to_vec(), [value].to_vec()].concat());
+      circuit_data.verify(proof)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_non_membership_circuit_value_in_tree() {
+    let depth = 4;
+    let imt = test_tree(depth, 10);
+    let (circuit_data, targets) = verify_imt_non_membership_circuit(depth, &ConfigProfile::default());
+
+    // Use the proof of a value next to a value in the tree, but claim the value in the tree
+    let present = imt.leaves[3].value;
+    let mut non_membership = imt.get_non_membership_proof(present + GoldilocksField::ONE).unwrap();
+    non_membership.value = present;
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &non_membership);
+    circuit_data.prove(pw).unwrap();
+  }
+
+  #[test]
+  fn test_insertion_circuit() -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let depth = 4;
+    let mut imt = test_tree(depth, 5);
+    let (circuit_data, targets) = verify_imt_insertion_circuit(depth, &ConfigProfile::default());
+
+    for _ in 0..3 {
+      let value = GoldilocksField::from_canonical_u64(rng.gen_range(1..GOLDILOCKS_FIELD_ORDER));
+      let insertion = imt.insert(value)?;
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, &insertion);
+      let proof = circuit_data.prove(pw)?;
+      assert!(proof.public_inputs == [
+        insertion.old_root.elements.to_vec(),
+        insertion.new_root.elements.to_vec(),
+        [value, GoldilocksField::from_canonical_usize(insertion.new_leaf_index)].to_vec()
+      ].concat());
+      circuit_data.verify(proof)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,293 @@
+// Indexed Merkle Tree impl
+
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Result};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+use crate::simple_merkle_tree::simple_merkle_tree::{MerkleTree, verify_merkle_proof_for_hash};
+
+/**
+ * Indexed Merkle Tree: a Merkle Tree whose leaves together form a linked list, sorted by value.
+ *
+ * Every leaf stores (value, next_index, next_value), pointing to the leaf with the next larger value.
+ * The leaf with the largest value has next_value zero, which marks the end of the list.
+ * Leaf 0 is a sentinel leaf with value zero, so zero can't be inserted.
+ *
+ * To show a value is not in the tree, it suffices to show a single leaf (the "low leaf") with
+ *  low_leaf.value < value < low_leaf.next_value
+ * This only needs a Merkle proof of the tree depth, instead of a path as deep as the key space like in a Sparse Merkle Tree.
+ *
+ * Values are compared as canonical u64. Leaves are inserted at the next free index; empty slots hash to the zero hash,
+ * while filled leaves are hashed with Poseidon so an empty slot can never be mistaken for a leaf.
+ * The nodes are hashed the same way as in the simple Merkle Tree.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IndexedLeaf {
+  pub value: GoldilocksField,
+  pub next_index: usize,
+  pub next_value: GoldilocksField
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexedMerkleTree {
+  pub depth: usize,
+  // The filled leaves, in order of insertion. The tree has 2^depth slots in total
+  pub leaves: Vec<IndexedLeaf>,
+  pub tree: MerkleTree,
+  // Maps the (canonical) value of each leaf to its index
+  indices: BTreeMap<u64, usize>
+}
+
+// Proof that value is not in the tree, by showing the low leaf that "skips over" it
+#[derive(Debug, Clone)]
+pub struct NonMembershipProof {
+  pub value: GoldilocksField,
+  pub low_leaf: IndexedLeaf,
+  pub low_leaf_index: usize,
+  pub low_leaf_siblings: Vec<HashOut<GoldilocksField>>
+}
+
+// Proof that inserting value changed the tree from old_root to new_root
+// Inserting takes 2 steps:
+// 1. The low leaf is updated to point to the new leaf. The siblings are those of the low leaf before the update
+// 2. The new leaf is written in the empty slot at new_leaf_index. The siblings are those of the slot after step 1
+#[derive(Debug, Clone)]
+pub struct InsertionProof {
+  pub value: GoldilocksField,
+  pub low_leaf: IndexedLeaf,
+  pub low_leaf_index: usize,
+  pub low_leaf_siblings: Vec<HashOut<GoldilocksField>>,
+  pub new_leaf_index: usize,
+  pub new_leaf_siblings: Vec<HashOut<GoldilocksField>>,
+  pub old_root: HashOut<GoldilocksField>,
+  pub new_root: HashOut<GoldilocksField>
+}
+
+impl IndexedLeaf {
+  pub fn hash(&self) -> HashOut<GoldilocksField> {
+    PoseidonHash::hash_no_pad(&[self.value, GoldilocksField::from_canonical_usize(self.next_index), self.next_value])
+  }
+
+  // Returns true if value lies strictly between this leaf and the next one in the list
+  pub fn skips_over(&self, value: GoldilocksField) -> bool {
+    let value = value.to_canonical_u64();
+    let is_last = self.next_value == GoldilocksField::ZERO;
+    self.value.to_canonical_u64() < value && (is_last || value < self.next_value.to_canonical_u64())
+  }
+}
+
+impl IndexedMerkleTree {
+  // Create a tree with 2^depth slots, holding only the sentinel leaf
+  pub fn new(depth: usize) -> Self {
+    assert!(depth > 0);
+    let sentinel = IndexedLeaf {
+      value: GoldilocksField::ZERO,
+      next_index: 0,
+      next_value: GoldilocksField::ZERO
+    };
+    let mut indices = BTreeMap::new();
+    indices.insert(0, 0);
+    // The other slots are empty; inserts update the paths of the changed leaves
+    let mut level0 = [sentinel.hash()].to_vec();
+    level0.resize(1 << depth, HashOut::ZERO);
+    IndexedMerkleTree {
+      depth: depth,
+      leaves: [sentinel].to_vec(),
+      tree: MerkleTree::build_from_hashes(level0),
+      indices: indices
+    }
+  }
+
+  pub fn root(&self) -> HashOut<GoldilocksField> {
+    self.tree.root
+  }
+
+  pub fn contains(&self, value: GoldilocksField) -> bool {
+    self.indices.contains_key(&value.to_canonical_u64())
+  }
+
+  // Index of the leaf with the largest value that is smaller than the given value
+  fn low_leaf_index(&self, value: GoldilocksField) -> usize {
+    // The sentinel has value 0, so there always is a smaller value
+    *self.indices.range(..value.to_canonical_u64()).next_back().unwrap().1
+  }
+
+  pub fn get_non_membership_proof(&self, value: GoldilocksField) -> Result<NonMembershipProof> {
+    ensure!(value != GoldilocksField::ZERO, "zero is the value of the sentinel leaf");
+    ensure!(!self.contains(value), "value is in the tree");
+    let low_leaf_index = self.low_leaf_index(value);
+    Ok(NonMembershipProof {
+      value: value,
+      low_leaf: self.leaves[low_leaf_index],
+      low_leaf_index: low_leaf_index,
+      low_leaf_siblings: self.tree.get_merkle_proof(low_leaf_index)
+    })
+  }
+
+  // Insert a value in the next free slot and return the proof of the insertion
+  pub fn insert(&mut self, value: GoldilocksField) -> Result<InsertionProof> {
+    ensure!(self.leaves.len() < 1 << self.depth, "tree is full");
+    let non_membership = self.get_non_membership_proof(value)?;
+    let old_root = self.root();
+    let low_leaf_index = non_membership.low_leaf_index;
+    let low_leaf = non_membership.low_leaf;
+    let new_leaf_index = self.leaves.len();
+
+    // 1. Point the low leaf to the new leaf
+    self.leaves[low_leaf_index] = IndexedLeaf {
+      value: low_leaf.value,
+      next_index: new_leaf_index,
+      next_value: value
+    };
+    self.tree.update_leaf_hash(low_leaf_index, self.leaves[low_leaf_index].hash());
+
+    // 2. The new leaf takes over the old pointer of the low leaf
+    let new_leaf = IndexedLeaf {
+      value: value,
+      next_index: low_leaf.next_index,
+      next_value: low_leaf.next_value
+    };
+    self.leaves.push(new_leaf);
+    self.indices.insert(value.to_canonical_u64(), new_leaf_index);
+    // The siblings of the empty slot are those after step 1
+    let new_leaf_siblings = self.tree.update_leaf_hash(new_leaf_index, new_leaf.hash());
+
+    Ok(InsertionProof {
+      value: value,
+      low_leaf: low_leaf,
+      low_leaf_index: low_leaf_index,
+      low_leaf_siblings: non_membership.low_leaf_siblings,
+      new_leaf_index: new_leaf_index,
+      new_leaf_siblings: new_leaf_siblings,
+      old_root: old_root,
+      new_root: self.root()
+    })
+  }
+}
+
+impl NonMembershipProof {
+  pub fn verify(&self, root: HashOut<GoldilocksField>) -> bool {
+    self.low_leaf.skips_over(self.value)
+      && verify_merkle_proof_for_hash(self.low_leaf.hash(), self.low_leaf_index, root, self.low_leaf_siblings.clone())
+  }
+}
+
+impl InsertionProof {
+  pub fn verify(&self) -> bool {
+    // The value was not in the old tree
+    let non_membership = NonMembershipProof {
+      value: self.value,
+      low_leaf: self.low_leaf,
+      low_leaf_index: self.low_leaf_index,
+      low_leaf_siblings: self.low_leaf_siblings.clone()
+    };
+    if !non_membership.verify(self.old_root) {
+      return false;
+    }
+
+    // Root after updating the low leaf, in which the slot of the new leaf is still empty
+    let updated_low_leaf = IndexedLeaf {
+      value: self.low_leaf.value,
+      next_index: self.new_leaf_index,
+      next_value: self.value
+    };
+    let intermediate_root = compute_root(updated_low_leaf.hash(), self.low_leaf_index, &self.low_leaf_siblings);
+    if compute_root(HashOut::ZERO, self.new_leaf_index, &self.new_leaf_siblings) != intermediate_root {
+      return false;
+    }
+
+    let new_leaf = IndexedLeaf {
+      value: self.value,
+      next_index: self.low_leaf.next_index,
+      next_value: self.low_leaf.next_value
+    };
+    compute_root(new_leaf.hash(), self.new_leaf_index, &self.new_leaf_siblings) == self.new_root
+  }
+}
+
+fn compute_root(leaf_hash: HashOut<GoldilocksField>, leaf_index: usize, siblings: &[HashOut<GoldilocksField>]) -> HashOut<GoldilocksField> {
+  let mut next_hash = leaf_hash;
+  let mut updated_index = leaf_index;
+  for sibling in siblings {
+    if updated_index % 2 == 0 {
+      next_hash = PoseidonHash::two_to_one(next_hash, *sibling);
+    } else {
+      next_hash = PoseidonHash::two_to_one(*sibling, next_hash);
+    }
+    updated_index = updated_index / 2;
+  }
+  next_hash
+}
+
+#[cfg(test)]
+mod tests {
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::{Field, PrimeField64}}, hash::hash_types::HashOut};
+  use rand::Rng;
+
+  use crate::{mmr::common::GOLDILOCKS_FIELD_ORDER, indexed_merkle_tree::indexed_merkle_tree::IndexedMerkleTree, simple_merkle_tree::simple_merkle_tree::MerkleTree};
+
+  #[test]
+  fn test_insert_keeps_list_sorted() {
+    let mut rng = rand::thread_rng();
+    let mut imt = IndexedMerkleTree::new(5);
+    let mut values = Vec::new();
+    for _ in 0..20 {
+      let value = GoldilocksField::from_canonical_u64(rng.gen_range(1..GOLDILOCKS_FIELD_ORDER));
+      let proof = imt.insert(value).unwrap();
+      assert!(proof.verify());
+      assert!(proof.new_root == imt.root());
+      values.push(value.to_canonical_u64());
+    }
+
+    // Walking the linked list from the sentinel visits all values in increasing order
+    values.sort();
+    let mut leaf = imt.leaves[0];
+    for value in values {
+      assert!(leaf.next_value.to_canonical_u64() == value);
+      leaf = imt.leaves[leaf.next_index];
+    }
+    assert!(leaf.next_value == GoldilocksField::ZERO);
+
+    // Updating the paths of the changed leaves gives the same tree as building it from all leaves
+    let mut level0: Vec<HashOut<GoldilocksField>> = imt.leaves.iter().map(|leaf| leaf.hash()).collect();
+    level0.resize(1 << imt.depth, HashOut::ZERO);
+    assert!(imt.root() == MerkleTree::build_from_hashes(level0).root);
+  }
+
+  #[test]
+  fn test_non_membership() {
+    let mut imt = IndexedMerkleTree::new(4);
+    for value in [10, 30, 20, 50] {
+      imt.insert(GoldilocksField::from_canonical_u64(value)).unwrap();
+    }
+
+    for value in [1, 15, 25, 40, 51, GOLDILOCKS_FIELD_ORDER - 1] {
+      let proof = imt.get_non_membership_proof(GoldilocksField::from_canonical_u64(value)).unwrap();
+      assert!(proof.verify(imt.root()));
+    }
+
+    // Values in the tree have no non-membership proof
+    assert!(imt.get_non_membership_proof(GoldilocksField::from_canonical_u64(30)).is_err());
+    // And a low leaf that doesn't skip over the value doesn't work either
+    let mut proof = imt.get_non_membership_proof(GoldilocksField::from_canonical_u64(25)).unwrap();
+    proof.value = GoldilocksField::from_canonical_u64(30);
+    assert!(!proof.verify(imt.root()));
+  }
+
+  #[test]
+  fn test_insert_errors() {
+    let mut imt = IndexedMerkleTree::new(2);
+    assert!(imt.insert(GoldilocksField::ZERO).is_err());
+    for value in [1, 2, 3] {
+      imt.insert(GoldilocksField::from_canonical_u64(value)).unwrap();
+    }
+    assert!(imt.insert(GoldilocksField::from_canonical_u64(2)).is_err());
+    // 4 slots, of which 1 is the sentinel
+    assert!(imt.insert(GoldilocksField::from_canonical_u64(4)).is_err());
+  }
+}
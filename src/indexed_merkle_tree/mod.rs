@@ -0,0 +1,2 @@
+pub mod indexed_merkle_tree;
+pub mod imt_plonky2_verifier;
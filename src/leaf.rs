@@ -0,0 +1,243 @@
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, plonk::{config::Hasher, circuit_builder::CircuitBuilder}, iop::{target::Target, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+/**
+ * Leaves that consist of several field elements, such as structured records.
+ *
+ * A record is hashed with hash_no_pad over a domain tag, its number of field elements and the elements themselves.
+ * The tag and length keep records of different lengths apart (with hash_or_noop [a] and [a, 0] would collide) and
+ * keep records of 8 elements from hashing to an internal node, which is two_to_one of 8 elements.
+ * A single field element is still hashed with hash_or_noop, which is the hash the trees use for their leaves.
+ * The in-circuit counterpart exposes the targets of the individual fields, so a circuit can put constraints on them
+ * after checking the leaf is in a tree.
+ */
+
+// First element of the hash input of every record
+pub const RECORD_DOMAIN_TAG: u64 = 0x7265636f7264;
+
+fn record_hash_input(elements: &[GoldilocksField]) -> Vec<GoldilocksField> {
+  [[GoldilocksField::from_canonical_u64(RECORD_DOMAIN_TAG), GoldilocksField::from_canonical_usize(elements.len())].to_vec(), elements.to_vec()].concat()
+}
+
+pub trait Leaf {
+  fn to_field_elements(&self) -> Vec<GoldilocksField>;
+
+  fn hash(&self) -> HashOut<GoldilocksField> {
+    PoseidonHash::hash_no_pad(&record_hash_input(&self.to_field_elements()))
+  }
+}
+
+pub trait LeafTarget: Sized {
+  type Value: Leaf;
+
+  fn add_virtual(builder: &mut CircuitBuilder<GoldilocksField, 2>) -> Self;
+
+  // In the same order as Leaf::to_field_elements of the value
+  fn to_targets(&self) -> Vec<Target>;
+
+  fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, value: &Self::Value) {
+    let targets = self.to_targets();
+    let elements = value.to_field_elements();
+    assert!(targets.len() == elements.len());
+    for (target, element) in targets.into_iter().zip(elements) {
+      pw.set_target(target, element);
+    }
+  }
+
+  fn hash(&self, builder: &mut CircuitBuilder<GoldilocksField, 2>) -> HashOutTarget {
+    let targets = self.to_targets();
+    let prefix = [
+      builder.constant(GoldilocksField::from_canonical_u64(RECORD_DOMAIN_TAG)),
+      builder.constant(GoldilocksField::from_canonical_usize(targets.len()))
+    ];
+    builder.hash_n_to_hash_no_pad::<PoseidonHash>([prefix.to_vec(), targets].concat())
+  }
+}
+
+// The same leaf hash as MerkleTree::build and MMR::add_leaf
+impl Leaf for GoldilocksField {
+  fn to_field_elements(&self) -> Vec<GoldilocksField> {
+    [*self].to_vec()
+  }
+
+  fn hash(&self) -> HashOut<GoldilocksField> {
+    PoseidonHash::hash_or_noop(&[*self])
+  }
+}
+
+impl Leaf for Vec<GoldilocksField> {
+  fn to_field_elements(&self) -> Vec<GoldilocksField> {
+    self.clone()
+  }
+}
+
+impl LeafTarget for Target {
+  type Value = GoldilocksField;
+
+  fn add_virtual(builder: &mut CircuitBuilder<GoldilocksField, 2>) -> Self {
+    builder.add_virtual_target()
+  }
+
+  fn to_targets(&self) -> Vec<Target> {
+    [*self].to_vec()
+  }
+
+  fn hash(&self, builder: &mut CircuitBuilder<GoldilocksField, 2>) -> HashOutTarget {
+    builder.hash_or_noop::<PoseidonHash>([*self].to_vec())
+  }
+}
+
+// A leaf that is already hashed, such as one added with MMR::add_leaf_hash. Its hash is the leaf itself
+impl Leaf for HashOut<GoldilocksField> {
+  fn to_field_elements(&self) -> Vec<GoldilocksField> {
+    self.elements.to_vec()
+  }
+
+  fn hash(&self) -> HashOut<GoldilocksField> {
+    *self
+  }
+}
+
+impl LeafTarget for HashOutTarget {
+  type Value = HashOut<GoldilocksField>;
+
+  fn add_virtual(builder: &mut CircuitBuilder<GoldilocksField, 2>) -> Self {
+    builder.add_virtual_hash()
+  }
+
+  fn to_targets(&self) -> Vec<Target> {
+    self.elements.to_vec()
+  }
+
+  fn hash(&self, _builder: &mut CircuitBuilder<GoldilocksField, 2>) -> HashOutTarget {
+    *self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, hash::{hash_types::{HashOut, HashOutTarget}, poseidon::PoseidonHash}, plonk::{circuit_builder::CircuitBuilder, config::Hasher}, iop::{target::Target, witness::PartialWitness}};
+
+  use crate::{config_profile::ConfigProfile, mmr::{merkle_mountain_ranges::{MMR, get_mmr_index}, mmr_plonky2_verifier::verify_mmr_proof_circuit_for_record}, simple_merkle_tree::{circuit::verify_merkle_proof_circuit_for_record, simple_merkle_tree::{MerkleTree, verify_record_proof}}};
+  use super::{Leaf, LeafTarget};
+
+  #[derive(Debug, Clone)]
+  struct Record {
+    id: u64,
+    amount: u64,
+    timestamp: u64,
+    owner: HashOut<GoldilocksField>
+  }
+
+  struct RecordTarget {
+    id: Target,
+    amount: Target,
+    timestamp: Target,
+    owner: HashOutTarget
+  }
+
+  impl Leaf for Record {
+    fn to_field_elements(&self) -> Vec<GoldilocksField> {
+      [
+        [self.id, self.amount, self.timestamp].map(GoldilocksField::from_canonical_u64).to_vec(),
+        self.owner.elements.to_vec()
+      ].concat()
+    }
+  }
+
+  impl LeafTarget for RecordTarget {
+    type Value = Record;
+
+    fn add_virtual(builder: &mut CircuitBuilder<GoldilocksField, 2>) -> Self {
+      RecordTarget {
+        id: builder.add_virtual_target(),
+        amount: builder.add_virtual_target(),
+        timestamp: builder.add_virtual_target(),
+        owner: builder.add_virtual_hash()
+      }
+    }
+
+    fn to_targets(&self) -> Vec<Target> {
+      [[self.id, self.amount, self.timestamp].to_vec(), self.owner.elements.to_vec()].concat()
+    }
+  }
+
+  fn records(nr_records: u64) -> Vec<Record> {
+    (0..nr_records).map(|i| Record {
+      id: i,
+      amount: 100 * i,
+      timestamp: 1_700_000_000 + i,
+      owner: HashOut { elements: [GoldilocksField::from_canonical_u64(i); 4] }
+    }).collect()
+  }
+
+  #[test]
+  fn test_single_element_is_the_same() {
+    let leaves: Vec<GoldilocksField> = (0..8).map(GoldilocksField::from_canonical_u64).collect();
+    assert!(MerkleTree::build(leaves.clone()).root == MerkleTree::build_from_records(&leaves).root);
+  }
+
+  #[test]
+  fn test_records_in_trees() {
+    let records = records(10);
+    let tree = MerkleTree::build_from_records(&records);
+    for i in 0..records.len() {
+      assert!(verify_record_proof(&records[i], i, tree.root, tree.get_merkle_proof(i)));
+    }
+    assert!(!verify_record_proof(&records[1], 2, tree.root, tree.get_merkle_proof(2)));
+
+    let mut mmr = MMR::new();
+    for record in records.iter() {
+      mmr.add_record(record);
+    }
+    assert!(MMR::from_leaves(&records).elements == mmr.elements);
+    let proof = mmr.clone().get_proof(get_mmr_index(7));
+    assert!(proof.verify_record(&records[7], mmr.bagging_the_peaks()));
+  }
+
+  #[test]
+  fn test_constrain_record_field() -> Result<()> {
+    let records = records(16);
+    let tree = MerkleTree::build_from_records(&records);
+
+    // After the membership check, the amount of the record is made public
+    let (circuit_data, targets) = verify_merkle_proof_circuit_for_record::<RecordTarget>(tree.count_levels, false, &ConfigProfile::default(), |builder, record| {
+      builder.register_public_input(record.amount);
+    });
+    let mut pw = PartialWitness::new();
+    targets.set_record_witness(&mut pw, &records[5], 5, &tree.get_merkle_proof(5));
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs == [tree.root.elements.to_vec(), [GoldilocksField::from_canonical_u64(500)].to_vec()].concat());
+    circuit_data.verify(proof)
+  }
+
+  #[test]
+  fn test_constrain_record_field_in_mmr() -> Result<()> {
+    let records = records(11);
+    let mmr = MMR::from_leaves(&records);
+    let mmr_proof = mmr.clone().get_proof_normal_index(9);
+
+    let (circuit_data, targets) = verify_mmr_proof_circuit_for_record::<RecordTarget>(mmr_proof.merkle_proof.len(), mmr_proof.peaks.len(), &ConfigProfile::default(), |builder, record| {
+      builder.register_public_input(record.timestamp);
+    });
+    let mut pw = PartialWitness::new();
+    targets.set_record_witness(&mut pw, &mmr_proof, &records[9]);
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs == [mmr.bagging_the_peaks().elements.to_vec(), [GoldilocksField::from_canonical_u64(1_700_000_009)].to_vec()].concat());
+    circuit_data.verify(proof)
+  }
+
+  #[test]
+  fn test_records_are_domain_separated() {
+    let a = GoldilocksField::from_canonical_u64(7);
+    assert!([a].to_vec().hash() != [a, GoldilocksField::ZERO].to_vec().hash());
+    assert!([a].to_vec().hash() != a.hash());
+
+    // A record of 8 elements doesn't hash to the internal node of its two halves
+    let elements: Vec<GoldilocksField> = (0..8).map(GoldilocksField::from_canonical_u64).collect();
+    let left = HashOut::from_partial(&elements[0..4]);
+    let right = HashOut::from_partial(&elements[4..8]);
+    assert!(elements.hash() != PoseidonHash::two_to_one(left, right));
+  }
+}
@@ -1,2 +1,12 @@
 pub mod simple_merkle_tree;
-pub mod mmr;
\ No newline at end of file
+pub mod sparse_merkle_tree;
+pub mod indexed_merkle_tree;
+pub mod incremental_merkle_tree;
+pub mod mmr;
+pub mod recursion;
+pub mod leaf;
+pub mod circuit_cache;
+pub mod compression;
+pub mod config_profile;
+pub mod gadgets;
+pub mod semaphore;
\ No newline at end of file
@@ -1,18 +1,15 @@
 use plonky2::{plonk::circuit_builder::CircuitBuilder, iop::target::BoolTarget, hash::hash_types::HashOutTarget};
 
+use crate::gadgets::MerkleGadgets;
+
 pub const GOLDILOCKS_FIELD_ORDER: u64 = 18446744069414584321;
 
+// Returns whether all 4 elements of the hashes are equal, see MerkleGadgets::is_equal_hash
 pub fn equal(
   builder: &mut CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2>,
   first: HashOutTarget, 
   second: HashOutTarget) -> BoolTarget {
-  let elm0 = builder.is_equal(first.elements[0], second.elements[0]);
-  let elm1 = builder.is_equal(first.elements[1], second.elements[1]);
-  let elm2 = builder.is_equal(first.elements[2], second.elements[2]);
-  let elm3 = builder.is_equal(first.elements[3], second.elements[3]);
-  let elm0_or_elm1 = builder.or(elm0, elm1);
-  let elm2_or_elm3 = builder.or(elm2, elm3);
-  builder.or(elm0_or_elm1, elm2_or_elm3)
+  builder.is_equal_hash(first, second)
 }
 
 pub fn or_list(
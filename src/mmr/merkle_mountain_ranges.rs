@@ -2,6 +2,12 @@ use itertools::Itertools;
 use num::{PrimInt, ToPrimitive};
 use plonky2::{hash::{hash_types::HashOut, poseidon::PoseidonHash}, plonk::config::Hasher};
 use plonky2_field::goldilocks_field::GoldilocksField;
+use rayon::prelude::*;
+
+use crate::leaf::Leaf;
+
+// Below this amount of leaves a perfect subtree is hashed on the current thread
+const PARALLEL_SUBTREE_THRESHOLD: usize = 1 << 10;
 
 // Merkle Mountain Ranges see introduction here: https://github.com/opentimestamps/opentimestamps-server/blob/master/doc/merkle-mountain-range.md
 #[derive(Clone)]
@@ -87,13 +93,23 @@ impl MMR {
 
   // Adds a leaf to the MMR and any further nodes that might be necessary
   pub fn add_leaf(&mut self, leaf: GoldilocksField) {
+    self.add_leaf_hash(PoseidonHash::hash_or_noop(&[leaf]));
+  }
+
+  // Adds a structured record to the MMR. The leaf is the hash of all field elements of the record
+  pub fn add_record<L: Leaf>(&mut self, record: &L) {
+    self.add_leaf_hash(record.hash());
+  }
+
+  // Adds a leaf that is already hashed, such as an identity commitment
+  pub fn add_leaf_hash(&mut self, leaf_hash: HashOut<GoldilocksField>) {
     if self.elements.is_empty() {
-      self.elements.push(PoseidonHash::hash_or_noop(&[leaf]));
+      self.elements.push(leaf_hash);
       return;
     }
 
     // Add leaf
-    let mut next_hash = PoseidonHash::hash_or_noop(&[leaf]);
+    let mut next_hash = leaf_hash;
 
     // Add new peaks as long as needed:
     //   Reading from right to left; add a new peak if there was a peak at the position
@@ -119,6 +135,64 @@ impl MMR {
     }
   }
 
+  // Builds an MMR from a batch of leaves of any kind. The result is identical to calling add_record for every leaf,
+  //   which for field elements is the same as add_leaf
+  pub fn from_leaves<L: Leaf + Sync>(leaves: &[L]) -> Self {
+    let mut mmr = MMR::new();
+    mmr.extend(leaves);
+    mmr
+  }
+
+  // Appends a batch of leaves to the MMR
+  // The batch is split into perfect subtrees that line up with the existing leaves. These subtrees are hashed in parallel
+  //   and appended afterwards, merging with the existing peaks in the same way add_leaf does.
+  // Since the elements of a perfect subtree are stored in post-order, the resulting layout equals the sequential one.
+  pub fn extend<L: Leaf + Sync>(&mut self, leaves: &[L]) {
+    // The bitmap of peak heights equals the number of leaves in the MMR
+    let nr_leaves = get_heights_bitmap_for_mmr_size(self.elements.len()).0 as usize;
+
+    // 1. Split the batch into perfect subtrees
+    // A subtree of 2^h leaves can only be appended if the number of leaves so far is a multiple of 2^h
+    let mut chunks: Vec<(u32, &[L])> = Vec::new();
+    let mut leaf_count = nr_leaves;
+    let mut rest = leaves;
+    while !rest.is_empty() {
+      let max_height = if leaf_count == 0 { usize::BITS - 1 } else { leaf_count.trailing_zeros() };
+      let height = max_height.min(rest.len().ilog2());
+      let (chunk, remaining) = rest.split_at(1 << height);
+      chunks.push((height, chunk));
+      leaf_count += chunk.len();
+      rest = remaining;
+    }
+
+    // 2. Hash all subtrees in parallel
+    let subtrees: Vec<Vec<HashOut<GoldilocksField>>> = chunks.par_iter()
+      .map(|(height, chunk)| {
+        let mut nodes = vec![HashOut::ZERO; (1 << (height + 1)) - 1];
+        fill_subtree(chunk, &mut nodes);
+        nodes
+      })
+      .collect();
+
+    // 3. Append the subtrees and merge them with the peaks on their left, like add_leaf
+    let mut leaf_count = nr_leaves;
+    self.elements.reserve(subtrees.iter().map(|nodes| nodes.len()).sum());
+    for ((height, chunk), nodes) in chunks.into_iter().zip(subtrees) {
+      self.elements.extend(nodes);
+      let mut next_hash = *self.elements.last().unwrap();
+      let mut h = height;
+      // There is a peak on the left at the same height if that bit is set in the number of leaves
+      while (leaf_count >> h) & 1 == 1 {
+        let current_pos = self.elements.len() - 1;
+        let prev_peak = self.elements[current_pos - ((1 << (h + 1)) - 1)];
+        next_hash = PoseidonHash::two_to_one(prev_peak, next_hash);
+        self.elements.push(next_hash);
+        h += 1;
+      }
+      leaf_count += chunk.len();
+    }
+  }
+
   pub fn bagging_the_peaks(self) -> HashOut<GoldilocksField> {
     let peaks = self.get_peaks();
     let peaks_elm: Vec<GoldilocksField> = peaks.iter().flat_map(|h| h.elements).collect_vec();
@@ -224,13 +298,36 @@ impl MMR {
 }
 
 impl MMR_proof {
+  // Returns the index in peaks of the peak of the subtree the leaf is in
+  // The peaks are ordered by decreasing height, and the height of the subtree is the length of the Merkle proof
+  pub fn peak_index(&self) -> usize {
+    let (heights, _) = get_heights_bitmap_for_mmr_size(self.mmr_size);
+    (heights >> (self.merkle_proof.len() + 1)).count_ones() as usize
+  }
+
   // Returns whether the proof verifies for the given leaf and root
   // Checks:
   // - Merkle proof for leaf checks out
-  // - the root of subtree is among peaks
+  // - the root of subtree is the peak at the height given by the length of the Merkle proof
   // - hashing all roots together should give the root
   pub fn verify(self, leaf: GoldilocksField, root: HashOut<GoldilocksField>) -> bool {
-    let leaf_hash = PoseidonHash::hash_or_noop(&[leaf]);
+    self.verify_leaf_hash(PoseidonHash::hash_or_noop(&[leaf]), root)
+  }
+
+  // Same as verify, for a structured record that was added with MMR::add_record
+  pub fn verify_record<L: Leaf>(self, record: &L, root: HashOut<GoldilocksField>) -> bool {
+    self.verify_leaf_hash(record.hash(), root)
+  }
+
+  fn verify_leaf_hash(self, leaf_hash: HashOut<GoldilocksField>, root: HashOut<GoldilocksField>) -> bool {
+    // 0. The length of the Merkle proof is the height of the subtree, so there must be a peak at that height.
+    //   Otherwise a shorter proof from an internal node could end at a peak
+    let (heights, _) = get_heights_bitmap_for_mmr_size(self.mmr_size);
+    if self.peaks.len() != heights.count_ones() as usize || (heights >> self.merkle_proof.len()) & 1 == 0 {
+      return false;
+    }
+    let peak_index = self.peak_index();
+
     // 1. Check Merkle proof of subtree
     let mut next_hash = leaf_hash;
     for (sibling, sibling_on_left) in self.merkle_proof {
@@ -241,8 +338,10 @@ impl MMR_proof {
       }
     }
 
-    // Check this hash is among the peaks
-    assert!(self.peaks.contains(&next_hash));
+    // Check this hash is the peak of the subtree
+    if self.peaks[peak_index] != next_hash {
+      return false;
+    }
 
     // 2. Hash all peaks together
     let peaks_elm: Vec<GoldilocksField> = self.peaks.iter().flat_map(|p| p.elements).collect_vec();
@@ -252,6 +351,27 @@ impl MMR_proof {
   }
 }
 
+// Writes the elements of the perfect Merkle tree over the given leaves into nodes, in the order they appear in an MMR:
+//  left subtree, right subtree, root. Returns the root
+// nodes must have length 2*leaves.len()-1 and leaves.len() must be a power of 2
+fn fill_subtree<L: Leaf + Sync>(leaves: &[L], nodes: &mut [HashOut<GoldilocksField>]) -> HashOut<GoldilocksField> {
+  if leaves.len() == 1 {
+    nodes[0] = leaves[0].hash();
+    return nodes[0];
+  }
+  let half = leaves.len() / 2;
+  let (left_leaves, right_leaves) = leaves.split_at(half);
+  let (left_nodes, rest) = nodes.split_at_mut(2 * half - 1);
+  let (right_nodes, root) = rest.split_at_mut(2 * half - 1);
+  let (left, right) = if leaves.len() >= PARALLEL_SUBTREE_THRESHOLD {
+    rayon::join(|| fill_subtree(left_leaves, left_nodes), || fill_subtree(right_leaves, right_nodes))
+  } else {
+    (fill_subtree(left_leaves, left_nodes), fill_subtree(right_leaves, right_nodes))
+  };
+  root[0] = PoseidonHash::two_to_one(left, right);
+  root[0]
+}
+
 // Returns the "MMR index" of the given "normal index"
 //  For example, the 4th leaf would have "normal index" 5, and mmr index 8
 pub fn get_mmr_index(leaf_normal_index: usize) -> usize {
@@ -271,6 +391,7 @@ let mut index = leaf_normal_index;
 
 #[cfg(test)]
 mod tests {
+  use plonky2::{hash::poseidon::PoseidonHash, plonk::config::Hasher};
   use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
   use rand::Rng;
   use crate::mmr::{merkle_mountain_ranges::{MMR, get_heights_bitmap_for_mmr_size, get_mmr_index}, common::GOLDILOCKS_FIELD_ORDER};
@@ -372,4 +493,70 @@ mod tests {
     println!("{}", verified);
     
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_from_leaves_matches_add_leaf() {
+    let mut rng = rand::thread_rng();
+    for nr_leaves in 0..70 {
+      let leaves: Vec<GoldilocksField> = (0..nr_leaves)
+        .map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER)))
+        .collect();
+      let mut mmr = MMR::new();
+      for leaf in &leaves {
+        mmr.add_leaf(*leaf);
+      }
+      assert_eq!(MMR::from_leaves(&leaves).elements, mmr.elements);
+    }
+  }
+
+  #[test]
+  fn test_extend_matches_add_leaf() {
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<GoldilocksField> = (0..3000)
+      .map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER)))
+      .collect();
+    let mut expected = MMR::new();
+    for leaf in &leaves {
+      expected.add_leaf(*leaf);
+    }
+
+    // Extend in batches of varying sizes, so batches start at all kinds of positions in the MMR
+    for batch_size in [1, 3, 8, 13, 64, 1000, 2049] {
+      let mut mmr = MMR::new();
+      for batch in leaves.chunks(batch_size) {
+        mmr.extend(batch);
+      }
+      assert_eq!(mmr.elements, expected.elements);
+    }
+  }
+
+  #[test]
+  fn test_peak_index() {
+    for nr_leaves in 1..40 {
+      let leaves: Vec<GoldilocksField> = (0..nr_leaves).map(GoldilocksField::from_canonical_u64).collect();
+      let mmr = MMR::from_leaves(&leaves);
+      for leaf_index in 0..nr_leaves as usize {
+        let proof = mmr.clone().get_proof_normal_index(leaf_index);
+        let mut subtree_root = PoseidonHash::hash_or_noop(&[leaves[leaf_index]]);
+        for (sibling, sibling_on_left) in proof.merkle_proof.iter() {
+          subtree_root = if *sibling_on_left { PoseidonHash::two_to_one(*sibling, subtree_root) } else { PoseidonHash::two_to_one(subtree_root, *sibling) };
+        }
+        assert_eq!(proof.peaks[proof.peak_index()], subtree_root);
+      }
+    }
+  }
+
+  #[test]
+  fn test_proof_from_internal_node_is_rejected() {
+    let leaves: Vec<GoldilocksField> = (0..4).map(GoldilocksField::from_canonical_u64).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    let root = mmr.clone().bagging_the_peaks();
+    let proof = mmr.clone().get_proof_normal_index(0);
+    assert!(proof.clone().verify(leaves[0], root));
+
+    // The parent of the first 2 leaves with the rest of the path ends at the peak, but is too short for a leaf
+    let mut shortened = proof;
+    shortened.merkle_proof.remove(0);
+    assert!(!shortened.verify_leaf_hash(mmr.elements[2], root));
+  }
+}
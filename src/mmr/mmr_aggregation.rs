@@ -0,0 +1,485 @@
+use anyhow::{ensure, Result};
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::{CircuitData, VerifierCircuitTarget}, circuit_builder::CircuitBuilder, proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget}}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}, util::log2_ceil};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::{Field, PrimeField64}};
+
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, mmr::merkle_mountain_ranges::MMR_proof, simple_merkle_tree::simple_merkle_tree::{MerkleTree, PaddingPolicy}};
+
+/**
+ * Aggregation of many MMR membership proofs into a single proof.
+ *
+ * Proofs of verify_mmr_proof_circuit can't be combined directly: the shape of that circuit depends on the position of
+ * the leaf and the size of the MMR, and the leaf stays private. So every membership is first proven in a uniform base
+ * circuit, for MMRs with at most max_peaks peaks: the Merkle proof is padded to max_peaks - 1 levels with enable bits,
+ * and the root is bagged for every possible number of peaks, of which the actual one is selected. The top of the path
+ * is checked against the peak at a witnessed index, which must be below the number of peaks.
+ *
+ * Base proofs are combined pairwise in a binary tree of aggregation circuits. An aggregation circuit verifies two
+ * proofs of the level below against fixed verifier data and hashes their commitments together, so the final proof has
+ * the same size and verification cost for any number of proofs. With an odd number of proofs at a level, the last one
+ * is paired with itself, and counted once.
+ *
+ * Public inputs of every proof: commitment to the leaves (4), root or commitment to the roots (4), number of leaves (1)
+ * For a base proof these are the hash of the leaf, the root and 1. The commitments equal the root of a MerkleTree with
+ * PaddingPolicy::DuplicateLast over the leaves (roots) in the order of the proofs, see leaves_commitment and roots_commitment.
+ * That padding makes [a, b, c] and [a, b, c, c] give the same commitment, so a verifier also checks the number of leaves.
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RootMode {
+  // All proofs are against the same root, which is a public input of the aggregated proof
+  Single,
+  // Proofs can be against different roots; the aggregated proof commits to the list of roots
+  Listed
+}
+
+// Public inputs of a membership or aggregated proof
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AggregationPublicInputs {
+  pub leaves_commitment: HashOut<GoldilocksField>,
+  // The root with RootMode::Single, the commitment to the roots with RootMode::Listed
+  pub root: HashOut<GoldilocksField>,
+  // Number of proofs that were aggregated
+  pub nr_leaves: usize
+}
+
+impl AggregationPublicInputs {
+  pub fn from_proof(proof: &ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> Self {
+    AggregationPublicInputs {
+      leaves_commitment: HashOut::from_partial(&proof.public_inputs[0..4]),
+      root: HashOut::from_partial(&proof.public_inputs[4..8]),
+      nr_leaves: proof.public_inputs[8].to_canonical_u64() as usize
+    }
+  }
+
+  // Returns whether these public inputs are of an aggregated proof of exactly the given leaves, in order
+  pub fn commits_to_leaves(&self, leaves: &[GoldilocksField]) -> bool {
+    self.nr_leaves == leaves.len() && self.leaves_commitment == leaves_commitment(leaves)
+  }
+}
+
+// Returns the commitment to the proven leaves, in the order of the aggregated proofs
+pub fn leaves_commitment(leaves: &[GoldilocksField]) -> HashOut<GoldilocksField> {
+  MerkleTree::build_with_padding(leaves.to_vec(), PaddingPolicy::DuplicateLast).root
+}
+
+// Returns the commitment to the roots of the aggregated proofs, in order, for RootMode::Listed
+pub fn roots_commitment(roots: &[HashOut<GoldilocksField>]) -> HashOut<GoldilocksField> {
+  MerkleTree::build_from_hashes_with_padding(roots.to_vec(), PaddingPolicy::DuplicateLast).root
+}
+
+// Targets of a membership proof that need to be set in the witness
+pub struct MembershipTargets {
+  pub leaf: Target,
+  pub siblings: Vec<HashOutTarget>,
+  pub siblings_on_left: Vec<BoolTarget>,
+  pub enabled: Vec<BoolTarget>,
+  pub peaks: Vec<HashOutTarget>,
+  pub nr_peaks: Target,
+  // Index of the peak the Merkle proof leads to
+  pub peak_index: Target
+}
+
+impl MembershipTargets {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, leaf: GoldilocksField, proof: &MMR_proof) {
+    assert!(proof.merkle_proof.len() <= self.siblings.len());
+    assert!(proof.peaks.len() <= self.peaks.len());
+    pw.set_target(self.leaf, leaf);
+    for i in 0..self.siblings.len() {
+      let (sibling, on_left) = proof.merkle_proof.get(i).copied().unwrap_or((HashOut::ZERO, false));
+      pw.set_hash_target(self.siblings[i], sibling);
+      pw.set_bool_target(self.siblings_on_left[i], on_left);
+      pw.set_bool_target(self.enabled[i], i < proof.merkle_proof.len());
+    }
+    for i in 0..self.peaks.len() {
+      pw.set_hash_target(self.peaks[i], proof.peaks.get(i).copied().unwrap_or(HashOut::ZERO));
+    }
+    pw.set_target(self.nr_peaks, GoldilocksField::from_canonical_usize(proof.peaks.len()));
+    pw.set_target(self.peak_index, GoldilocksField::from_canonical_usize(proof.peak_index()));
+  }
+}
+
+// Targets of an aggregation step that need to be set in the witness
+pub struct AggregationTargets {
+  pub left: ProofWithPublicInputsTarget<2>,
+  pub right: ProofWithPublicInputsTarget<2>,
+  // Set when the last proof of a level is paired with itself
+  pub duplicate: BoolTarget
+}
+
+// Returns the uniform circuit that proves a leaf is in an MMR with at most max_peaks peaks
+// Public inputs are the hash of the leaf, followed by the root and the number of leaves, which is 1
+// The circuit is built with the CircuitConfig of profile
+pub fn mmr_membership_circuit(max_peaks: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MembershipTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+  assert!(max_peaks > 0);
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  // The highest peak of an MMR with max_peaks peaks has height max_peaks - 1
+  let targets = MembershipTargets {
+    leaf: builder.add_virtual_target(),
+    siblings: builder.add_virtual_hashes(max_peaks - 1),
+    siblings_on_left: (0..max_peaks - 1).map(|_| builder.add_virtual_bool_target_safe()).collect(),
+    enabled: (0..max_peaks - 1).map(|_| builder.add_virtual_bool_target_safe()).collect(),
+    peaks: builder.add_virtual_hashes(max_peaks),
+    nr_peaks: builder.add_virtual_target(),
+    peak_index: builder.add_virtual_target()
+  };
+
+  // 1. Hash up to the top of the subtree, skipping the disabled levels at the end
+  let leaf_hash = builder.hash_or_noop::<PoseidonHash>([targets.leaf].to_vec());
+  let mut next_hash = leaf_hash;
+  let zero = builder.zero();
+  for i in 0..max_peaks - 1 {
+    let sibling = targets.siblings[i];
+    let parent = builder.verify_merkle_path(next_hash, &[sibling], &[targets.siblings_on_left[i]]);
+    next_hash = builder.select_hash(targets.enabled[i], parent, next_hash);
+    // Only a prefix of the levels can be enabled
+    if i > 0 {
+      let previous_disabled = builder.not(targets.enabled[i - 1]);
+      let both = builder.and(targets.enabled[i], previous_disabled);
+      builder.connect(both.target, zero);
+    }
+  }
+
+  // 2. is_nr_peaks[j] tells whether there are j + 1 peaks; exactly one of them holds
+  let is_nr_peaks: Vec<BoolTarget> = (0..max_peaks).map(|j| {
+    let count = builder.constant(GoldilocksField::from_canonical_usize(j + 1));
+    builder.is_equal(targets.nr_peaks, count)
+  }).collect();
+  let sum = builder.add_many(is_nr_peaks.iter().map(|b| b.target));
+  let one = builder.one();
+  builder.connect(sum, one);
+
+  // 3. The top of the subtree must be the peak at peak_index, which has to be one of the actual peaks
+  builder.assert_hash_at_index(next_hash, &targets.peaks, targets.peak_index);
+  let last_peak_index = builder.sub(targets.nr_peaks, one);
+  let lower_peaks = builder.sub(last_peak_index, targets.peak_index);
+  builder.range_check(lower_peaks, log2_ceil(max_peaks));
+
+  // 4. Bag the peaks for every possible number of peaks and select the actual one
+  let mut root = builder.bag_peaks(&targets.peaks[0..1]);
+  for n in 2..=max_peaks {
+    let bagged = builder.bag_peaks(&targets.peaks[0..n]);
+    root = builder.select_hash(is_nr_peaks[n - 1], bagged, root);
+  }
+
+  builder.register_public_inputs(&leaf_hash.elements);
+  builder.register_public_inputs(&root.elements);
+  builder.register_public_input(one);
+  (builder.build::<C>(), targets)
+}
+
+// Returns a circuit that verifies two proofs of the inner circuit and combines their public inputs
+fn aggregation_circuit(
+  inner: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+  root_mode: RootMode,
+  profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, AggregationTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let targets = AggregationTargets {
+    left: builder.add_virtual_proof_with_pis(&inner.common),
+    right: builder.add_virtual_proof_with_pis(&inner.common),
+    duplicate: builder.add_virtual_bool_target_safe()
+  };
+  // The verifier data is a constant, so only proofs of the inner circuit are accepted
+  let verifier_data = VerifierCircuitTarget {
+    constants_sigmas_cap: builder.constant_merkle_cap(&inner.verifier_only.constants_sigmas_cap),
+    circuit_digest: builder.constant_hash(inner.verifier_only.circuit_digest)
+  };
+  builder.verify_proof::<C>(&targets.left, &verifier_data, &inner.common);
+  builder.verify_proof::<C>(&targets.right, &verifier_data, &inner.common);
+
+  let left = &targets.left.public_inputs;
+  let right = &targets.right.public_inputs;
+  let leaves_commitment = builder.hash_or_noop::<PoseidonHash>([&left[0..4], &right[0..4]].concat());
+  builder.register_public_inputs(&leaves_commitment.elements);
+  match root_mode {
+    RootMode::Single => {
+      for i in 4..8 {
+        builder.connect(left[i], right[i]);
+      }
+      builder.register_public_inputs(&left[4..8]);
+    },
+    RootMode::Listed => {
+      let roots_commitment = builder.hash_or_noop::<PoseidonHash>([&left[4..8], &right[4..8]].concat());
+      builder.register_public_inputs(&roots_commitment.elements);
+    }
+  }
+
+  // A proof paired with itself is counted once. Its public inputs must equal those of the left proof then
+  for i in 0..left.len() {
+    let difference = builder.sub(left[i], right[i]);
+    let masked = builder.mul(targets.duplicate.target, difference);
+    builder.assert_zero(masked);
+  }
+  let zero = builder.zero();
+  let right_count = builder.select(targets.duplicate, zero, right[8]);
+  let nr_leaves = builder.add(left[8], right_count);
+  builder.register_public_input(nr_leaves);
+
+  (builder.build::<C>(), targets)
+}
+
+// An aggregated proof
+pub struct AggregatedProof {
+  pub proof: ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>
+}
+
+impl AggregatedProof {
+  // Returns the number of aggregation levels the proof went through, which follows from the number of leaves in its
+  // public inputs: every level halves the number of proofs, rounding up
+  pub fn nr_levels(&self) -> usize {
+    log2_ceil(AggregationPublicInputs::from_proof(&self.proof).nr_leaves)
+  }
+}
+
+pub struct MmrProofAggregator {
+  pub max_peaks: usize,
+  pub root_mode: RootMode,
+  pub profile: ConfigProfile,
+  pub base_data: CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+  base_targets: MembershipTargets,
+  // levels[i] combines two proofs of levels[i - 1], or of the base circuit for i = 0. Built when first needed
+  levels: Vec<(CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, AggregationTargets)>
+}
+
+impl MmrProofAggregator {
+  // Aggregates proofs for MMRs with at most max_peaks peaks, which holds for fewer than 2^max_peaks leaves
+  // The base and aggregation circuits are built with the CircuitConfig of profile
+  pub fn new(max_peaks: usize, root_mode: RootMode, profile: &ConfigProfile) -> Self {
+    let (base_data, base_targets) = mmr_membership_circuit(max_peaks, profile);
+    MmrProofAggregator {
+      max_peaks: max_peaks,
+      root_mode: root_mode,
+      profile: profile.clone(),
+      base_data: base_data,
+      base_targets: base_targets,
+      levels: Vec::new()
+    }
+  }
+
+  // Proves the leaf is in the MMR of the proof, in the base circuit
+  pub fn prove_membership(&self, leaf: GoldilocksField, proof: &MMR_proof) -> Result<ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>> {
+    ensure!(proof.peaks.len() <= self.max_peaks, "the MMR has more than {} peaks", self.max_peaks);
+    let mut pw = PartialWitness::new();
+    self.base_targets.set_witness(&mut pw, leaf, proof);
+    self.base_data.prove(pw)
+  }
+
+  // Combines membership proofs of prove_membership into a single proof
+  pub fn aggregate(&mut self, proofs: Vec<ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>>) -> Result<AggregatedProof> {
+    ensure!(!proofs.is_empty(), "nothing to aggregate");
+    let mut current = proofs;
+    let mut level = 0;
+    while current.len() > 1 {
+      if self.levels.len() == level {
+        let inner = if level == 0 { &self.base_data } else { &self.levels[level - 1].0 };
+        let circuit = aggregation_circuit(inner, self.root_mode, &self.profile);
+        self.levels.push(circuit);
+      }
+      let (circuit_data, targets) = &self.levels[level];
+      current = current.chunks(2).map(|pair| {
+        let mut pw = PartialWitness::new();
+        pw.set_proof_with_pis_target(&targets.left, &pair[0]);
+        pw.set_proof_with_pis_target(&targets.right, pair.last().unwrap());
+        pw.set_bool_target(targets.duplicate, pair.len() == 1);
+        circuit_data.prove(pw)
+      }).collect::<Result<Vec<_>>>()?;
+      level += 1;
+    }
+    Ok(AggregatedProof { proof: current.pop().unwrap() })
+  }
+
+  // Verifies the proof with the circuit of the level that its number of leaves implies
+  // A proof of another level, or with a different number of leaves, is rejected
+  pub fn verify(&self, proof: &AggregatedProof) -> Result<()> {
+    let nr_levels = proof.nr_levels();
+    ensure!(nr_levels <= self.levels.len(), "unknown aggregation level");
+    let circuit_data = if nr_levels == 0 { &self.base_data } else { &self.levels[nr_levels - 1].0 };
+    circuit_data.verify(proof.proof.clone())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{hash::hash_types::HashOut, iop::witness::{PartialWitness, WitnessWrite}};
+  use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::MMR}};
+  use super::{AggregatedProof, MmrProofAggregator, RootMode, AggregationPublicInputs, leaves_commitment, roots_commitment};
+
+  fn random_leaves(nr_leaves: usize) -> Vec<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    (0..nr_leaves).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect()
+  }
+
+  #[test]
+  fn test_aggregate_same_root() -> Result<()> {
+    let leaves = random_leaves(21);
+    let mmr = MMR::from_leaves(&leaves);
+    let root = mmr.clone().bagging_the_peaks();
+    let mut aggregator = MmrProofAggregator::new(5, RootMode::Single, &ConfigProfile::default());
+
+    // 5 proofs, of leaves at different heights in the MMR
+    let indices = [0, 7, 16, 19, 20];
+    let proofs = indices.iter()
+      .map(|i| aggregator.prove_membership(leaves[*i], &mmr.clone().get_proof_normal_index(*i)))
+      .collect::<Result<Vec<_>>>()?;
+    let aggregated = aggregator.aggregate(proofs)?;
+    assert!(aggregated.nr_levels() == 3);
+    aggregator.verify(&aggregated)?;
+
+    let public_inputs = AggregationPublicInputs::from_proof(&aggregated.proof);
+    let proven_leaves: Vec<GoldilocksField> = indices.iter().map(|i| leaves[*i]).collect();
+    assert!(public_inputs.leaves_commitment == leaves_commitment(&proven_leaves));
+    assert!(public_inputs.nr_leaves == 5);
+    assert!(public_inputs.commits_to_leaves(&proven_leaves));
+    assert!(public_inputs.root == root);
+    Ok(())
+  }
+
+  #[test]
+  fn test_aggregate_listed_roots() -> Result<()> {
+    let leaves = random_leaves(12);
+    let mut aggregator = MmrProofAggregator::new(4, RootMode::Listed, &ConfigProfile::default());
+
+    // Proofs against the MMR after 7 leaves (3 peaks) and after 12 leaves (2 peaks)
+    let mut mmr = MMR::from_leaves(&leaves[0..7]);
+    let first_root = mmr.clone().bagging_the_peaks();
+    let first_proof = aggregator.prove_membership(leaves[2], &mmr.clone().get_proof_normal_index(2))?;
+    mmr.extend(&leaves[7..12]);
+    let second_root = mmr.clone().bagging_the_peaks();
+    let second_proof = aggregator.prove_membership(leaves[10], &mmr.clone().get_proof_normal_index(10))?;
+
+    let aggregated = aggregator.aggregate([first_proof, second_proof].to_vec())?;
+    aggregator.verify(&aggregated)?;
+    let public_inputs = AggregationPublicInputs::from_proof(&aggregated.proof);
+    assert!(public_inputs.leaves_commitment == leaves_commitment(&[leaves[2], leaves[10]]));
+    assert!(public_inputs.root == roots_commitment(&[first_root, second_root]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_count_tells_duplicate_last_apart() -> Result<()> {
+    let leaves = random_leaves(9);
+    let mmr = MMR::from_leaves(&leaves);
+    let mut aggregator = MmrProofAggregator::new(4, RootMode::Single, &ConfigProfile::default());
+    let proofs = [1, 4, 8].iter()
+      .map(|i| aggregator.prove_membership(leaves[*i], &mmr.clone().get_proof_normal_index(*i)))
+      .collect::<Result<Vec<_>>>()?;
+
+    // Padding the odd level gives the same commitment as repeating the last leaf, but a different count
+    let three = AggregationPublicInputs::from_proof(&aggregator.aggregate(proofs.clone())?.proof);
+    let four = AggregationPublicInputs::from_proof(&aggregator.aggregate([proofs.clone(), [proofs[2].clone()].to_vec()].concat())?.proof);
+    assert!(three.leaves_commitment == four.leaves_commitment);
+    assert!(three.nr_leaves == 3 && four.nr_leaves == 4);
+    assert!(three.commits_to_leaves(&[leaves[1], leaves[4], leaves[8]]));
+    assert!(!three.commits_to_leaves(&[leaves[1], leaves[4], leaves[8], leaves[8]]));
+    assert!(four.commits_to_leaves(&[leaves[1], leaves[4], leaves[8], leaves[8]]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_single_proof() -> Result<()> {
+    let leaves = random_leaves(6);
+    let mmr = MMR::from_leaves(&leaves);
+    let mut aggregator = MmrProofAggregator::new(3, RootMode::Single, &ConfigProfile::default());
+    let proof = aggregator.prove_membership(leaves[4], &mmr.clone().get_proof_normal_index(4))?;
+    let aggregated = aggregator.aggregate([proof].to_vec())?;
+    assert!(aggregated.nr_levels() == 0);
+    aggregator.verify(&aggregated)?;
+    assert!(AggregationPublicInputs::from_proof(&aggregated.proof).root == mmr.bagging_the_peaks());
+    Ok(())
+  }
+
+  #[test]
+  fn test_zero_knowledge() -> Result<()> {
+    let leaves = random_leaves(7);
+    let mmr = MMR::from_leaves(&leaves);
+    let mut aggregator = MmrProofAggregator::new(3, RootMode::Single, &ConfigProfile::ZeroKnowledge);
+    let proofs = [0, 6].iter()
+      .map(|i| aggregator.prove_membership(leaves[*i], &mmr.clone().get_proof_normal_index(*i)))
+      .collect::<Result<Vec<_>>>()?;
+    let aggregated = aggregator.aggregate(proofs)?;
+    assert!(aggregator.levels[0].0.common.config.zero_knowledge);
+    aggregator.verify(&aggregated)?;
+    let public_inputs = AggregationPublicInputs::from_proof(&aggregated.proof);
+    assert!(public_inputs.commits_to_leaves(&[leaves[0], leaves[6]]));
+    assert!(public_inputs.root == mmr.bagging_the_peaks());
+    Ok(())
+  }
+
+  #[test]
+  fn test_forged_nr_leaves() -> Result<()> {
+    let leaves = random_leaves(4);
+    let mmr = MMR::from_leaves(&leaves);
+    let mut aggregator = MmrProofAggregator::new(3, RootMode::Single, &ConfigProfile::default());
+    let proofs = [0, 3].iter()
+      .map(|i| aggregator.prove_membership(leaves[*i], &mmr.clone().get_proof_normal_index(*i)))
+      .collect::<Result<Vec<_>>>()?;
+    aggregator.aggregate(proofs.clone())?;
+
+    // A membership proof that claims 2 leaves is checked against the aggregation circuit, and fails
+    let mut forged = proofs[0].clone();
+    forged.public_inputs[8] = GoldilocksField::TWO;
+    let forged = AggregatedProof { proof: forged };
+    assert!(forged.nr_levels() == 1);
+    assert!(aggregator.verify(&forged).is_err());
+    Ok(())
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_different_roots_in_single_mode() {
+    let leaves = random_leaves(8);
+    let mut aggregator = MmrProofAggregator::new(4, RootMode::Single, &ConfigProfile::default());
+    let first_mmr = MMR::from_leaves(&leaves[0..5]);
+    let second_mmr = MMR::from_leaves(&leaves);
+    let first_proof = aggregator.prove_membership(leaves[1], &first_mmr.get_proof_normal_index(1)).unwrap();
+    let second_proof = aggregator.prove_membership(leaves[1], &second_mmr.get_proof_normal_index(1)).unwrap();
+    aggregator.aggregate([first_proof, second_proof].to_vec()).unwrap();
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_wrong_leaf() {
+    let leaves = random_leaves(8);
+    let mmr = MMR::from_leaves(&leaves);
+    let aggregator = MmrProofAggregator::new(4, RootMode::Single, &ConfigProfile::default());
+    aggregator.prove_membership(leaves[2], &mmr.get_proof_normal_index(3)).unwrap();
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_peak_beyond_nr_peaks() {
+    let leaves = random_leaves(7);
+    let mmr = MMR::from_leaves(&leaves);
+    let proof = mmr.get_proof_normal_index(6);
+    let aggregator = MmrProofAggregator::new(3, RootMode::Single, &ConfigProfile::default());
+
+    // The leaf is the last of 3 peaks, but the witness only counts the first 2, which bag to another root
+    let targets = &aggregator.base_targets;
+    let mut pw = PartialWitness::new();
+    pw.set_target(targets.leaf, leaves[6]);
+    for i in 0..2 {
+      pw.set_hash_target(targets.siblings[i], HashOut::ZERO);
+      pw.set_bool_target(targets.siblings_on_left[i], false);
+      pw.set_bool_target(targets.enabled[i], false);
+    }
+    for i in 0..3 {
+      pw.set_hash_target(targets.peaks[i], proof.peaks[i]);
+    }
+    pw.set_target(targets.nr_peaks, GoldilocksField::TWO);
+    pw.set_target(targets.peak_index, GoldilocksField::TWO);
+    aggregator.base_data.prove(pw).unwrap();
+  }
+}
@@ -0,0 +1,273 @@
+use itertools::Itertools;
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}, hashing::SPONGE_WIDTH}, plonk::circuit_builder::CircuitBuilder, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::mmr::merkle_mountain_ranges::get_heights_bitmap_for_mmr_size;
+
+/**
+ * In-circuit version of MMR::add_leaf.
+ *
+ * The peaks are kept in fixed slots, one per height up to max_peaks - 1; the slot of height h holds a peak if bit h
+ * of the peaks bitmap of get_heights_bitmap_for_mmr_size is set, and zero otherwise. The bitmap is witnessed and
+ * checked against the mmr_size: a peak of height h covers 2^(h+1) - 1 elements.
+ *
+ * Appending is the carry logic of add_leaf: the new leaf merges with the peaks of height 0, 1, ... as long as they
+ * exist, every merge adds an element to the MMR, and the result goes into the first empty slot.
+ */
+
+// Returns the peak slots of an MMR with the given peaks bitmap and peaks, as returned by MMR::get_peaks
+pub fn peaks_to_slots(peaks_bitmap: usize, peaks: &[HashOut<GoldilocksField>], max_peaks: usize) -> Vec<HashOut<GoldilocksField>> {
+  assert!(peaks_bitmap < 1 << max_peaks);
+  assert!(peaks.len() == peaks_bitmap.count_ones() as usize);
+  let mut slots = [HashOut::ZERO].repeat(max_peaks);
+  // Peaks go from the highest to the lowest
+  let mut peaks_iter = peaks.iter();
+  for height in (0..max_peaks).rev() {
+    if (peaks_bitmap >> height) & 1 == 1 {
+      slots[height] = *peaks_iter.next().unwrap();
+    }
+  }
+  slots
+}
+
+// Returns the peaks in the order of MMR::get_peaks
+pub fn slots_to_peaks(peaks_bitmap: usize, slots: &[HashOut<GoldilocksField>]) -> Vec<HashOut<GoldilocksField>> {
+  (0..slots.len()).rev().filter(|height| (peaks_bitmap >> height) & 1 == 1).map(|height| slots[height]).collect()
+}
+
+// Appends a leaf to the peak slots natively, returning the new slots
+pub fn append_to_slots(peaks_bitmap: usize, slots: &[HashOut<GoldilocksField>], leaf: GoldilocksField) -> Vec<HashOut<GoldilocksField>> {
+  let mut slots = slots.to_vec();
+  let mut next_hash = PoseidonHash::hash_or_noop(&[leaf]);
+  let mut height = 0;
+  while (peaks_bitmap >> height) & 1 == 1 {
+    next_hash = PoseidonHash::two_to_one(slots[height], next_hash);
+    slots[height] = HashOut::ZERO;
+    height += 1;
+  }
+  slots[height] = next_hash;
+  slots
+}
+
+// Appends the leaf to the slots if enabled, where occupied[h] tells whether slot h holds a peak
+// Updates slots and occupied in place, and returns the number of elements that were added to the MMR
+pub fn append_leaf_circuit(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  slots: &mut [HashOutTarget],
+  occupied: &mut [BoolTarget],
+  leaf_hash: HashOutTarget,
+  enabled: BoolTarget) -> Target {
+  let zero_hash = HashOutTarget::from_vec([builder.zero(); 4].to_vec());
+  let mut next_hash = leaf_hash;
+  // The leaf itself, and a parent for every merge
+  let mut nr_added = enabled.target;
+  // The carry is set as long as the new peak still has to be merged or placed
+  let mut carry = enabled;
+  for height in 0..slots.len() {
+    let merge = builder.and(carry, occupied[height]);
+    let not_occupied = builder.not(occupied[height]);
+    let place = builder.and(carry, not_occupied);
+
+    let merged = builder.hash_or_noop::<PoseidonHash>([slots[height].elements.to_vec(), next_hash.elements.to_vec()].concat());
+    let placed_or_kept = builder.select_hash(place, next_hash, slots[height]);
+    slots[height] = builder.select_hash(merge, zero_hash, placed_or_kept);
+    next_hash = builder.select_hash(merge, merged, next_hash);
+
+    // Merging empties the slot, placing fills it; both can't happen
+    let filled = builder.add(occupied[height].target, place.target);
+    occupied[height] = BoolTarget::new_unsafe(builder.sub(filled, merge.target));
+    nr_added = builder.add(nr_added, merge.target);
+    carry = merge;
+  }
+  // The MMR is full when the carry gets past the highest slot
+  let zero = builder.zero();
+  builder.connect(carry.target, zero);
+  nr_added
+}
+
+// Returns the bagged root of the peaks in the slots, as MMR::bagging_the_peaks
+// The bag is a sponge over the peaks from the highest to the lowest, so the slots are absorbed in a single pass from
+// the highest height down: a peak fills the first or the second half of the rate, and the state is permuted once both
+// halves are filled. Empty slots are skipped, whatever value they hold
+pub fn bag_slots_circuit(builder: &mut CircuitBuilder<GoldilocksField, 2>, slots: &[HashOutTarget], occupied: &[BoolTarget]) -> HashOutTarget {
+  let mut state = [builder.zero(); SPONGE_WIDTH];
+  // Whether the first half of the rate holds a peak that hasn't been permuted yet
+  let mut half_full = builder._false();
+  // Whether the state has been permuted, i.e. there are at least two peaks
+  let mut permuted = builder._false();
+  for height in (0..slots.len()).rev() {
+    let mut first_half = state;
+    first_half[0..4].copy_from_slice(&slots[height].elements);
+    let mut second_half = state;
+    second_half[4..8].copy_from_slice(&slots[height].elements);
+    let second_half = builder.permute::<PoseidonHash>(second_half);
+
+    let not_half_full = builder.not(half_full);
+    let fill_first_half = builder.and(occupied[height], not_half_full);
+    let fill_second_half = builder.and(occupied[height], half_full);
+    for i in 0..SPONGE_WIDTH {
+      let first = builder.select(fill_first_half, first_half[i], state[i]);
+      state[i] = builder.select(fill_second_half, second_half[i], first);
+    }
+    // The first half stays full if it's filled now, or if it was full and the slot is empty
+    let stays_full = builder.sub(half_full.target, fill_second_half.target);
+    half_full = BoolTarget::new_unsafe(builder.add(fill_first_half.target, stays_full));
+    permuted = builder.or(permuted, fill_second_half);
+  }
+  // An odd number of peaks leaves a half-full rate to permute, unless it's a single peak, which is the root itself like
+  // in hash_or_noop. No peaks gives the zero hash
+  let last_permutation = builder.permute::<PoseidonHash>(state);
+  let permute_last = builder.and(half_full, permuted);
+  let root = (0..4).map(|i| builder.select(permute_last, last_permutation[i], state[i])).collect_vec();
+  HashOutTarget::from_vec(root)
+}
+
+// Returns the mmr_size of an MMR with the peaks in the bitmap: a peak of height h covers 2^(h+1) - 1 elements
+pub fn mmr_size_circuit(builder: &mut CircuitBuilder<GoldilocksField, 2>, peaks_bitmap: &[BoolTarget]) -> Target {
+  let mut mmr_size = builder.zero();
+  for (height, bit) in peaks_bitmap.iter().enumerate() {
+    let subtree_size = builder.constant(GoldilocksField::from_canonical_u64((1 << (height + 1)) - 1));
+    mmr_size = builder.mul_add(bit.target, subtree_size, mmr_size);
+  }
+  mmr_size
+}
+
+// Targets of an append: the inputs need to be set in the witness, the outputs are computed by the circuit
+pub struct MmrAppendTargets {
+  // Inputs. Slot h holds the peak of height h, or zero
+  pub peaks: Vec<HashOutTarget>,
+  pub mmr_size: Target,
+  pub leaf: Target,
+  peaks_bitmap: Vec<BoolTarget>,
+  // Outputs
+  pub new_peaks: Vec<HashOutTarget>,
+  pub new_mmr_size: Target,
+  pub new_root: HashOutTarget
+}
+
+impl MmrAppendTargets {
+  // peaks as returned by MMR::get_peaks for an MMR with mmr_size elements
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, peaks: &[HashOut<GoldilocksField>], mmr_size: usize, leaf: GoldilocksField) {
+    let peaks_bitmap = get_heights_bitmap_for_mmr_size(mmr_size).0 as usize;
+    let slots = peaks_to_slots(peaks_bitmap, peaks, self.peaks.len());
+    for height in 0..self.peaks.len() {
+      pw.set_hash_target(self.peaks[height], slots[height]);
+      pw.set_bool_target(self.peaks_bitmap[height], (peaks_bitmap >> height) & 1 == 1);
+    }
+    pw.set_target(self.mmr_size, GoldilocksField::from_canonical_usize(mmr_size));
+    pw.set_target(self.leaf, leaf);
+  }
+}
+
+// Adds the append of a leaf to an MMR with at most max_peaks peaks, so fewer than 2^max_peaks leaves, to the builder
+pub fn add_mmr_append(builder: &mut CircuitBuilder<GoldilocksField, 2>, max_peaks: usize) -> MmrAppendTargets {
+  let peaks = builder.add_virtual_hashes(max_peaks);
+  let mmr_size = builder.add_virtual_target();
+  let leaf = builder.add_virtual_target();
+  let peaks_bitmap: Vec<BoolTarget> = (0..max_peaks).map(|_| builder.add_virtual_bool_target_safe()).collect();
+
+  // The bitmap must be the one of the mmr_size
+  let expected_mmr_size = mmr_size_circuit(builder, &peaks_bitmap);
+  builder.connect(expected_mmr_size, mmr_size);
+
+  let mut new_peaks = peaks.clone();
+  let mut occupied = peaks_bitmap.clone();
+  let leaf_hash = builder.hash_or_noop::<PoseidonHash>([leaf].to_vec());
+  let enabled = builder._true();
+  let nr_added = append_leaf_circuit(builder, &mut new_peaks, &mut occupied, leaf_hash, enabled);
+  let new_mmr_size = builder.add(mmr_size, nr_added);
+  let new_root = bag_slots_circuit(builder, &new_peaks, &occupied);
+
+  MmrAppendTargets {
+    peaks: peaks,
+    mmr_size: mmr_size,
+    leaf: leaf,
+    peaks_bitmap: peaks_bitmap,
+    new_peaks: new_peaks,
+    new_mmr_size: new_mmr_size,
+    new_root: new_root
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, iop::{generator::generate_partial_witness, witness::{PartialWitness, Witness, WitnessWrite}}, plonk::{circuit_builder::CircuitBuilder, circuit_data::CircuitConfig, config::PoseidonGoldilocksConfig}};
+  use rand::Rng;
+
+  use crate::mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::{MMR, get_heights_bitmap_for_mmr_size}};
+  use super::{add_mmr_append, peaks_to_slots, slots_to_peaks};
+
+  fn random_element() -> GoldilocksField {
+    let mut rng = rand::thread_rng();
+    GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))
+  }
+
+  #[test]
+  fn test_every_size_up_to_2_12() {
+    // Fewer than 2^13 leaves
+    let max_peaks = 13;
+    let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+    let targets = add_mmr_append(&mut builder, max_peaks);
+    let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+
+    // Generating the witness runs all computations of the circuit and checks the values of connected targets
+    let mut mmr = MMR::new();
+    for _ in 0..=(1 << 12) {
+      let peaks = if mmr.elements.is_empty() { Vec::new() } else { mmr.clone().get_peaks() };
+      let mmr_size = mmr.elements.len();
+      let leaf = random_element();
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, &peaks, mmr_size, leaf);
+      let witness = generate_partial_witness(pw, &circuit_data.prover_only, &circuit_data.common);
+
+      mmr.add_leaf(leaf);
+      let new_mmr_size = witness.get_target(targets.new_mmr_size);
+      assert!(new_mmr_size == GoldilocksField::from_canonical_usize(mmr.elements.len()));
+      let new_slots: Vec<_> = targets.new_peaks.iter().map(|peak| witness.get_hash_target(*peak)).collect();
+      let new_bitmap = get_heights_bitmap_for_mmr_size(mmr.elements.len()).0 as usize;
+      assert!(slots_to_peaks(new_bitmap, &new_slots) == mmr.clone().get_peaks());
+      assert!(witness.get_hash_target(targets.new_root) == mmr.clone().bagging_the_peaks());
+    }
+  }
+
+  #[test]
+  fn test_prove_append() -> Result<()> {
+    let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+    let targets = add_mmr_append(&mut builder, 8);
+    builder.register_public_input(targets.new_mmr_size);
+    builder.register_public_inputs(&targets.new_root.elements);
+    let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+
+    let mut mmr = MMR::from_leaves(&(0..22).map(|_| random_element()).collect::<Vec<_>>());
+    let mut pw = PartialWitness::new();
+    let leaf = random_element();
+    targets.set_witness(&mut pw, &mmr.clone().get_peaks(), mmr.elements.len(), leaf);
+    let proof = circuit_data.prove(pw)?;
+
+    mmr.add_leaf(leaf);
+    assert!(proof.public_inputs[0] == GoldilocksField::from_canonical_usize(mmr.elements.len()));
+    assert!(proof.public_inputs[1..5] == mmr.bagging_the_peaks().elements);
+    circuit_data.verify(proof)
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_wrong_mmr_size() {
+    let mut builder = CircuitBuilder::<GoldilocksField, 2>::new(CircuitConfig::standard_recursion_config());
+    let targets = add_mmr_append(&mut builder, 8);
+    let circuit_data = builder.build::<PoseidonGoldilocksConfig>();
+
+    let mmr = MMR::from_leaves(&(0..6).map(|_| random_element()).collect::<Vec<_>>());
+    let slots = peaks_to_slots(6, &mmr.clone().get_peaks(), 8);
+    let mut pw = PartialWitness::new();
+    // The peaks bitmap of 6 leaves, but the size of an MMR with 7 leaves
+    for height in 0..8 {
+      pw.set_hash_target(targets.peaks[height], slots[height]);
+      pw.set_bool_target(targets.peaks_bitmap[height], (6 >> height) & 1 == 1);
+    }
+    pw.set_target(targets.mmr_size, GoldilocksField::from_canonical_usize(11));
+    pw.set_target(targets.leaf, random_element());
+    circuit_data.prove(pw).unwrap();
+  }
+}
@@ -0,0 +1,289 @@
+use anyhow::{ensure, Result};
+use itertools::Itertools;
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig, Hasher}, circuit_data::{CircuitData, CommonCircuitData, VerifierCircuitTarget}, circuit_builder::CircuitBuilder, proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget}}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}, recursion::{cyclic_recursion::check_cyclic_proof_verifier_data, dummy_circuit::cyclic_base_proof}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::{Field, PrimeField64}};
+
+use crate::{config_profile::ConfigProfile, mmr::mmr_append_gadget::{append_leaf_circuit, append_to_slots, bag_slots_circuit, peaks_to_slots, slots_to_peaks}, recursion::{common_data_for_recursion, fits_in_degree}};
+
+/**
+ * Incrementally verifiable computation of MMR appends: a single proof of constant size that the current root
+ * was obtained by appending leaves one by one to the empty MMR, in the same way as MMR::add_leaf.
+ *
+ * The peaks are kept in max_peaks fixed slots, one per height; the slot of height h holds a peak if bit h of the number
+ * of leaves is set, and zero otherwise. Appending a leaf is a binary increment: the new leaf merges with the peaks of
+ * height 0, 1, ... as long as they exist, and the result goes into the first empty slot.
+ *
+ * Every step appends up to leaves_per_step leaves and verifies the proof of the previous step (cyclic recursion).
+ * The peaks themselves are not public; a step gets them as witness and checks them against the commitment of the
+ * previous step. The first step starts from the empty MMR.
+ *
+ * Public inputs of every proof (before the verifier data that cyclic recursion adds):
+ *  number of leaves, root (4), commitment to the peak slots (4)
+ */
+
+// Public inputs of a proof of the MMR append prover
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MmrAppendPublicInputs {
+  pub nr_leaves: usize,
+  pub root: HashOut<GoldilocksField>,
+  pub peaks_commitment: HashOut<GoldilocksField>
+}
+
+impl MmrAppendPublicInputs {
+  pub fn from_proof(proof: &ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> Self {
+    let pis = &proof.public_inputs;
+    MmrAppendPublicInputs {
+      nr_leaves: pis[0].to_canonical_u64() as usize,
+      root: HashOut::from_partial(&pis[1..5]),
+      peaks_commitment: HashOut::from_partial(&pis[5..9])
+    }
+  }
+}
+
+// Returns the commitment to the peak slots, as in the public inputs
+pub fn peaks_commitment(slots: &[HashOut<GoldilocksField>]) -> HashOut<GoldilocksField> {
+  PoseidonHash::hash_no_pad(&slots.iter().flat_map(|slot| slot.elements).collect_vec())
+}
+
+// Targets of a step that need to be set in the witness
+struct StepTargets {
+  slots: Vec<HashOutTarget>,
+  leaves: Vec<Target>,
+  enabled: Vec<BoolTarget>,
+  // false for the first step, which starts from the empty MMR
+  has_previous: BoolTarget,
+  previous_proof: ProofWithPublicInputsTarget<2>,
+  verifier_data: VerifierCircuitTarget
+}
+
+// Adds the step circuit to a new builder. Returns the builder before it is built, since the caller needs to check its size
+fn step_circuit(leaves_per_step: usize, max_peaks: usize, mut common_data: CommonCircuitData<GoldilocksField, 2>, profile: &ConfigProfile)
+  -> Result<(CircuitBuilder<GoldilocksField, 2>, StepTargets, CommonCircuitData<GoldilocksField, 2>)> {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let zero = builder.zero();
+
+  let nr_leaves_out = builder.add_virtual_public_input();
+  let root_out = builder.add_virtual_hash();
+  builder.register_public_inputs(&root_out.elements);
+  let commitment_out = builder.add_virtual_hash();
+  builder.register_public_inputs(&commitment_out.elements);
+
+  let verifier_data = builder.add_verifier_data_public_inputs();
+  common_data.num_public_inputs = builder.num_public_inputs();
+
+  // The state of the previous step, or the empty MMR for the first step
+  let has_previous = builder.add_virtual_bool_target_safe();
+  let previous_proof = builder.add_virtual_proof_with_pis(&common_data);
+  let previous_pis = &previous_proof.public_inputs;
+  let previous_commitment = HashOutTarget::from_vec(previous_pis[5..9].to_vec());
+  let empty_commitment = builder.constant_hash(peaks_commitment(&[HashOut::ZERO].repeat(max_peaks)));
+  let commitment = builder.select_hash(has_previous, previous_commitment, empty_commitment);
+  let mut nr_leaves = builder.select(has_previous, previous_pis[0], zero);
+
+  // The witnessed peaks must be those of the previous step
+  let slots_in = builder.add_virtual_hashes(max_peaks);
+  let slots_commitment = builder.hash_n_to_hash_no_pad::<PoseidonHash>(slots_in.iter().flat_map(|slot| slot.elements).collect_vec());
+  builder.connect_hashes(slots_commitment, commitment);
+
+  // Splitting the number of leaves also checks it fits in max_peaks bits
+  let mut occupied = builder.split_le(nr_leaves, max_peaks);
+  let mut slots = slots_in.clone();
+  let leaves: Vec<Target> = builder.add_virtual_targets(leaves_per_step);
+  let enabled: Vec<BoolTarget> = (0..leaves_per_step).map(|_| builder.add_virtual_bool_target_safe()).collect();
+  for i in 0..leaves_per_step {
+    let leaf_hash = builder.hash_or_noop::<PoseidonHash>([leaves[i]].to_vec());
+    append_leaf_circuit(&mut builder, &mut slots, &mut occupied, leaf_hash, enabled[i]);
+    nr_leaves = builder.add(nr_leaves, enabled[i].target);
+  }
+
+  let root = bag_slots_circuit(&mut builder, &slots, &occupied);
+  let commitment = builder.hash_n_to_hash_no_pad::<PoseidonHash>(slots.iter().flat_map(|slot| slot.elements).collect_vec());
+  builder.connect(nr_leaves, nr_leaves_out);
+  builder.connect_hashes(root, root_out);
+  builder.connect_hashes(commitment, commitment_out);
+
+  builder.conditionally_verify_cyclic_proof_or_dummy::<C>(has_previous, &previous_proof, &common_data)?;
+
+  let targets = StepTargets {
+    slots: slots_in,
+    leaves: leaves,
+    enabled: enabled,
+    has_previous: has_previous,
+    previous_proof: previous_proof,
+    verifier_data: verifier_data
+  };
+  Ok((builder, targets, common_data))
+}
+
+pub struct MmrAppendProver {
+  pub leaves_per_step: usize,
+  pub max_peaks: usize,
+  pub circuit_data: CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+  common_data: CommonCircuitData<GoldilocksField, 2>,
+  targets: StepTargets
+}
+
+impl MmrAppendProver {
+  // Builds the step circuit that appends up to leaves_per_step leaves, for MMRs of fewer than 2^max_peaks leaves
+  // The circuit has the smallest size, starting at 2^12 gates, that holds both the appends and the recursive verifier
+  // The circuit is built with the CircuitConfig of profile
+  pub fn new(leaves_per_step: usize, max_peaks: usize, profile: &ConfigProfile) -> Result<Self> {
+    type C = PoseidonGoldilocksConfig;
+    assert!(leaves_per_step > 0 && max_peaks > 0 && max_peaks < 64);
+
+    let mut degree_bits = 12;
+    loop {
+      let (builder, targets, common_data) = step_circuit(leaves_per_step, max_peaks, common_data_for_recursion(degree_bits, profile), profile)?;
+      if fits_in_degree(builder.num_gates(), degree_bits) {
+        let circuit_data = builder.build::<C>();
+        return Ok(MmrAppendProver {
+          leaves_per_step: leaves_per_step,
+          max_peaks: max_peaks,
+          circuit_data: circuit_data,
+          common_data: common_data,
+          targets: targets
+        });
+      }
+      degree_bits += 1;
+    }
+  }
+
+  // Appends the leaves to the MMR of the previous proof, which has the given peaks, as returned by MMR::get_peaks
+  // Without a previous proof, the leaves are appended to the empty MMR
+  // Returns the new proof and the new peaks
+  pub fn append(
+    &self,
+    previous_proof: Option<ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>>,
+    peaks: &[HashOut<GoldilocksField>],
+    leaves: &[GoldilocksField]) -> Result<(ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>, Vec<HashOut<GoldilocksField>>)> {
+    let mut nr_leaves = previous_proof.as_ref().map_or(0, |proof| MmrAppendPublicInputs::from_proof(proof).nr_leaves);
+    ensure!(peaks.len() == nr_leaves.count_ones() as usize, "the peaks don't match the number of leaves");
+    ensure!(nr_leaves + leaves.len() < 1 << self.max_peaks, "the MMR can hold fewer than 2^{} leaves", self.max_peaks);
+    let mut slots = peaks_to_slots(nr_leaves, peaks, self.max_peaks);
+
+    let has_previous = previous_proof.is_some();
+    let mut proof = match previous_proof {
+      Some(proof) => proof,
+      None => cyclic_base_proof(&self.common_data, &self.circuit_data.verifier_only, Default::default())
+    };
+    // Appending no leaves to the empty MMR still takes a step
+    let nr_steps = (leaves.len() + self.leaves_per_step - 1) / self.leaves_per_step;
+    let nr_steps = if has_previous { nr_steps } else { nr_steps.max(1) };
+    for step in 0..nr_steps {
+      let mut pw = PartialWitness::new();
+      pw.set_bool_target(self.targets.has_previous, has_previous || step > 0);
+      pw.set_proof_with_pis_target(&self.targets.previous_proof, &proof);
+      pw.set_verifier_data_target(&self.targets.verifier_data, &self.circuit_data.verifier_only);
+      for i in 0..self.max_peaks {
+        pw.set_hash_target(self.targets.slots[i], slots[i]);
+      }
+
+      for i in 0..self.leaves_per_step {
+        let leaf = leaves.get(step * self.leaves_per_step + i).copied();
+        pw.set_bool_target(self.targets.enabled[i], leaf.is_some());
+        pw.set_target(self.targets.leaves[i], leaf.unwrap_or(GoldilocksField::ZERO));
+        if let Some(leaf) = leaf {
+          slots = append_to_slots(nr_leaves, &slots, leaf);
+          nr_leaves += 1;
+        }
+      }
+      proof = self.circuit_data.prove(pw)?;
+    }
+    Ok((proof, slots_to_peaks(nr_leaves, &slots)))
+  }
+
+  // Verifies the proof, and that it was made with this step circuit all the way down
+  pub fn verify(&self, proof: ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> Result<()> {
+    check_cyclic_proof_verifier_data(&proof, &self.circuit_data.verifier_only, &self.circuit_data.common)?;
+    self.circuit_data.verify(proof)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, hash::hash_types::HashOut};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::MMR, mmr_append_gadget::peaks_to_slots}};
+  use super::{MmrAppendProver, MmrAppendPublicInputs, peaks_commitment};
+
+  fn random_leaves(nr_leaves: usize) -> Vec<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    (0..nr_leaves).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect()
+  }
+
+  #[test]
+  fn test_same_root_as_mmr() -> Result<()> {
+    let max_peaks = 6;
+    let prover = MmrAppendProver::new(4, max_peaks, &ConfigProfile::default())?;
+    let leaves = random_leaves(23);
+    let mut mmr = MMR::new();
+
+    // Batches of different sizes, also smaller and larger than a step
+    let mut proof = None;
+    let mut peaks = Vec::new();
+    let mut start = 0;
+    for batch_size in [3, 9, 1, 10] {
+      let batch = &leaves[start..start + batch_size];
+      let (new_proof, new_peaks) = prover.append(proof, &peaks, batch)?;
+      for leaf in batch {
+        mmr.add_leaf(*leaf);
+      }
+      start += batch_size;
+
+      let public_inputs = MmrAppendPublicInputs::from_proof(&new_proof);
+      assert!(public_inputs.nr_leaves == start);
+      assert!(public_inputs.root == mmr.clone().bagging_the_peaks());
+      assert!(new_peaks == mmr.clone().get_peaks());
+      assert!(public_inputs.peaks_commitment == peaks_commitment(&peaks_to_slots(start, &new_peaks, max_peaks)));
+      prover.verify(new_proof.clone())?;
+      proof = Some(new_proof);
+      peaks = new_peaks;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_empty_mmr() -> Result<()> {
+    let prover = MmrAppendProver::new(2, 4, &ConfigProfile::default())?;
+    let (proof, peaks) = prover.append(None, &[], &[])?;
+    assert!(peaks.is_empty());
+    assert!(MmrAppendPublicInputs::from_proof(&proof).root == HashOut::ZERO);
+    prover.verify(proof)
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_wrong_peaks() {
+    let prover = MmrAppendProver::new(2, 4, &ConfigProfile::default()).unwrap();
+    let leaves = random_leaves(5);
+    let (proof, mut peaks) = prover.append(None, &[], &leaves[0..3]).unwrap();
+    peaks[0] = peaks[1];
+    prover.append(Some(proof), &peaks, &leaves[3..5]).unwrap();
+  }
+
+  #[test]
+  fn test_full_mmr() -> Result<()> {
+    let prover = MmrAppendProver::new(4, 3, &ConfigProfile::default())?;
+    let leaves = random_leaves(8);
+    let (proof, peaks) = prover.append(None, &[], &leaves[0..7])?;
+    assert!(prover.append(Some(proof), &peaks, &leaves[7..8]).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_zero_knowledge() -> Result<()> {
+    let prover = MmrAppendProver::new(4, 4, &ConfigProfile::ZeroKnowledge)?;
+    assert!(prover.circuit_data.common.config.zero_knowledge);
+    let leaves = random_leaves(6);
+    let (proof, _) = prover.append(None, &[], &leaves)?;
+    assert!(MmrAppendPublicInputs::from_proof(&proof).root == MMR::from_leaves(&leaves).bagging_the_peaks());
+    prover.verify(proof)
+  }
+}
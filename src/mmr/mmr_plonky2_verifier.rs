@@ -1,103 +1,253 @@
-use itertools::Itertools;
-use plonky2::{hash::{poseidon::PoseidonHash, hash_types::HashOutTarget}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::{CircuitData, CircuitConfig}, circuit_builder::CircuitBuilder}, iop::target::{BoolTarget, Target}};
-use plonky2_field::goldilocks_field::GoldilocksField;
-use crate::mmr::common::{equal, or_list, pick_hash};
+use std::sync::OnceLock;
+
+use anyhow::{ensure, Result};
+use plonky2::{hash::hash_types::{HashOut, HashOutTarget}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder, proof::ProofWithPublicInputs}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, leaf::LeafTarget, mmr::{merkle_mountain_ranges::{MMR, MMR_proof, get_heights_bitmap_for_mmr_size, get_mmr_index}, mmr_append_gadget::{bag_slots_circuit, peaks_to_slots}}};
+
+// Targets of an MMR proof that need to be set in the witness
+// By default the leaf is a single field element; for other leaves see verify_mmr_proof_circuit_for_record
+#[derive(Debug)]
+pub struct MmrProofTargets<L: LeafTarget = Target> {
+  // The leaf for which the proof is
+  pub leaf: L,
+  // The merkle proof elements with indication whether that hash is on the left
+  pub merkle_proof: Vec<(HashOutTarget, BoolTarget)>,
+  pub peaks: Vec<HashOutTarget>,
+  // Index of the peak of the subtree the leaf is in
+  pub peak_index: Target
+}
+
+impl MmrProofTargets<Target> {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &MMR_proof, leaf: GoldilocksField) {
+    self.set_record_witness(pw, proof, &leaf);
+  }
+}
+
+impl<L: LeafTarget> MmrProofTargets<L> {
+  pub fn set_record_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &MMR_proof, leaf: &L::Value) {
+    assert!(proof.merkle_proof.len() == self.merkle_proof.len() && proof.peaks.len() == self.peaks.len());
+    self.leaf.set_witness(pw, leaf);
+    for ((hash_target, on_left_target), (hash, on_left)) in self.merkle_proof.iter().zip(&proof.merkle_proof) {
+      pw.set_hash_target(*hash_target, *hash);
+      pw.set_bool_target(*on_left_target, *on_left);
+    }
+    for (peak_target, peak) in self.peaks.iter().zip(&proof.peaks) {
+      pw.set_hash_target(*peak_target, *peak);
+    }
+    pw.set_target(self.peak_index, GoldilocksField::from_canonical_usize(proof.peak_index()));
+  }
+}
+
+// Adds the constraints of an MMR proof with [nr_merkle_proof_elms] Merkle proof elements and [nr_peaks] peaks to the
+// builder, to embed it in a larger circuit
+// Returns the targets and the root the proof leads to; registering or connecting the root is up to the caller
+pub fn add_verify_mmr_proof(builder: &mut CircuitBuilder<GoldilocksField, 2>, nr_merkle_proof_elms: usize, nr_peaks: usize) -> (MmrProofTargets, HashOutTarget) {
+  add_verify_mmr_proof_for_record::<Target>(builder, nr_merkle_proof_elms, nr_peaks)
+}
+
+// Same as add_verify_mmr_proof, for any kind of leaf. The targets of the leaf can be constrained further by the caller
+pub fn add_verify_mmr_proof_for_record<L: LeafTarget>(builder: &mut CircuitBuilder<GoldilocksField, 2>, nr_merkle_proof_elms: usize, nr_peaks: usize) -> (MmrProofTargets<L>, HashOutTarget) {
+  // Verifying proof does the following:
+  // 1. Hashes its way through the (public input) merkle proof elements
+  // 2. Check result of (1) equals the peak at the (witnessed) peak index
+  // 3. Hash peaks and compare to public input root
+
+  // The leaf to prove is in the MMR
+  let leaf_to_prove = L::add_virtual(builder);
+  let hashed_leaf = leaf_to_prove.hash(builder);
+
+  // The merkle proof elements with indication whether that hash is on the left
+  let siblings = builder.add_virtual_hashes(nr_merkle_proof_elms);
+  let siblings_on_left: Vec<BoolTarget> = (0..nr_merkle_proof_elms).map(|_| builder.add_virtual_bool_target_safe()).collect();
+  let peak_targets = builder.add_virtual_hashes(nr_peaks);
+  let peak_index = builder.add_virtual_target();
+
+  // This is the expected root value (bagged MMR)
+  let root = builder.verify_mmr_membership(hashed_leaf, &siblings, &siblings_on_left, &peak_targets, peak_index);
+  let targets = MmrProofTargets {
+    leaf: leaf_to_prove,
+    merkle_proof: siblings.into_iter().zip(siblings_on_left).collect(),
+    peaks: peak_targets,
+    peak_index: peak_index
+  };
+  (targets, root)
+}
 
 // Returns a circuit that verifies an MMR proof that has:
 // - [nr_merkle_proof_elms] hashes that make up the Merkle proof of the subtree that the leaf is part of
 // - [nr_peaks] peaks that have to be hashed together to get to the root
-// Also returns targets that need to be set in the witness: (in order)
-// - Target: to set the leaf for which the proof is
-// - Vec<(HashOutTarget, BoolTarget)>: to set the merkle proof elements with indication whether that hash is on the left
-// - Vec<HashOutTarget>: to set the peaks
+// The circuit is built with the CircuitConfig of [profile]
+// Also returns the targets that need to be set in the witness. Public input is the root
 pub fn verify_mmr_proof_circuit(
   nr_merkle_proof_elms: usize,
-  nr_peaks: usize
-  // Returns circuit data, targets for leaf, targets for proof elements (hashes), targets for peaks
-) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, Target, Vec<(HashOutTarget, BoolTarget)>, Vec<HashOutTarget>) {
+  nr_peaks: usize,
+  profile: &ConfigProfile
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MmrProofTargets) {
+  verify_mmr_proof_circuit_for_record::<Target>(nr_merkle_proof_elms, nr_peaks, profile, |_, _| {})
+}
+
+// Same as verify_mmr_proof_circuit, for any kind of leaf that was added with MMR::add_record
+// After the membership check, constrain_leaf can add constraints on the fields of the leaf, and register public inputs
+// Public inputs are the root, followed by those of constrain_leaf
+pub fn verify_mmr_proof_circuit_for_record<L: LeafTarget>(
+  nr_merkle_proof_elms: usize,
+  nr_peaks: usize,
+  profile: &ConfigProfile,
+  constrain_leaf: impl FnOnce(&mut CircuitBuilder<GoldilocksField, 2>, &L)
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MmrProofTargets<L>) {
   const D: usize = 2;
   type C = PoseidonGoldilocksConfig;
   type F = <C as GenericConfig<D>>::F;
-  // Verifying proof does the following:
-  // 1. Hashes its way through the (public input) merkle proof elements
-  // 2. Check result of (1) is amongst peaks
-  //     (for this, compare it to all peaks and check that the OR of these comparisons together true)
-  // 3. Hash peaks and compare to public input root
-
-  let mut proof_targets: Vec<(HashOutTarget, BoolTarget)> = Vec::new();
-  let mut peak_targets: Vec<HashOutTarget> = Vec::new();
 
-  let config = CircuitConfig::standard_recursion_config();
+  let config = profile.circuit_config();
   let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
-  // The leaf to prove is in the MMR
-  let leaf_to_prove = builder.add_virtual_target();
-  let hashed_leaf = builder.hash_or_noop::<PoseidonHash>([leaf_to_prove].to_vec());
-  
-  // The first hashing outside of the loop, since it uses the leaf_to_prove
-  let mut next_hash: plonky2::hash::hash_types::HashOutTarget = hashed_leaf;
-
-  let mut proof_elm_index = 0;
-  while proof_elm_index < nr_merkle_proof_elms {
-    let merkle_proof_elm = builder.add_virtual_hash();
-    let elm_on_left = builder.add_virtual_bool_target_safe();
-    proof_targets.push((merkle_proof_elm, elm_on_left));
-    // Create the 2 options and then chose the correct one
-    // Option 1: sibling on the left
-    let option1 = builder.hash_or_noop::<PoseidonHash>([
-      merkle_proof_elm.elements.to_vec(),
-      next_hash.elements.to_vec()
-    ].concat());
-    // Option 2: sibling on the right
-    let option2 = builder.hash_or_noop::<PoseidonHash>([
-      next_hash.elements.to_vec(),
-      merkle_proof_elm.elements.to_vec()
-    ].concat());
-
-    // Pick the right next hash according to the bool that has been given with this element
-    next_hash = pick_hash(&mut builder, option1, option2, elm_on_left);
-    proof_elm_index += 1;
-  }
-
-  // Hash all peaks together
-  let mut peaks: Vec<HashOutTarget> = Vec::new();
-  let mut equals: Vec<BoolTarget> = Vec::new();
-  for _peaks in 0..nr_peaks {
-    let peak = builder.add_virtual_hash();
-    peaks.push(peak);
-    peak_targets.push(peak);
-    let equals_peak: BoolTarget = equal(&mut builder, peak, next_hash);
-    equals.push(equals_peak);
-  }
-
-  // Now check that the resulting "next_hash" appears in the given peaks
-  let hash_in_peaks = or_list(&mut builder, equals);
-  // check that its "true"
-  let one: plonky2::iop::target::Target = builder.one();
-  builder.connect(one, hash_in_peaks.target); 
-  // for some reason this below doesn't work
-  // builder.assert_bool(hash_in_peaks);
-
-  if peaks.len() > 1 {
-    let root = builder.hash_n_to_hash_no_pad::<PoseidonHash>(peaks.into_iter().flat_map(|x| x.elements).collect_vec());
-    // This is the expected root value (bagged MMR)
-    builder.register_public_inputs(&root.elements);
-  } else {
-    // If there's only 1 peak, the root will be equal to that peak
-    builder.register_public_inputs(&peaks[0].elements);
-  }
-
-  let data = builder.build::<C>();
-  (data, leaf_to_prove, proof_targets, peak_targets)
+  let (targets, root) = add_verify_mmr_proof_for_record::<L>(&mut builder, nr_merkle_proof_elms, nr_peaks);
+  builder.register_public_inputs(&root.elements);
+  constrain_leaf(&mut builder, &targets.leaf);
+
+  (builder.build::<C>(), targets)
+}
+
+// Number of peak slots of the membership circuit of prove_membership: it takes every MMR of fewer than 2^32 leaves
+pub const MEMBERSHIP_MAX_PEAKS: usize = 32;
+
+// Targets of the membership circuit, which proves any leaf of any MMR with fewer than 2^max_peaks leaves
+// The peaks are kept in slots, one per height, as in mmr_append_gadget
+#[derive(Debug)]
+pub struct MmrMembershipTargets {
+  // The hash of the leaf, as stored in the MMR
+  pub leaf_hash: HashOutTarget,
+  // Index of the leaf (not the MMR index)
+  pub leaf_index: Target,
+  pub nr_leaves: Target,
+  // Slot h holds the peak of height h, or anything if there is none
+  pub peaks: Vec<HashOutTarget>,
+  // The Merkle proof of the leaf in its subtree, from the leaf up. Only the first (height of the peak) are used
+  pub siblings: Vec<HashOutTarget>
+}
+
+impl MmrMembershipTargets {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, mmr: &MMR, leaf_index: usize) {
+    // A peak of height h holds 2^h leaves, so the heights bitmap is the number of leaves
+    let nr_leaves = get_heights_bitmap_for_mmr_size(mmr.elements.len()).0 as usize;
+    assert!(leaf_index < nr_leaves && nr_leaves < 1 << self.peaks.len());
+    let mmr_proof = mmr.clone().get_proof_normal_index(leaf_index);
+    pw.set_hash_target(self.leaf_hash, mmr.elements[get_mmr_index(leaf_index)]);
+    pw.set_target(self.leaf_index, GoldilocksField::from_canonical_usize(leaf_index));
+    pw.set_target(self.nr_leaves, GoldilocksField::from_canonical_usize(nr_leaves));
+    for (peak_target, peak) in self.peaks.iter().zip(peaks_to_slots(nr_leaves, &mmr_proof.peaks, self.peaks.len())) {
+      pw.set_hash_target(*peak_target, peak);
+    }
+    for (i, sibling_target) in self.siblings.iter().enumerate() {
+      let sibling = mmr_proof.merkle_proof.get(i).map_or(HashOut::ZERO, |(hash, _)| *hash);
+      pw.set_hash_target(*sibling_target, sibling);
+    }
+  }
+}
+
+// Adds the constraints of the membership of the leaf at leaf_index in an MMR with nr_leaves leaves to the builder
+// The directions of the Merkle path are the bits of leaf_index. The leaf is in the peak of height h, where h is the
+// highest bit set in nr_leaves and not in leaf_index, with all higher bits equal; such an h exists iff leaf_index is
+// below nr_leaves. The path is hashed for exactly h levels and has to end at the peak in slot h, so an internal node
+// can't pass for a leaf. Returns the targets and the root of the MMR
+pub fn add_mmr_membership(builder: &mut CircuitBuilder<GoldilocksField, 2>, max_peaks: usize) -> (MmrMembershipTargets, HashOutTarget) {
+  let leaf_hash = builder.add_virtual_hash();
+  let leaf_index = builder.add_virtual_target();
+  let nr_leaves = builder.add_virtual_target();
+  let peaks = builder.add_virtual_hashes(max_peaks);
+  let siblings = builder.add_virtual_hashes(max_peaks - 1);
+
+  let index_bits = builder.split_le(leaf_index, max_peaks);
+  let nr_leaves_bits = builder.split_le(nr_leaves, max_peaks);
+
+  // Find the height of the peak from the top, and which levels of the path are below it
+  let mut higher_bits_equal = builder._true();
+  let mut peak_found = builder._false();
+  let mut peak_height = builder.zero();
+  let mut level_in_path = [peak_found].repeat(max_peaks);
+  for height in (0..max_peaks).rev() {
+    level_in_path[height] = peak_found;
+    let index_bit_unset = builder.not(index_bits[height]);
+    let bit_only_in_nr_leaves = builder.and(nr_leaves_bits[height], index_bit_unset);
+    let is_peak_height = builder.and(higher_bits_equal, bit_only_in_nr_leaves);
+    peak_found = builder.or(peak_found, is_peak_height);
+    let height_target = builder.constant(GoldilocksField::from_canonical_usize(height));
+    peak_height = builder.mul_add(is_peak_height.target, height_target, peak_height);
+    let bits_equal = builder.is_equal(index_bits[height].target, nr_leaves_bits[height].target);
+    higher_bits_equal = builder.and(higher_bits_equal, bits_equal);
+  }
+  builder.assert_one(peak_found.target);
+
+  let mut subtree_root = leaf_hash;
+  for (height, sibling) in siblings.iter().enumerate() {
+    let parent = builder.verify_merkle_path(subtree_root, &[*sibling], &[index_bits[height]]);
+    subtree_root = builder.select_hash(level_in_path[height], parent, subtree_root);
+  }
+  builder.assert_hash_at_index(subtree_root, &peaks, peak_height);
+  let root = bag_slots_circuit(builder, &peaks, &nr_leaves_bits);
+
+  let targets = MmrMembershipTargets {
+    leaf_hash: leaf_hash,
+    leaf_index: leaf_index,
+    nr_leaves: nr_leaves,
+    peaks: peaks,
+    siblings: siblings
+  };
+  (targets, root)
+}
+
+// Returns a circuit that proves the membership of any leaf in any MMR with fewer than 2^max_peaks leaves
+// Public inputs are the root, the leaf hash, the leaf index and the number of leaves
+pub fn mmr_membership_circuit(max_peaks: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MmrMembershipTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder = CircuitBuilder::<F, D>::new(config);
+  let (targets, root) = add_mmr_membership(&mut builder, max_peaks);
+  builder.register_public_inputs(&root.elements);
+  builder.register_public_inputs(&targets.leaf_hash.elements);
+  builder.register_public_input(targets.leaf_index);
+  builder.register_public_input(targets.nr_leaves);
+
+  (builder.build::<C>(), targets)
+}
+
+static MEMBERSHIP_CIRCUIT: OnceLock<(CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MmrMembershipTargets)> = OnceLock::new();
+
+fn membership_circuit() -> &'static (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MmrMembershipTargets) {
+  MEMBERSHIP_CIRCUIT.get_or_init(|| mmr_membership_circuit(MEMBERSHIP_MAX_PEAKS, &ConfigProfile::default()))
+}
+
+// Returns the circuit of prove_membership, which is built once and shared by all its proofs
+// Verify the proofs with its verifier data
+pub fn membership_circuit_data() -> &'static CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2> {
+  &membership_circuit().0
+}
+
+// Proves that the leaf at leaf_index (not the MMR index) is in the MMR
+// The circuit takes the leaf hash as stored in the MMR, so any kind of leaf can be proven
+// Public inputs are the root, the leaf hash, the leaf index and the number of leaves, see mmr_membership_circuit
+pub fn prove_membership(mmr: &MMR, leaf_index: usize) -> Result<ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>> {
+  let nr_leaves = get_heights_bitmap_for_mmr_size(mmr.elements.len()).0 as usize;
+  ensure!(leaf_index < nr_leaves, "leaf index {} is not below the number of leaves {}", leaf_index, nr_leaves);
+  ensure!(nr_leaves < 1 << MEMBERSHIP_MAX_PEAKS, "the MMR has too many leaves for the membership circuit");
+  let (circuit_data, targets) = membership_circuit();
+  let mut pw = PartialWitness::new();
+  targets.set_witness(&mut pw, mmr, leaf_index);
+  circuit_data.prove(pw)
 }
 
 #[cfg(test)]
 mod tests {
   use anyhow::Result;
-  use plonky2::{iop::witness::WitnessWrite, plonk::config::PoseidonGoldilocksConfig};
+  use plonky2::{hash::{hash_types::HashOut, poseidon::PoseidonHash}, iop::witness::{PartialWitness, WitnessWrite}, plonk::config::{Hasher, PoseidonGoldilocksConfig}};
   use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
   use rand::Rng;
 
-  use crate::mmr::{merkle_mountain_ranges::{MMR, get_mmr_index}, mmr_plonky2_verifier::verify_mmr_proof_circuit, common::GOLDILOCKS_FIELD_ORDER};
+  use crate::{config_profile::ConfigProfile, leaf::Leaf, mmr::{merkle_mountain_ranges::{MMR, get_mmr_index}, mmr_append_gadget::peaks_to_slots, mmr_plonky2_verifier::{membership_circuit_data, mmr_membership_circuit, prove_membership, verify_mmr_proof_circuit}, common::GOLDILOCKS_FIELD_ORDER}};
 
   fn test_mmr_verifier(nr_leaves: usize, leaf_normal_index: usize) -> Result<()> {
     let leaf_mmr_index: usize = get_mmr_index(leaf_normal_index);
@@ -116,28 +266,12 @@ mod tests {
     let root = mmr.bagging_the_peaks();
     assert!(pr.clone().verify(leaves[leaf_normal_index], root));
 
-    let (circuit_data, 
-      leaf_target, 
-      proof_elms_targets, 
-      peak_targets) =
-      verify_mmr_proof_circuit(pr.clone().merkle_proof.len(), pr.clone().peaks.len());
-
-    // Create witness
-    let mut pw = plonky2::iop::witness::PartialWitness::new();
+    let (circuit_data, targets) =
+      verify_mmr_proof_circuit(pr.clone().merkle_proof.len(), pr.clone().peaks.len(), &ConfigProfile::default());
 
-    // Leaf to be proved
-    pw.set_target(leaf_target, leaves[leaf_normal_index]);
-    
-    // Merkle proof hashes, along with the indication whether the hash is on the left
-    for i in 0..pr.merkle_proof.len() {
-      pw.set_hash_target(proof_elms_targets[i].0, pr.merkle_proof[i].0);
-      pw.set_bool_target(proof_elms_targets[i].1, pr.merkle_proof[i].1);
-    }
-
-    // Add all peaks
-    for i in 0..pr.peaks.len() {
-      pw.set_hash_target(peak_targets[i], pr.peaks[i]);
-    }
+    // Create witness: the leaf to be proved, the Merkle proof and the peaks
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &pr, leaves[leaf_normal_index]);
     
     // Set the public inputs; leaf and root
     let expected_public_inputs = circuit_data.prover_only.public_inputs.clone();
@@ -207,4 +341,70 @@ mod tests {
     
     Ok(())
   }
-}
\ No newline at end of file
+  #[test]
+  fn test_prove_membership() -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<GoldilocksField> = (0..21).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    // The same circuit proves every leaf, in every peak
+    for leaf_index in [0, 16, 17, 20] {
+      let proof = prove_membership(&mmr, leaf_index)?;
+      let leaf_hash = mmr.elements[get_mmr_index(leaf_index)];
+      let expected_public_inputs = [
+        mmr.clone().bagging_the_peaks().elements.to_vec(),
+        leaf_hash.elements.to_vec(),
+        [GoldilocksField::from_canonical_usize(leaf_index), GoldilocksField::from_canonical_usize(21)].to_vec()
+      ].concat();
+      assert!(proof.public_inputs == expected_public_inputs);
+      membership_circuit_data().verify(proof)?;
+    }
+
+    // Leaves of several field elements are proven from their hash in the MMR as well
+    let records: Vec<Vec<GoldilocksField>> = leaves.chunks(3).map(|chunk| chunk.to_vec()).collect();
+    let mmr = MMR::from_leaves(&records);
+    let proof = prove_membership(&mmr, 4)?;
+    assert!(proof.public_inputs[0..4] == mmr.clone().bagging_the_peaks().elements);
+    assert!(proof.public_inputs[4..8] == records[4].hash().elements);
+    membership_circuit_data().verify(proof)
+  }
+
+  #[test]
+  fn test_membership_index_out_of_range() {
+    let leaves: Vec<GoldilocksField> = (0..21).map(GoldilocksField::from_canonical_u64).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    assert!(prove_membership(&mmr, 21).is_err());
+  }
+
+  // A leaf that isn't the one in the MMR at its index can't be proven
+  #[test]
+  #[should_panic]
+  fn test_membership_wrong_leaf() {
+    let leaves: Vec<GoldilocksField> = (0..21).map(GoldilocksField::from_canonical_u64).collect();
+    let mut mmr = MMR::from_leaves(&leaves);
+    mmr.elements[get_mmr_index(17)] = mmr.elements[get_mmr_index(16)];
+    prove_membership(&mmr, 17).unwrap();
+  }
+
+  // The parent of leaves 16 and 17 with the rest of their path reaches the peak, but the path of leaf 16 is one level
+  // longer
+  #[test]
+  #[should_panic]
+  fn test_internal_node_as_leaf() {
+    let leaves: Vec<GoldilocksField> = (0..21).map(GoldilocksField::from_canonical_u64).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    let (circuit_data, targets) = mmr_membership_circuit(8, &ConfigProfile::default());
+    let mut path = mmr.clone().get_proof_normal_index(16).merkle_proof;
+    let parent = PoseidonHash::two_to_one(mmr.elements[get_mmr_index(16)], path.remove(0).0);
+    let mut pw = PartialWitness::new();
+    pw.set_hash_target(targets.leaf_hash, parent);
+    pw.set_target(targets.leaf_index, GoldilocksField::from_canonical_usize(16));
+    pw.set_target(targets.nr_leaves, GoldilocksField::from_canonical_usize(21));
+    for (peak_target, peak) in targets.peaks.iter().zip(peaks_to_slots(21, &mmr.clone().get_peaks(), 8)) {
+      pw.set_hash_target(*peak_target, peak);
+    }
+    for (i, sibling_target) in targets.siblings.iter().enumerate() {
+      pw.set_hash_target(*sibling_target, path.get(i).map_or(HashOut::ZERO, |(hash, _)| *hash));
+    }
+    circuit_data.prove(pw).unwrap();
+  }
+}
@@ -1,7 +1,9 @@
-use itertools::Itertools;
-use plonky2::{hash::{poseidon::PoseidonHash, hash_types::HashOutTarget}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::{CircuitData, CircuitConfig, CommonCircuitData, VerifierCircuitTarget}, circuit_builder::CircuitBuilder, proof::ProofWithPublicInputsTarget}, iop::target::{BoolTarget, Target}};
-use plonky2_field::goldilocks_field::GoldilocksField;
-use crate::mmr::common::{pick_hash, equal, or_list};
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use plonky2::{hash::hash_types::HashOutTarget, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::{CircuitData, VerifierCircuitTarget}, circuit_builder::CircuitBuilder, proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget}}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, leaf::LeafTarget, mmr::{merkle_mountain_ranges::{MMR, MMR_proof}, mmr_plonky2_verifier}};
 
 /** 
  * An mmr proof consists of 2 parts:
@@ -12,131 +14,213 @@ use crate::mmr::common::{pick_hash, equal, or_list};
  * That is the strategy used here.
 */
 
+// Targets of the inner proof that need to be set in the witness
+// By default the leaf is a single field element; for other leaves see verify_inner_merkle_proof_circuit_for_record
+pub struct InnerMerkleProofTargets<L: LeafTarget = Target> {
+  // The leaf for which the proof is
+  pub leaf: L,
+  // The merkle proof elements with indication whether that hash is on the left
+  pub merkle_proof: Vec<(HashOutTarget, BoolTarget)>,
+  // The peaks are public inputs
+  pub peaks: Vec<HashOutTarget>,
+  // Index of the peak of the subtree the leaf is in
+  pub peak_index: Target
+}
+
+impl InnerMerkleProofTargets<Target> {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &MMR_proof, leaf: GoldilocksField) {
+    self.set_record_witness(pw, proof, &leaf);
+  }
+}
+
+impl<L: LeafTarget> InnerMerkleProofTargets<L> {
+  pub fn set_record_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &MMR_proof, leaf: &L::Value) {
+    assert!(proof.merkle_proof.len() == self.merkle_proof.len() && proof.peaks.len() == self.peaks.len());
+    self.leaf.set_witness(pw, leaf);
+    for ((hash_target, on_left_target), (hash, on_left)) in self.merkle_proof.iter().zip(&proof.merkle_proof) {
+      pw.set_hash_target(*hash_target, *hash);
+      pw.set_bool_target(*on_left_target, *on_left);
+    }
+    for (peak_target, peak) in self.peaks.iter().zip(&proof.peaks) {
+      pw.set_hash_target(*peak_target, *peak);
+    }
+    pw.set_target(self.peak_index, GoldilocksField::from_canonical_usize(proof.peak_index()));
+  }
+}
+
+// Targets of the outer proof that need to be set in the witness
+// The verifier data of the inner circuit is a constant of the outer circuit, so it is not part of the witness
+pub struct RecursiveMmrTargets {
+  pub inner_proof: ProofWithPublicInputsTarget<2>,
+  pub peaks: Vec<HashOutTarget>
+}
+
+impl RecursiveMmrTargets {
+  pub fn set_witness(
+    &self,
+    pw: &mut PartialWitness<GoldilocksField>,
+    proof: &MMR_proof,
+    inner_proof: &ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) {
+    assert!(proof.peaks.len() == self.peaks.len());
+    pw.set_proof_with_pis_target(&self.inner_proof, inner_proof);
+    for (peak_target, peak) in self.peaks.iter().zip(&proof.peaks) {
+      pw.set_hash_target(*peak_target, *peak);
+    }
+  }
+}
+
 // Returns a circuit that verifies a Merkle proof. The final check will be that the resulting root is present in a list of peaks
-// Also returns targets that need to be set in the witness: (in order)
-// - Target: to set the leaf for which the proof is
-// - Vec<(HashOutTarget, BoolTarget)>: to set the merkle proof elements with indication whether that hash is on the left
-// Public input is the resulting root
-pub fn verify_inner_merkle_proof_circuit(nr_merkle_proof_elms: usize, nr_peaks: usize) 
-  -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, Target, Vec<(HashOutTarget, BoolTarget)>) {
+// Also returns the targets that need to be set in the witness
+// Public inputs are the peaks
+// The circuit is built with the CircuitConfig of [profile]
+pub fn verify_inner_merkle_proof_circuit(nr_merkle_proof_elms: usize, nr_peaks: usize, profile: &ConfigProfile) 
+  -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, InnerMerkleProofTargets) {
+  verify_inner_merkle_proof_circuit_for_record::<Target>(nr_merkle_proof_elms, nr_peaks, profile, |_, _| {})
+}
+
+// Same as verify_inner_merkle_proof_circuit, for any kind of leaf that was added with MMR::add_record
+// After the membership check, constrain_leaf can add constraints on the fields of the leaf, and register public inputs
+// Public inputs are the peaks, followed by those of constrain_leaf
+pub fn verify_inner_merkle_proof_circuit_for_record<L: LeafTarget>(
+  nr_merkle_proof_elms: usize,
+  nr_peaks: usize,
+  profile: &ConfigProfile,
+  constrain_leaf: impl FnOnce(&mut CircuitBuilder<GoldilocksField, 2>, &L)
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, InnerMerkleProofTargets<L>) {
     const D: usize = 2;
     type C = PoseidonGoldilocksConfig;
     type F = <C as GenericConfig<D>>::F;
     
-    let mut proof_targets: Vec<(HashOutTarget, BoolTarget)> = Vec::new();
-
-    let config = CircuitConfig::standard_recursion_config();
+    let config = profile.circuit_config();
     let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
     // The leaf to prove is in the MMR
-    let leaf_to_prove = builder.add_virtual_target();
-    let hashed_leaf = builder.hash_or_noop::<PoseidonHash>([leaf_to_prove].to_vec());
-      
-    // The first hashing outside of the loop, since it uses the leaf_to_prove
-    let mut next_hash: plonky2::hash::hash_types::HashOutTarget = hashed_leaf;
-
-    let mut proof_elm_index = 0;
-    while proof_elm_index < nr_merkle_proof_elms {
-      let merkle_proof_elm = builder.add_virtual_hash();
-      let elm_on_left = builder.add_virtual_bool_target_safe();
-      proof_targets.push((merkle_proof_elm, elm_on_left));
-      // Create the 2 options and then chose the correct one
-      // Option 1: sibling on the left
-      let option1 = builder.hash_or_noop::<PoseidonHash>([
-        merkle_proof_elm.elements.to_vec(),
-        next_hash.elements.to_vec()
-      ].concat());
-      // Option 2: sibling on the right
-      let option2 = builder.hash_or_noop::<PoseidonHash>([
-        next_hash.elements.to_vec(),
-        merkle_proof_elm.elements.to_vec()
-      ].concat());
-  
-      // Pick the right next hash according to the bool that has been given with this element
-      next_hash = pick_hash(&mut builder, option1, option2, elm_on_left);
-      proof_elm_index += 1;
-    }
+    let leaf_to_prove = L::add_virtual(&mut builder);
+    let hashed_leaf = leaf_to_prove.hash(&mut builder);
 
-    let mut equals: Vec<BoolTarget> = Vec::new();
-    for _ in 0..nr_peaks {
-      let peak = builder.add_virtual_hash();
-      peak.elements.map(|elm| builder.register_public_input(elm));
-      let equals_peak: BoolTarget = equal(&mut builder, peak, next_hash);
-      equals.push(equals_peak);
+    let siblings = builder.add_virtual_hashes(nr_merkle_proof_elms);
+    let siblings_on_left: Vec<BoolTarget> = (0..nr_merkle_proof_elms).map(|_| builder.add_virtual_bool_target_safe()).collect();
+    let subtree_root = builder.verify_merkle_path(hashed_leaf, &siblings, &siblings_on_left);
+
+    let peak_targets = builder.add_virtual_hashes(nr_peaks);
+    for peak in peak_targets.iter() {
+      builder.register_public_inputs(&peak.elements);
     }
+    // Check that the resulting root of the subtree is the peak at peak_index
+    let peak_index = builder.add_virtual_target();
+    builder.assert_hash_at_index(subtree_root, &peak_targets, peak_index);
+    constrain_leaf(&mut builder, &leaf_to_prove);
 
-    // Now check that the resulting "next_hash" appears in the given peaks
-    let hash_in_peaks = or_list(&mut builder, equals);
-    // check that its "true"
-    let one: plonky2::iop::target::Target = builder.one();
-    builder.connect(one, hash_in_peaks.target); 
-    
     let data = builder.build::<C>();
-    (data, leaf_to_prove, proof_targets)
+    let targets = InnerMerkleProofTargets {
+      leaf: leaf_to_prove,
+      merkle_proof: siblings.into_iter().zip(siblings_on_left).collect(),
+      peaks: peak_targets,
+      peak_index: peak_index
+    };
+    (data, targets)
 }
 
 // This is the same as for the naive impl
 /**
  * Returns a circuit for the outer proof, which does the following:
- * - verifies inner proof
- * - checks that the resulting hash of the inner proof is part of the peaks
+ * - verifies inner proof, against the verifier data of [inner_proof_circuit_data] as a constant
+ * - checks that the peaks of the inner proof are the peaks that are bagged; the inner proof already checked
+ *   that the root of the subtree is the peak at its peak index
  * - checks the root is correct
+ * The inner circuit is one of verify_inner_merkle_proof_circuit(_for_record) for nr_peaks peaks
+ * Public inputs are the root, followed by the public inputs of the inner proof after its peaks
+ * The inner proof can be of another profile, but the config of [profile] must be wide enough to verify it
  */
 pub fn complete_verification_circuit_with_inner_proof(
-  inner_proof_circuit_data_common: CommonCircuitData<GoldilocksField, 2>, 
-  nr_peaks: usize
-) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, ProofWithPublicInputsTarget<2>, VerifierCircuitTarget, Vec<HashOutTarget>) {
+  inner_proof_circuit_data: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, 
+  nr_peaks: usize,
+  profile: &ConfigProfile
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, RecursiveMmrTargets) {
   const D: usize = 2;
   type C = PoseidonGoldilocksConfig;
   type F = <C as GenericConfig<D>>::F;
 
-  let config = CircuitConfig::standard_recursion_config();
+  let inner_common = &inner_proof_circuit_data.common;
+  assert!(inner_common.num_public_inputs >= nr_peaks * 4);
+  let config = profile.circuit_config();
   let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
 
   let prev_proof_target = 
-    builder.add_virtual_proof_with_pis(&inner_proof_circuit_data_common);
+    builder.add_virtual_proof_with_pis(inner_common);
   
-  let prev_proof_verifier_data = 
-    builder.add_virtual_verifier_data(inner_proof_circuit_data_common.config.fri_config.cap_height);
+  // The verifier data is a constant, so only proofs of the inner circuit are accepted
+  let prev_proof_verifier_data = VerifierCircuitTarget {
+    constants_sigmas_cap: builder.constant_merkle_cap(&inner_proof_circuit_data.verifier_only.constants_sigmas_cap),
+    circuit_digest: builder.constant_hash(inner_proof_circuit_data.verifier_only.circuit_digest)
+  };
 
   builder.verify_proof::<PoseidonGoldilocksConfig>(
     &prev_proof_target, 
     &prev_proof_verifier_data, 
-    &inner_proof_circuit_data_common);
+    inner_common);
   
-  let mut targets: Vec<HashOutTarget> = Vec::new();
-
-  // Hash all peaks together
-  let mut peaks: Vec<HashOutTarget> = Vec::new();
-  let mut equals: Vec<BoolTarget> = Vec::new();
-  let prev_hash = HashOutTarget::from_vec(prev_proof_target.public_inputs[0..4].to_vec());
-  for _peaks in 0..nr_peaks {
-    let peak = builder.add_virtual_hash();
-    peaks.push(peak);
-    targets.push(peak);
-    let equals_peak: BoolTarget = equal(&mut builder, peak, prev_hash);
-    equals.push(equals_peak);
-  }
-  // Check that the resulting hash of the merkle proof appears in the given peaks
-  let hash_in_peaks = or_list(&mut builder, equals);
-  // check that its "true"
-  let one: plonky2::iop::target::Target = builder.one();
-  builder.connect(one, hash_in_peaks.target); 
-
-  if peaks.len() > 1 {
-    let root = builder.hash_n_to_hash_no_pad::<PoseidonHash>(peaks.into_iter().flat_map(|x| x.elements).collect_vec());
-    // This is the expected root value (bagged MMR)
-    builder.register_public_inputs(&root.elements);
-  } else {
-    // If there's only 1 peak, the root will be equal to that peak
-    builder.register_public_inputs(&peaks[0].elements);
+  // The inner proof checked the root of the subtree against its peaks, which must be the peaks that are bagged
+  let peaks = builder.add_virtual_hashes(nr_peaks);
+  for (peak, inner_peak) in peaks.iter().zip(prev_proof_target.public_inputs.chunks(4)) {
+    builder.connect_hashes(*peak, HashOutTarget::from_vec(inner_peak.to_vec()));
   }
 
-  // Returns:
-  // - Current circuit
-  // - target where previous proof has to be added in witness
-  // - target where previous verifier data has to be added in witness
-  // - targets to set for this circuit wrt other checks that will be done
-  (builder.build::<C>(), prev_proof_target, prev_proof_verifier_data, targets)
+  // This is the expected root value (bagged MMR)
+  let root = builder.bag_peaks(&peaks);
+  builder.register_public_inputs(&root.elements);
+  builder.register_public_inputs(&prev_proof_target.public_inputs[nr_peaks * 4..]);
+
+  let targets = RecursiveMmrTargets {
+    inner_proof: prev_proof_target,
+    peaks: peaks
+  };
+  (builder.build::<C>(), targets)
+}
+
+// Returns an outer circuit that verifies a proof of the membership circuit of mmr_plonky2_verifier::prove_membership,
+// against its verifier data as a constant. Public inputs are those of the membership proof: the root, the leaf hash,
+// the leaf index and the number of leaves
+fn membership_outer_circuit(inner_proof_circuit_data: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>)
+  -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, ProofWithPublicInputsTarget<2>) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = ConfigProfile::default().circuit_config();
+  let mut builder = CircuitBuilder::<F, D>::new(config);
+  let inner_proof = builder.add_virtual_proof_with_pis(&inner_proof_circuit_data.common);
+  let inner_verifier_data = VerifierCircuitTarget {
+    constants_sigmas_cap: builder.constant_merkle_cap(&inner_proof_circuit_data.verifier_only.constants_sigmas_cap),
+    circuit_digest: builder.constant_hash(inner_proof_circuit_data.verifier_only.circuit_digest)
+  };
+  builder.verify_proof::<C>(&inner_proof, &inner_verifier_data, &inner_proof_circuit_data.common);
+  builder.register_public_inputs(&inner_proof.public_inputs);
+
+  (builder.build::<C>(), inner_proof)
+}
+
+static MEMBERSHIP_OUTER_CIRCUIT: OnceLock<(CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, ProofWithPublicInputsTarget<2>)> = OnceLock::new();
+
+fn membership_outer() -> &'static (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, ProofWithPublicInputsTarget<2>) {
+  MEMBERSHIP_OUTER_CIRCUIT.get_or_init(|| membership_outer_circuit(mmr_plonky2_verifier::membership_circuit_data()))
+}
+
+// Returns the outer circuit of prove_membership, which is built once and shared by all its proofs
+// Verify the proofs with its verifier data
+pub fn membership_circuit_data() -> &'static CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2> {
+  &membership_outer().0
+}
+
+// Proves that the leaf at leaf_index (not the MMR index) is in the MMR, with the proof of
+// mmr_plonky2_verifier::prove_membership as inner proof that is verified in the outer proof
+// Public inputs are the same as those of the inner proof: the root, the leaf hash, the leaf index and the number of
+// leaves
+pub fn prove_membership(mmr: &MMR, leaf_index: usize) -> Result<ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>> {
+  let inner_proof = mmr_plonky2_verifier::prove_membership(mmr, leaf_index)?;
+  let (circuit_data, inner_proof_target) = membership_outer();
+  let mut pw = PartialWitness::new();
+  pw.set_proof_with_pis_target(inner_proof_target, &inner_proof);
+  circuit_data.prove(pw)
 }
 
 
@@ -146,8 +230,8 @@ mod tests {
   use plonky2::{plonk::config::PoseidonGoldilocksConfig, iop::witness::WitnessWrite};
   use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
   use rand::Rng;
-  use crate::mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::{MMR, get_mmr_index}};
-  use super::{complete_verification_circuit_with_inner_proof, verify_inner_merkle_proof_circuit};
+  use crate::{config_profile::ConfigProfile, mmr::{common::GOLDILOCKS_FIELD_ORDER, merkle_mountain_ranges::{MMR, get_mmr_index}}};
+  use super::{complete_verification_circuit_with_inner_proof, membership_circuit_data, prove_membership, verify_inner_merkle_proof_circuit};
 
   pub fn test_complete_verification_circuit_with_inner_proof(nr_leaves: usize, normal_leaf_index: usize) -> Result<()> {
     let mut rng = rand::thread_rng();
@@ -163,30 +247,13 @@ mod tests {
     // Note that the merkle proof also contains the root of the subtree; we need this to chop the verification up in 2 parts 
     let pr = mmr.clone().get_proof(mmr_leaf_index);
 
-    let (inner_circuit_data, leaf_target, proof_targets) = verify_inner_merkle_proof_circuit(
-      pr.merkle_proof.len(), pr.peaks.len()
+    let (inner_circuit_data, inner_targets) = verify_inner_merkle_proof_circuit(
+      pr.merkle_proof.len(), pr.peaks.len(), &ConfigProfile::default()
     );
 
+    // The leaf to prove, the proof elements and the peaks, which are the public inputs since the root must be among them
     let mut pw1 = plonky2::iop::witness::PartialWitness::new();
-    pw1.set_target(leaf_target, leaves[normal_leaf_index]); // leaf to prove
-
-    // Add the proof elements; for each element we add the hash and a bool whether this hash is on the left
-    for i in 0..pr.merkle_proof.len() {
-      pw1.set_hash_target(proof_targets[i].0, pr.merkle_proof[i].0);
-      pw1.set_bool_target(proof_targets[i].1, pr.merkle_proof[i].1);
-    }
-    
-    let expected_public_inputs = inner_circuit_data.prover_only.public_inputs.clone();
-    // The public inputs are the peaks, since the root must be among them
-    let mut i = 0;
-    for peak in &pr.peaks {
-      // pw1.set_target(expected_public_inputs[i], subtree_root.elements[i]);
-      pw1.set_target(expected_public_inputs[i], peak.elements[0]);
-      pw1.set_target(expected_public_inputs[i+1], peak.elements[1]);
-      pw1.set_target(expected_public_inputs[i+2], peak.elements[2]);
-      pw1.set_target(expected_public_inputs[i+3], peak.elements[3]);
-      i+=4;
-    }
+    inner_targets.set_witness(&mut pw1, &pr, leaves[normal_leaf_index]);
 
     let inner_proof: plonky2::plonk::proof::ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2> = 
       inner_circuit_data.prove(pw1).unwrap();
@@ -195,17 +262,12 @@ mod tests {
     // inner_circuit_data.verify(inner_proof)
     
     
-    let (main_circuit_data, inner_proof_target, inner_verifier_data_target, targets) = 
-      complete_verification_circuit_with_inner_proof(inner_circuit_data.common, pr.peaks.len());
+    let (main_circuit_data, targets) = 
+      complete_verification_circuit_with_inner_proof(&inner_circuit_data, pr.peaks.len(), &ConfigProfile::default());
 
+    // The inner proof and all peaks
     let mut pw2 = plonky2::iop::witness::PartialWitness::new();
-    pw2.set_proof_with_pis_target(&inner_proof_target, &inner_proof);
-    pw2.set_verifier_data_target(&inner_verifier_data_target, &inner_circuit_data.verifier_only);
-
-    // Add all peaks to witness
-    for i in 0..pr.peaks.len() {
-      pw2.set_hash_target(targets[i], pr.peaks[i]);
-    }
+    targets.set_witness(&mut pw2, &pr, &inner_proof);
 
     let root = mmr.clone().bagging_the_peaks();
 
@@ -255,4 +317,50 @@ mod tests {
     let nr_leaves: usize = 1031;
     test_complete_verification_circuit_with_inner_proof(nr_leaves, 100)
   }
-}
\ No newline at end of file
+  #[test]
+  fn test_prove_membership() -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let leaves: Vec<GoldilocksField> = (0..21).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect();
+    let mmr = MMR::from_leaves(&leaves);
+    let proof = prove_membership(&mmr, 3)?;
+    let expected_public_inputs = [
+      mmr.clone().bagging_the_peaks().elements.to_vec(),
+      mmr.elements[get_mmr_index(3)].elements.to_vec(),
+      [GoldilocksField::from_canonical_usize(3), GoldilocksField::from_canonical_usize(21)].to_vec()
+    ].concat();
+    assert!(proof.public_inputs == expected_public_inputs);
+    membership_circuit_data().verify(proof)
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_membership_wrong_leaf() {
+    let leaves: Vec<GoldilocksField> = (0..21).map(GoldilocksField::from_canonical_u64).collect();
+    let mut mmr = MMR::from_leaves(&leaves);
+    mmr.elements[get_mmr_index(3)] = mmr.elements[get_mmr_index(2)];
+    prove_membership(&mmr, 3).unwrap();
+  }
+
+  // An inner proof for the leaf in another MMR of the same shape can't be used for the peaks of this one
+  #[test]
+  #[should_panic]
+  fn test_inner_proof_of_other_peaks() {
+    let leaves: Vec<GoldilocksField> = (0..7).map(GoldilocksField::from_canonical_u64).collect();
+    let other_leaves: Vec<GoldilocksField> = (100..107).map(GoldilocksField::from_canonical_u64).collect();
+    let proof = MMR::from_leaves(&leaves).get_proof_normal_index(2);
+    let other_proof = MMR::from_leaves(&other_leaves).get_proof_normal_index(2);
+
+    let (inner_circuit_data, inner_targets) = verify_inner_merkle_proof_circuit(
+      other_proof.merkle_proof.len(), other_proof.peaks.len(), &ConfigProfile::default()
+    );
+    let mut pw = plonky2::iop::witness::PartialWitness::new();
+    inner_targets.set_witness(&mut pw, &other_proof, other_leaves[2]);
+    let inner_proof = inner_circuit_data.prove(pw).unwrap();
+
+    let (circuit_data, targets) =
+      complete_verification_circuit_with_inner_proof(&inner_circuit_data, proof.peaks.len(), &ConfigProfile::default());
+    let mut pw = plonky2::iop::witness::PartialWitness::new();
+    targets.set_witness(&mut pw, &proof, &inner_proof);
+    circuit_data.prove(pw).unwrap();
+  }
+}
@@ -5,4 +5,7 @@ pub mod naive_mmr_plonky2_verifier_1_recursion;
 
 pub mod merkle_mountain_ranges;
 pub mod mmr_plonky2_verifier;
-pub mod mmr_plonky2_verifier_1_recursion;
\ No newline at end of file
+pub mod mmr_plonky2_verifier_1_recursion;
+pub mod mmr_aggregation;
+pub mod mmr_append_prover;
+pub mod mmr_append_gadget;
\ No newline at end of file
@@ -134,6 +134,13 @@ impl naive_MMR {
     (merkle_proof, self.peaks, relative_index)
   }
 
+  // Return the index among the peaks of the peak of the subtree the leaf at index is in
+  // The circuits take it in the witness to pick that peak
+  pub fn get_peak_index(&self, index: usize) -> usize {
+    let (_, index_highest_peak, _) = get_info_subtree_leaf_index(self, index);
+    self.peaks.iter().position(|peak| *peak == self.elements[index_highest_peak]).unwrap()
+  }
+
   // Return MMR proof with an extended Merkle proof, consisting of:
   // - Merkle proof for the subtree of which the leaf is part of WITH ROOT. 
   //     In a standard Merkle proof the root is not included, but this is useful for the recursive step, and included here
@@ -1,33 +1,19 @@
-use itertools::Itertools;
 use num::ToPrimitive;
-use plonky2::{plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::{CircuitData, CircuitConfig}, circuit_builder::CircuitBuilder}, hash::{poseidon::PoseidonHash, hash_types::HashOutTarget}, iop::target::BoolTarget};
-use plonky2_field::goldilocks_field::GoldilocksField;
+use plonky2::{plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, iop::{target::Target, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
 
-use crate::mmr::{naive_merkle_mountain_ranges::get_standard_index, common::{equal, or_list}};
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, leaf::LeafTarget, mmr::naive_merkle_mountain_ranges::get_standard_index};
 
-// Returns a circuit that verifies an mmr proof, and the targets that need to be set in the witness
-pub fn verify_naive_mmr_proof_circuit(
+// Hashes the leaf hash up to the root of its subtree, where the position of the leaf is fixed in the circuit
+// Adds the targets of the proof elements to targets, and returns the root of the subtree
+pub fn add_naive_subtree_path(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  leaf_hash: HashOutTarget,
   relative_leaf_index: usize, // index of leaf within subtree. This is an MMR index
   nr_proof_elms: usize, // nr of layers within subtree
-  nr_peaks: usize // peaks in MMR
-) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, Vec<HashOutTarget>) {
-  // 1. Hashes its way through the (public input) merkle proof elements
-  // 2. Check result of (1) is amongst peaks
-  //     (for this, compare it to all peaks and check that the OR of these comparisons together true)
-  // 3. Hash peaks and compare to public input root
-
-  const D: usize = 2;
-  type C = PoseidonGoldilocksConfig;
-  type F = <C as GenericConfig<D>>::F;
-
-  let mut targets: Vec<HashOutTarget> = Vec::new();
-
-  let config = CircuitConfig::standard_recursion_config();
-  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
-  // The leaf to prove is in the MMR
-  let leaf_to_prove = builder.add_virtual_hash();
-  targets.push(leaf_to_prove);
-  // The first hashing outside of the loop, since it uses the leaf_to_prove
+  targets: &mut Vec<HashOutTarget>
+) -> HashOutTarget {
+  // The first hashing outside of the loop, since it uses the leaf hash
   let merkle_proof_elm = builder.add_virtual_hash();
   targets.push(merkle_proof_elm);
   let mut next_hash: plonky2::hash::hash_types::HashOutTarget;
@@ -37,13 +23,13 @@ pub fn verify_naive_mmr_proof_circuit(
 
   if standardized_index % 2 == 0 {
     next_hash = builder.hash_or_noop::<PoseidonHash>([
-      leaf_to_prove.elements.to_vec(), 
+      leaf_hash.elements.to_vec(), 
       merkle_proof_elm.elements.to_vec()
     ].concat());
   } else {
     next_hash = builder.hash_or_noop::<PoseidonHash>([
       merkle_proof_elm.elements.to_vec(),
-      leaf_to_prove.elements.to_vec()
+      leaf_hash.elements.to_vec()
     ].concat());
   }
   let mut current_layer_index = standardized_index / 2;
@@ -64,49 +50,148 @@ pub fn verify_naive_mmr_proof_circuit(
     }
     current_layer_index = current_layer_index/2;
   }
+  next_hash
+}
 
-  // Hash all peaks together
-  let mut peaks: Vec<HashOutTarget> = Vec::new();
-  let mut equals: Vec<BoolTarget> = Vec::new();
-  for _peaks in 0..nr_peaks {
-    let peak = builder.add_virtual_hash();
-    peaks.push(peak);
-    targets.push(peak);
-    let equals_peak: BoolTarget = equal(&mut builder, peak, next_hash);
-    equals.push(equals_peak);
+// Targets of a naive MMR proof that need to be set in the witness
+// By default the leaf is its hash; for other leaves see verify_naive_mmr_proof_circuit_for_record
+#[derive(Debug)]
+pub struct NaiveMmrProofTargets<L: LeafTarget = HashOutTarget> {
+  // The leaf for which the proof is
+  pub leaf: L,
+  // The merkle proof elements, from the leaf up; their order follows from the position of the leaf
+  pub merkle_proof: Vec<HashOutTarget>,
+  pub peaks: Vec<HashOutTarget>,
+  // Index of the peak of the subtree the leaf is in
+  pub peak_index: Target
+}
+
+impl NaiveMmrProofTargets<HashOutTarget> {
+  // The proof elements and peaks are those of naive_MMR::get_proof, the peak index that of naive_MMR::get_peak_index
+  pub fn set_witness(
+    &self,
+    pw: &mut PartialWitness<GoldilocksField>,
+    leaf_hash: HashOut<GoldilocksField>,
+    merkle_proof: &[HashOut<GoldilocksField>],
+    peaks: &[HashOut<GoldilocksField>],
+    peak_index: usize
+  ) {
+    self.set_record_witness(pw, &leaf_hash, merkle_proof, peaks, peak_index);
   }
+}
 
-  // Now check that the resulting "next_hash" appears in the given peaks
-  let hash_in_peaks = or_list(&mut builder, equals);
-  // check that its "true"
-  let one: plonky2::iop::target::Target = builder.one();
-  builder.connect(one, hash_in_peaks.target); 
-  // for some reason this below doesn't work
-  // builder.assert_bool(hash_in_peaks);
-
-  if peaks.len() > 1 {
-    let root = builder.hash_n_to_hash_no_pad::<PoseidonHash>(peaks.into_iter().flat_map(|x| x.elements).collect_vec());
-    // This is the expected root value (bagged MMR)
-    builder.register_public_inputs(&root.elements);
-  } else {
-    // If there's only 1 peak, the root will be equal to that peak
-    builder.register_public_inputs(&peaks[0].elements);
+impl<L: LeafTarget> NaiveMmrProofTargets<L> {
+  pub fn set_record_witness(
+    &self,
+    pw: &mut PartialWitness<GoldilocksField>,
+    leaf: &L::Value,
+    merkle_proof: &[HashOut<GoldilocksField>],
+    peaks: &[HashOut<GoldilocksField>],
+    peak_index: usize
+  ) {
+    assert!(merkle_proof.len() == self.merkle_proof.len() && peaks.len() == self.peaks.len());
+    self.leaf.set_witness(pw, leaf);
+    for (hash_target, hash) in self.merkle_proof.iter().zip(merkle_proof) {
+      pw.set_hash_target(*hash_target, *hash);
+    }
+    for (peak_target, peak) in self.peaks.iter().zip(peaks) {
+      pw.set_hash_target(*peak_target, *peak);
+    }
+    pw.set_target(self.peak_index, GoldilocksField::from_canonical_usize(peak_index));
   }
+}
 
-  let data = builder.build::<C>();
-  (data, targets)
+// Adds the constraints of verify_naive_mmr_proof_circuit to the builder, to embed it in a larger circuit
+// Returns the targets and the root; registering the root is up to the caller
+pub fn add_verify_naive_mmr_proof(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  relative_leaf_index: usize,
+  nr_proof_elms: usize,
+  nr_peaks: usize
+) -> (NaiveMmrProofTargets, HashOutTarget) {
+  add_verify_naive_mmr_proof_for_record::<HashOutTarget>(builder, relative_leaf_index, nr_proof_elms, nr_peaks)
+}
+
+// Same as add_verify_naive_mmr_proof, for any kind of leaf. The targets of the leaf can be constrained further by the
+// caller
+pub fn add_verify_naive_mmr_proof_for_record<L: LeafTarget>(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  relative_leaf_index: usize,
+  nr_proof_elms: usize,
+  nr_peaks: usize
+) -> (NaiveMmrProofTargets<L>, HashOutTarget) {
+  // The leaf to prove is in the MMR
+  let leaf = L::add_virtual(builder);
+  let leaf_hash = leaf.hash(builder);
+  let mut merkle_proof: Vec<HashOutTarget> = Vec::new();
+  let subtree_root = add_naive_subtree_path(builder, leaf_hash, relative_leaf_index, nr_proof_elms, &mut merkle_proof);
+
+  // Now check that the resulting root of the subtree is the peak at peak_index. The position of the leaf is fixed in
+  // the circuit, but the peak of its subtree depends on the size of the MMR, so the index is in the witness
+  let peaks = builder.add_virtual_hashes(nr_peaks);
+  let peak_index = builder.add_virtual_target();
+  builder.assert_hash_at_index(subtree_root, &peaks, peak_index);
+
+  // This is the expected root value (bagged MMR)
+  let root = builder.bag_peaks(&peaks);
+  let targets = NaiveMmrProofTargets {
+    leaf: leaf,
+    merkle_proof: merkle_proof,
+    peaks: peaks,
+    peak_index: peak_index
+  };
+  (targets, root)
+}
+
+// Returns a circuit that verifies an mmr proof, and the targets that need to be set in the witness
+// The circuit is built with the CircuitConfig of [profile]
+pub fn verify_naive_mmr_proof_circuit(
+  relative_leaf_index: usize, // index of leaf within subtree. This is an MMR index
+  nr_proof_elms: usize, // nr of layers within subtree
+  nr_peaks: usize, // peaks in MMR
+  profile: &ConfigProfile
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, NaiveMmrProofTargets) {
+  verify_naive_mmr_proof_circuit_for_record::<HashOutTarget>(relative_leaf_index, nr_proof_elms, nr_peaks, profile, |_, _| {})
+}
+
+// Same as verify_naive_mmr_proof_circuit, for a leaf of any kind that is hashed in the circuit, instead of a leaf hash
+// After the membership check, constrain_leaf can add constraints on the fields of the leaf, and register public inputs
+// Public inputs are the root, followed by those of constrain_leaf
+pub fn verify_naive_mmr_proof_circuit_for_record<L: LeafTarget>(
+  relative_leaf_index: usize,
+  nr_proof_elms: usize,
+  nr_peaks: usize,
+  profile: &ConfigProfile,
+  constrain_leaf: impl FnOnce(&mut CircuitBuilder<GoldilocksField, 2>, &L)
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, NaiveMmrProofTargets<L>) {
+  // 1. Hashes its way through the (public input) merkle proof elements
+  // 2. Check result of (1) is the peak at the peak index
+  //     (for this, the peak is picked with a random access into the peaks)
+  // 3. Hash peaks and compare to public input root
+
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let (targets, root) = add_verify_naive_mmr_proof_for_record::<L>(&mut builder, relative_leaf_index, nr_proof_elms, nr_peaks);
+  builder.register_public_inputs(&root.elements);
+  constrain_leaf(&mut builder, &targets.leaf);
+
+  (builder.build::<C>(), targets)
 }
 
 #[cfg(test)]
 mod tests {
   use anyhow::Result;
   use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
-  use plonky2::{iop::witness::WitnessWrite, plonk::config::PoseidonGoldilocksConfig};
+  use plonky2::{iop::{target::Target, witness::WitnessWrite}, plonk::config::PoseidonGoldilocksConfig};
   use rand::Rng;
 
-  use crate::mmr::naive_merkle_mountain_ranges::naive_MMR;
+  use crate::{config_profile::ConfigProfile, mmr::naive_merkle_mountain_ranges::naive_MMR};
 
-  use super::verify_naive_mmr_proof_circuit;
+  use super::{verify_naive_mmr_proof_circuit, verify_naive_mmr_proof_circuit_for_record};
   const GOLDILOCKS_FIELD_ORDER: u64 = 18446744069414584321;
 
 
@@ -123,19 +208,13 @@ mod tests {
     let (circuit_data, targets) = verify_naive_mmr_proof_circuit(
       pr.2,
       pr.0.len(),
-      pr.1.len()
+      pr.1.len(),
+      &ConfigProfile::default()
     );
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
-    pw.set_hash_target(targets[0], mmr.elements[leaf_index]); // hashed leaf to prove
-    // Add all proof elements
-    for i in 0..pr.0.len() {
-      pw.set_hash_target(targets[1 + i], pr.0[i]);
-    }
-    // Add all peaks
-    for i in 0..pr.1.len() {
-      pw.set_hash_target(targets[pr.0.len() + 1 + i], pr.1[i]);
-    }
+    // The hashed leaf to prove, the proof elements and the peaks
+    targets.set_witness(&mut pw, mmr.elements[leaf_index], &pr.0, &pr.1, mmr.get_peak_index(leaf_index));
     
     let expected_public_inputs = circuit_data.prover_only.public_inputs.clone();
     for i in 0..4 {
@@ -228,6 +307,33 @@ mod tests {
     do_test_verify_proof(10101, 56)
   }
 
+  #[test]
+  fn test_verify_proof_for_record() -> Result<()> {
+    // The leaf itself instead of its hash, which the circuit hashes and makes public
+    let leaves: Vec<GoldilocksField> = (0..11).map(|i| GoldilocksField::from_canonical_u64(100 + i)).collect();
+    let mut mmr = naive_MMR::new(leaves[0]);
+    for leaf in leaves[1..].iter() {
+      mmr.add_leaf(*leaf);
+    }
+    let mmr_bagged = mmr.clone().bagging_the_peaks();
+    // MMR index 8 is leaf 5
+    let pr = mmr.clone().get_proof(8);
+
+    let (circuit_data, targets) = verify_naive_mmr_proof_circuit_for_record::<Target>(
+      pr.2,
+      pr.0.len(),
+      pr.1.len(),
+      &ConfigProfile::default(),
+      |builder, leaf| builder.register_public_input(*leaf)
+    );
+
+    let mut pw = plonky2::iop::witness::PartialWitness::new();
+    targets.set_record_witness(&mut pw, &leaves[5], &pr.0, &pr.1, mmr.get_peak_index(8));
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs == [mmr_bagged.root.elements.to_vec(), [leaves[5]].to_vec()].concat());
+    circuit_data.verify(proof)
+  }
+
   fn test_wrong_proof(nr_leaves: usize, leaf_index: usize, wrong_leaf: usize) {
     // let nr_leaves: usize = 1001;
     // let leaf_index: usize = 25;
@@ -244,20 +350,13 @@ mod tests {
     let (circuit_data, targets) = verify_naive_mmr_proof_circuit(
       pr.2,
       pr.0.len(),
-      pr.1.len()
+      pr.1.len(),
+      &ConfigProfile::default()
     );
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
     // WRONG LEAF
-    pw.set_hash_target(targets[0], mmr.elements[wrong_leaf]);
-    // Add all proof elements
-    for i in 0..pr.0.len() {
-      pw.set_hash_target(targets[1 + i], pr.0[i]);
-    }
-    // Add all peaks
-    for i in 0..pr.1.len() {
-      pw.set_hash_target(targets[pr.0.len() + 1 + i], pr.1[i]);
-    }
+    targets.set_witness(&mut pw, mmr.elements[wrong_leaf], &pr.0, &pr.1, mmr.get_peak_index(leaf_index));
     
     let expected_public_inputs = circuit_data.prover_only.public_inputs.clone();
     for i in 0..4 {
@@ -293,6 +392,23 @@ mod tests {
     test_wrong_proof(100100,1, 0);
   }
 
+  // The root of the subtree is a peak, but not the one at the peak index in the witness
+  #[test]
+  #[should_panic]
+  fn test_wrong_peak_index() {
+    let leaves: Vec<GoldilocksField> = (0..11).map(GoldilocksField::from_canonical_u64).collect();
+    let mut mmr = naive_MMR::new(leaves[0]);
+    for leaf in leaves[1..].iter() {
+      mmr.add_leaf(*leaf);
+    }
+    let pr = mmr.clone().get_proof(8);
+    let (circuit_data, targets) = verify_naive_mmr_proof_circuit(pr.2, pr.0.len(), pr.1.len(), &ConfigProfile::default());
+
+    let mut pw = plonky2::iop::witness::PartialWitness::new();
+    targets.set_witness(&mut pw, mmr.elements[8], &pr.0, &pr.1, mmr.get_peak_index(8) + 1);
+    circuit_data.prove(pw).unwrap();
+  }
+
   #[test]
   #[should_panic]
   fn test_wrong_root() {
@@ -311,20 +427,13 @@ mod tests {
     let (circuit_data, targets) = verify_naive_mmr_proof_circuit(
       pr.2,
       pr.0.len(),
-      pr.1.len()
+      pr.1.len(),
+      &ConfigProfile::default()
     );
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
     
-    pw.set_hash_target(targets[0], mmr.elements[0]);
-    // Add all proof elements
-    for i in 0..pr.0.len() {
-      pw.set_hash_target(targets[1 + i], pr.0[i]);
-    }
-    // Add all peaks
-    for i in 0..pr.1.len() {
-      pw.set_hash_target(targets[pr.0.len() + 1 + i], pr.1[i]);
-    }
+    targets.set_witness(&mut pw, mmr.elements[0], &pr.0, &pr.1, mmr.get_peak_index(leaf_index));
     
     let expected_public_inputs = circuit_data.prover_only.public_inputs.clone();
     for i in 0..4 {
@@ -356,20 +465,15 @@ mod tests {
     let (circuit_data, targets) = verify_naive_mmr_proof_circuit(
       pr.2,
       pr.0.len(),
-      pr.1.len()
+      pr.1.len(),
+      &ConfigProfile::default()
     );
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
     
-    pw.set_hash_target(targets[0], mmr.elements[0]);
-    // Add all proof elements
-    for i in 0..pr.0.len() {
-      pw.set_hash_target(targets[1 + i], pr.0[i]);
-    }
-    // Add all peaks
-    for i in 0..pr.1.len() {
-      pw.set_hash_target(targets[pr.0.len() + 1 + i], pr.1[0]);
-    }
+    // All peaks are the first one
+    let wrong_peaks = [pr.1[0]].repeat(pr.1.len());
+    targets.set_witness(&mut pw, mmr.elements[0], &pr.0, &wrong_peaks, mmr.get_peak_index(leaf_index));
     
     let expected_public_inputs = circuit_data.prover_only.public_inputs.clone();
     for i in 0..4 {
@@ -1,9 +1,7 @@
-use itertools::Itertools;
-use num::ToPrimitive;
-use plonky2::{plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::{CircuitData, CircuitConfig, CommonCircuitData, VerifierCircuitTarget}, circuit_builder::CircuitBuilder, proof::ProofWithPublicInputsTarget}, hash::{poseidon::PoseidonHash, hash_types::HashOutTarget}, iop::target::BoolTarget};
+use plonky2::{plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::{CircuitData, VerifierCircuitTarget}, circuit_builder::CircuitBuilder, proof::ProofWithPublicInputsTarget}, hash::hash_types::HashOutTarget, iop::target::Target};
 use plonky2_field::goldilocks_field::GoldilocksField;
 
-use crate::mmr::{naive_merkle_mountain_ranges::get_standard_index, common::{equal, or_list}};
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, leaf::LeafTarget, mmr::naive_mmr_plonky2_verifier::add_naive_subtree_path};
 
 /** 
  * An mmr proof consists of 2 parts:
@@ -17,10 +15,12 @@ use crate::mmr::{naive_merkle_mountain_ranges::get_standard_index, common::{equa
 /** Returns a circuit for the (inner) Merkle proof and the accompanying targets that have to be set by the witness
  *    Public input: root of subtree
  *    Inputs: leaf_to_prove (hashed), all elements of Merkle proof (count = nr_proof_elms) 
+ *    The circuit is built with the CircuitConfig of [profile]
  */
 pub fn verify_inner_merkle_proof_circuit(
   relative_leaf_index: usize, // index of leaf within subtree. This is an MMR index
-  nr_proof_elms: usize // nr of layers within subtree
+  nr_proof_elms: usize, // nr of layers within subtree
+  profile: &ConfigProfile
 ) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, Vec<HashOutTarget>) {
   const D: usize = 2;
   type C = PoseidonGoldilocksConfig;
@@ -29,119 +29,100 @@ pub fn verify_inner_merkle_proof_circuit(
   // targets that must be filled by witness
   let mut targets: Vec<HashOutTarget> = Vec::new();
 
-  let config = CircuitConfig::standard_recursion_config();
+  let config = profile.circuit_config();
   let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
   // The leaf to prove is in the MMR
   let leaf_to_prove = builder.add_virtual_hash();
   targets.push(leaf_to_prove);
-  // The first hashing outside of the loop, since it uses the leaf_to_prove
-  let merkle_proof_elm = builder.add_virtual_hash();
-  targets.push(merkle_proof_elm);
-  let mut next_hash: plonky2::hash::hash_types::HashOutTarget;
-
-  let nr_leaves_subtree = 2i32.pow(nr_proof_elms.to_u32().unwrap()).to_usize().unwrap();
-  let standardized_index = get_standard_index(relative_leaf_index, nr_leaves_subtree);
-
-  if standardized_index % 2 == 0 {
-    next_hash = builder.hash_or_noop::<PoseidonHash>([
-      leaf_to_prove.elements.to_vec(), 
-      merkle_proof_elm.elements.to_vec()
-    ].concat());
-  } else {
-    next_hash = builder.hash_or_noop::<PoseidonHash>([
-      merkle_proof_elm.elements.to_vec(),
-      leaf_to_prove.elements.to_vec()
-    ].concat());
-  }
-  let mut current_layer_index = standardized_index / 2;
-  for _layer in 1..nr_proof_elms {
-    let merkle_proof_elm= builder.add_virtual_hash();
-    targets.push(merkle_proof_elm);
-
-    if current_layer_index % 2 == 0 {
-      next_hash = builder.hash_or_noop::<PoseidonHash>([
-        next_hash.elements.to_vec(), 
-        merkle_proof_elm.elements.to_vec()
-      ].concat());
-    } else {
-      next_hash = builder.hash_or_noop::<PoseidonHash>([
-        merkle_proof_elm.elements.to_vec(),
-        next_hash.elements.to_vec()
-      ].concat());
-    }
-    current_layer_index = current_layer_index/2;
-  }
+  let subtree_root = add_naive_subtree_path(&mut builder, leaf_to_prove, relative_leaf_index, nr_proof_elms, &mut targets);
 
   // The proof is complete by checking the result equals the root of the subtree
-  builder.register_public_inputs(&next_hash.elements);
+  builder.register_public_inputs(&subtree_root.elements);
 
   let data = builder.build::<C>();
   (data, targets)
 }
 
+// Same as verify_inner_merkle_proof_circuit, for a leaf of any kind that is hashed in the circuit, instead of a leaf hash
+// After the membership check, constrain_leaf can add constraints on the fields of the leaf, and register public inputs
+// Returns the circuit, the leaf and the targets of the proof elements
+// Public inputs are the root of the subtree, followed by those of constrain_leaf
+pub fn verify_inner_merkle_proof_circuit_for_record<L: LeafTarget>(
+  relative_leaf_index: usize,
+  nr_proof_elms: usize,
+  profile: &ConfigProfile,
+  constrain_leaf: impl FnOnce(&mut CircuitBuilder<GoldilocksField, 2>, &L)
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, L, Vec<HashOutTarget>) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let mut targets: Vec<HashOutTarget> = Vec::new();
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let leaf = L::add_virtual(&mut builder);
+  let leaf_hash = leaf.hash(&mut builder);
+  let subtree_root = add_naive_subtree_path(&mut builder, leaf_hash, relative_leaf_index, nr_proof_elms, &mut targets);
+  builder.register_public_inputs(&subtree_root.elements);
+  constrain_leaf(&mut builder, &leaf);
+
+  (builder.build::<C>(), leaf, targets)
+}
+
 // This is the same as for the non-naive impl
 /**
  * Returns a circuit for the outer proof, which does the following:
- * - verifies inner proof
- * - checks that the resulting hash of the inner proof is part of the peaks
+ * - verifies inner proof, against the verifier data of [inner_proof_circuit_data] as a constant
+ * - checks that the resulting hash of the inner proof is the peak at the peak index in the witness
+ *   (see naive_MMR::get_peak_index)
  * - checks the root is correct
+ * The inner proof can be of another profile, but the config of [profile] must be wide enough to verify it
  */
 pub fn complete_verification_circuit_with_inner_proof(
-  inner_proof_circuit_data_common: CommonCircuitData<GoldilocksField, 2>, 
-  nr_peaks: usize
-) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, ProofWithPublicInputsTarget<2>, VerifierCircuitTarget, Vec<HashOutTarget>) {
+  inner_proof_circuit_data: &CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, 
+  nr_peaks: usize,
+  profile: &ConfigProfile
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, ProofWithPublicInputsTarget<2>, Vec<HashOutTarget>, Target) {
   const D: usize = 2;
   type C = PoseidonGoldilocksConfig;
   type F = <C as GenericConfig<D>>::F;
 
-  let config = CircuitConfig::standard_recursion_config();
+  let config = profile.circuit_config();
   let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
 
   let prev_proof_target = 
-    builder.add_virtual_proof_with_pis(&inner_proof_circuit_data_common);
+    builder.add_virtual_proof_with_pis(&inner_proof_circuit_data.common);
   
-  let prev_proof_verifier_data = 
-    builder.add_virtual_verifier_data(inner_proof_circuit_data_common.config.fri_config.cap_height);
+  // The verifier data is a constant, so only proofs of the inner circuit are accepted
+  let prev_proof_verifier_data = VerifierCircuitTarget {
+    constants_sigmas_cap: builder.constant_merkle_cap(&inner_proof_circuit_data.verifier_only.constants_sigmas_cap),
+    circuit_digest: builder.constant_hash(inner_proof_circuit_data.verifier_only.circuit_digest)
+  };
 
   builder.verify_proof::<PoseidonGoldilocksConfig>(
     &prev_proof_target, 
     &prev_proof_verifier_data, 
-    &inner_proof_circuit_data_common);
+    &inner_proof_circuit_data.common);
   
   let mut targets: Vec<HashOutTarget> = Vec::new();
 
-  // Hash all peaks together
-  let mut peaks: Vec<HashOutTarget> = Vec::new();
-  let mut equals: Vec<BoolTarget> = Vec::new();
+  let peaks = builder.add_virtual_hashes(nr_peaks);
+  targets.extend(peaks.iter());
   let prev_hash = HashOutTarget::from_vec(prev_proof_target.public_inputs[0..4].to_vec());
-  for _peaks in 0..nr_peaks {
-    let peak = builder.add_virtual_hash();
-    peaks.push(peak);
-    targets.push(peak);
-    let equals_peak: BoolTarget = equal(&mut builder, peak, prev_hash);
-    equals.push(equals_peak);
-  }
-  // Check that the resulting hash of the merkle proof appears in the given peaks
-  let hash_in_peaks = or_list(&mut builder, equals);
-  // check that its "true"
-  let one: plonky2::iop::target::Target = builder.one();
-  builder.connect(one, hash_in_peaks.target); 
-
-  if peaks.len() > 1 {
-    let root = builder.hash_n_to_hash_no_pad::<PoseidonHash>(peaks.into_iter().flat_map(|x| x.elements).collect_vec());
-    // This is the expected root value (bagged MMR)
-    builder.register_public_inputs(&root.elements);
-  } else {
-    // If there's only 1 peak, the root will be equal to that peak
-    builder.register_public_inputs(&peaks[0].elements);
-  }
+  // Check that the resulting hash of the merkle proof is the peak at peak_index
+  let peak_index = builder.add_virtual_target();
+  builder.assert_hash_at_index(prev_hash, &peaks, peak_index);
+
+  // This is the expected root value (bagged MMR)
+  let root = builder.bag_peaks(&peaks);
+  builder.register_public_inputs(&root.elements);
 
   // Returns:
   // - Current circuit
   // - target where previous proof has to be added in witness
-  // - target where previous verifier data has to be added in witness
-  // - targets to set for this circuit wrt other checks that will be done
-  (builder.build::<C>(), prev_proof_target, prev_proof_verifier_data, targets)
+  // - targets to set for this circuit wrt other checks that will be done: the peaks and the peak index
+  (builder.build::<C>(), prev_proof_target, targets, peak_index)
 }
 
 #[cfg(test)]
@@ -151,7 +132,7 @@ mod tests {
   use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
   use rand::Rng;
 
-  use crate::mmr::naive_merkle_mountain_ranges::naive_MMR;
+  use crate::{config_profile::ConfigProfile, mmr::naive_merkle_mountain_ranges::naive_MMR};
 
   use super::{verify_inner_merkle_proof_circuit, complete_verification_circuit_with_inner_proof};
   const GOLDILOCKS_FIELD_ORDER: u64 = 18446744069414584321;
@@ -170,7 +151,8 @@ mod tests {
 
     let (circuit_data, targets) = verify_inner_merkle_proof_circuit(
       pr.2,
-      pr.0.len()-1
+      pr.0.len()-1,
+      &ConfigProfile::default()
     );
 
     let mut pw = plonky2::iop::witness::PartialWitness::new();
@@ -217,7 +199,8 @@ mod tests {
 
     let (inner_circuit_data, targets) = verify_inner_merkle_proof_circuit(
       pr.2,
-      pr.0.len()-1
+      pr.0.len()-1,
+      &ConfigProfile::default()
     );
 
     let mut pw1 = plonky2::iop::witness::PartialWitness::new();
@@ -236,17 +219,17 @@ mod tests {
     let inner_proof: plonky2::plonk::proof::ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2> = 
       inner_circuit_data.prove(pw1).unwrap();
 
-    let (main_circuit_data, inner_proof_target, inner_verifier_data_target, targets) = 
-      complete_verification_circuit_with_inner_proof(inner_circuit_data.common, mmr.peaks.len());
+    let (main_circuit_data, inner_proof_target, targets, peak_index) = 
+      complete_verification_circuit_with_inner_proof(&inner_circuit_data, mmr.peaks.len(), &ConfigProfile::default());
 
     let mut pw2 = plonky2::iop::witness::PartialWitness::new();
     pw2.set_proof_with_pis_target(&inner_proof_target, &inner_proof);
-    pw2.set_verifier_data_target(&inner_verifier_data_target, &inner_circuit_data.verifier_only);
 
     // Add all peaks to witness
     for i in 0..pr.1.len() {
       pw2.set_hash_target(targets[i], pr.1[i]);
     }
+    pw2.set_target(peak_index, GoldilocksField::from_canonical_usize(mmr.get_peak_index(leaf_index)));
 
     let mmr_bagged = mmr.clone().bagging_the_peaks();
 
@@ -302,7 +285,8 @@ mod tests {
 
     let (inner_circuit_data, targets) = verify_inner_merkle_proof_circuit(
       pr.2,
-      pr.0.len()-1
+      pr.0.len()-1,
+      &ConfigProfile::default()
     );
 
     let mut pw1 = plonky2::iop::witness::PartialWitness::new();
@@ -321,18 +305,18 @@ mod tests {
     let inner_proof: plonky2::plonk::proof::ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2> = 
       inner_circuit_data.prove(pw1).unwrap();
 
-    let (main_circuit_data, inner_proof_target, inner_verifier_data_target, targets) = 
-      complete_verification_circuit_with_inner_proof(inner_circuit_data.common, mmr.peaks.len());
+    let (main_circuit_data, inner_proof_target, targets, peak_index) = 
+      complete_verification_circuit_with_inner_proof(&inner_circuit_data, mmr.peaks.len(), &ConfigProfile::default());
 
     // Outer proof is for leaf 3
     let mut pw2 = plonky2::iop::witness::PartialWitness::new();
     pw2.set_proof_with_pis_target(&inner_proof_target, &inner_proof);
-    pw2.set_verifier_data_target(&inner_verifier_data_target, &inner_circuit_data.verifier_only);
 
     // Add all peaks to witness
     for i in 0..pr.1.len() {
       pw2.set_hash_target(targets[i], pr.1[i]);
     }
+    pw2.set_target(peak_index, GoldilocksField::from_canonical_usize(mmr.get_peak_index(leaf_index)));
 
     let mmr_bagged = mmr.clone().bagging_the_peaks();
 
@@ -345,6 +329,39 @@ mod tests {
       main_circuit_data.prove(pw2).unwrap();
   }
 
+  // The outer circuit only accepts proofs of the inner circuit it was built for, not of another inner circuit of the
+  // same shape, which here proves a leaf at another position
+  #[test]
+  #[should_panic]
+  fn test_complete_verification_circuit_with_proof_of_other_circuit() {
+    let leaves: Vec<GoldilocksField> = (0..16).map(GoldilocksField::from_canonical_u64).collect();
+    let mut mmr = naive_MMR::new(leaves[0]);
+    for leaf in leaves[1..].iter() {
+      mmr.add_leaf(*leaf);
+    }
+    let pr = mmr.clone().get_proof_with_extended_merkleproof(0);
+    let other_pr = mmr.clone().get_proof_with_extended_merkleproof(1);
+
+    let (inner_circuit_data, _) = verify_inner_merkle_proof_circuit(pr.2, pr.0.len()-1, &ConfigProfile::default());
+    let (other_circuit_data, targets) = verify_inner_merkle_proof_circuit(other_pr.2, other_pr.0.len()-1, &ConfigProfile::default());
+    let mut pw1 = plonky2::iop::witness::PartialWitness::new();
+    pw1.set_hash_target(targets[0], mmr.elements[1]);
+    for i in 0..other_pr.0.len()-1 {
+      pw1.set_hash_target(targets[1 + i], other_pr.0[i]);
+    }
+    let other_proof = other_circuit_data.prove(pw1).unwrap();
+
+    let (main_circuit_data, inner_proof_target, targets, peak_index) = 
+      complete_verification_circuit_with_inner_proof(&inner_circuit_data, mmr.peaks.len(), &ConfigProfile::default());
+    let mut pw2 = plonky2::iop::witness::PartialWitness::new();
+    pw2.set_proof_with_pis_target(&inner_proof_target, &other_proof);
+    for i in 0..other_pr.1.len() {
+      pw2.set_hash_target(targets[i], other_pr.1[i]);
+    }
+    pw2.set_target(peak_index, GoldilocksField::from_canonical_usize(mmr.get_peak_index(1)));
+    main_circuit_data.prove(pw2).unwrap();
+  }
+
   #[test]
   #[should_panic]
   fn test_complete_verification_circuit_with_wrong_outer_proof() {
@@ -360,7 +377,8 @@ mod tests {
 
     let (inner_circuit_data, targets) = verify_inner_merkle_proof_circuit(
       pr.2,
-      pr.0.len()-1
+      pr.0.len()-1,
+      &ConfigProfile::default()
     );
 
     let mut pw1 = plonky2::iop::witness::PartialWitness::new();
@@ -379,17 +397,17 @@ mod tests {
     let inner_proof: plonky2::plonk::proof::ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2> = 
       inner_circuit_data.prove(pw1).unwrap();
 
-    let (main_circuit_data, inner_proof_target, inner_verifier_data_target, targets) = 
-      complete_verification_circuit_with_inner_proof(inner_circuit_data.common, mmr.peaks.len());
+    let (main_circuit_data, inner_proof_target, targets, peak_index) = 
+      complete_verification_circuit_with_inner_proof(&inner_circuit_data, mmr.peaks.len(), &ConfigProfile::default());
 
     let mut pw2 = plonky2::iop::witness::PartialWitness::new();
     pw2.set_proof_with_pis_target(&inner_proof_target, &inner_proof);
-    pw2.set_verifier_data_target(&inner_verifier_data_target, &inner_circuit_data.verifier_only);
 
     // Add all peaks to witness
     for i in 0..pr.1.len() {
       pw2.set_hash_target(targets[i], pr.1[i]);
     }
+    pw2.set_target(peak_index, GoldilocksField::from_canonical_usize(mmr.get_peak_index(leaf_index)));
 
     let mmr_bagged = mmr.clone().bagging_the_peaks();
 
@@ -420,7 +438,8 @@ mod tests {
 
     let (inner_circuit_data, targets) = verify_inner_merkle_proof_circuit(
       pr.2,
-      pr.0.len()-1
+      pr.0.len()-1,
+      &ConfigProfile::default()
     );
 
     let mut pw1 = plonky2::iop::witness::PartialWitness::new();
@@ -439,17 +458,17 @@ mod tests {
     let inner_proof: plonky2::plonk::proof::ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2> = 
       inner_circuit_data.prove(pw1).unwrap();
 
-    let (main_circuit_data, inner_proof_target, inner_verifier_data_target, targets) = 
-      complete_verification_circuit_with_inner_proof(inner_circuit_data.common, mmr.peaks.len());
+    let (main_circuit_data, inner_proof_target, targets, peak_index) = 
+      complete_verification_circuit_with_inner_proof(&inner_circuit_data, mmr.peaks.len(), &ConfigProfile::default());
 
     let mut pw2 = plonky2::iop::witness::PartialWitness::new();
     pw2.set_proof_with_pis_target(&inner_proof_target, &inner_proof);
-    pw2.set_verifier_data_target(&inner_verifier_data_target, &inner_circuit_data.verifier_only);
 
     // Add all peaks to witness
     for i in 0..pr.1.len() {
       pw2.set_hash_target(targets[i], pr.1[i]);
     }
+    pw2.set_target(peak_index, GoldilocksField::from_canonical_usize(mmr.get_peak_index(leaf_index)));
 
     // Adding 1 more leaf changes the peaks and should cause a mismatch between inner and outer proof
     mmr.add_leaf(GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER)));  
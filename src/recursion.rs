@@ -0,0 +1,51 @@
+use plonky2::{gates::noop::NoopGate, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CommonCircuitData, circuit_builder::CircuitBuilder}};
+use plonky2_field::goldilocks_field::GoldilocksField;
+
+use crate::config_profile::ConfigProfile;
+
+/**
+ * Helpers for cyclic recursion, where a circuit verifies proofs of itself.
+ *
+ * Such a circuit needs to know its own common circuit data before it is built. This is solved by building
+ * a circuit that only verifies a proof, padded to a fixed number of gates; the cyclic circuit must end up with
+ * exactly the same shape, so it has to stay below that number of gates.
+ */
+
+// Gates that are added when building, after the last gate of the circuit logic: hashing the public inputs, constants
+pub const BUILD_GATES_MARGIN: usize = 128;
+
+// Returns the common data of a circuit with 2^degree_bits gates that verifies a proof of itself
+// The circuit is built with the CircuitConfig of profile, which must be that of the cyclic circuit
+pub fn common_data_for_recursion(degree_bits: usize, profile: &ConfigProfile) -> CommonCircuitData<GoldilocksField, 2> {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let builder = CircuitBuilder::<F, D>::new(config);
+  let data = builder.build::<C>();
+
+  // A circuit that verifies the empty circuit
+  let config = profile.circuit_config();
+  let mut builder = CircuitBuilder::<F, D>::new(config);
+  let proof = builder.add_virtual_proof_with_pis(&data.common);
+  let verifier_data = builder.add_virtual_verifier_data(data.common.config.fri_config.cap_height);
+  builder.verify_proof::<C>(&proof, &verifier_data, &data.common);
+  let data = builder.build::<C>();
+
+  // A circuit that verifies a recursive proof, which contains all gates a recursive verifier uses
+  let config = profile.circuit_config();
+  let mut builder = CircuitBuilder::<F, D>::new(config);
+  let proof = builder.add_virtual_proof_with_pis(&data.common);
+  let verifier_data = builder.add_virtual_verifier_data(data.common.config.fri_config.cap_height);
+  builder.verify_proof::<C>(&proof, &verifier_data, &data.common);
+  while builder.num_gates() < 1 << degree_bits {
+    builder.add_gate(NoopGate, vec![]);
+  }
+  builder.build::<C>().common
+}
+
+// Returns whether a cyclic circuit with the given number of gates (before building) fits in 2^degree_bits gates
+pub fn fits_in_degree(num_gates: usize, degree_bits: usize) -> bool {
+  num_gates + BUILD_GATES_MARGIN <= 1 << degree_bits
+}
@@ -0,0 +1,2 @@
+pub mod semaphore;
+pub mod semaphore_plonky2_verifier;
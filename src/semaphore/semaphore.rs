@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+use anyhow::{ensure, Result};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::Field;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::circuit_data::VerifierCircuitData;
+use plonky2::plonk::config::{Hasher, PoseidonGoldilocksConfig};
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use rand::Rng;
+
+use crate::{leaf::Leaf, mmr::common::GOLDILOCKS_FIELD_ORDER};
+
+/**
+ * Semaphore-style anonymous signals: a member proves their identity is in a group without revealing which one, and
+ * publishes a nullifier so they can signal only once per external nullifier (for example an epoch).
+ *
+ * An identity is a secret and a trapdoor of 4 field elements each. The group is a MerkleTree or MMR whose leaves are
+ * the identity commitments Poseidon(COMMITMENT_DOMAIN_TAG, secret, trapdoor); the commitment is the leaf hash itself.
+ * The nullifier is Poseidon(NULLIFIER_DOMAIN_TAG, secret, external_nullifier), so it is the same for every signal of an
+ * identity in an epoch, but can't be linked to the commitment or to nullifiers of other epochs.
+ * Without the tag a commitment would be hash_no_pad of 8 elements, which is two_to_one: the children of any internal
+ * node of the group would then be an identity whose commitment is that node.
+ * The circuits are in semaphore_plonky2_verifier.rs. A verifier keeps the nullifiers it has seen in a NullifierRegistry.
+ */
+// Domain tags that are hashed first, so commitments, nullifiers and the nodes of the group can't be equal
+pub const COMMITMENT_DOMAIN_TAG: u64 = 0x636f6d6d6974;
+pub const NULLIFIER_DOMAIN_TAG: u64 = 0x6e756c6c6966;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Identity {
+  pub secret: [GoldilocksField; 4],
+  pub trapdoor: [GoldilocksField; 4]
+}
+
+// The public inputs of a signal proof, in the order the circuits register them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SemaphorePublicInputs {
+  pub root: HashOut<GoldilocksField>,
+  pub nullifier: HashOut<GoldilocksField>,
+  pub signal_hash: HashOut<GoldilocksField>,
+  pub external_nullifier: GoldilocksField
+}
+
+// The nullifiers of the signals that have been accepted
+#[derive(Debug, Clone, Default)]
+pub struct NullifierRegistry {
+  used: HashSet<HashOut<GoldilocksField>>
+}
+
+fn random_elements() -> [GoldilocksField; 4] {
+  let mut rng = rand::thread_rng();
+  [(); 4].map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER)))
+}
+
+// The leaf is hashed over its own domain tag, the secret and trapdoor, which gives the identity commitment
+impl Leaf for Identity {
+  fn to_field_elements(&self) -> Vec<GoldilocksField> {
+    [self.secret.to_vec(), self.trapdoor.to_vec()].concat()
+  }
+
+  fn hash(&self) -> HashOut<GoldilocksField> {
+    PoseidonHash::hash_no_pad(&[[GoldilocksField::from_canonical_u64(COMMITMENT_DOMAIN_TAG)].to_vec(), self.to_field_elements()].concat())
+  }
+}
+
+impl Identity {
+  pub fn random() -> Self {
+    Identity {
+      secret: random_elements(),
+      trapdoor: random_elements()
+    }
+  }
+
+  // The leaf of this identity in the group, see MerkleTree::build_from_hashes and MMR::add_leaf_hash
+  pub fn commitment(&self) -> HashOut<GoldilocksField> {
+    self.hash()
+  }
+
+  pub fn nullifier(&self, external_nullifier: GoldilocksField) -> HashOut<GoldilocksField> {
+    PoseidonHash::hash_no_pad(&[[GoldilocksField::from_canonical_u64(NULLIFIER_DOMAIN_TAG)].to_vec(), self.secret.to_vec(), [external_nullifier].to_vec()].concat())
+  }
+}
+
+// Hash of the message that is signaled, which is a public input of the proof
+pub fn hash_signal(signal: &[GoldilocksField]) -> HashOut<GoldilocksField> {
+  PoseidonHash::hash_no_pad(signal)
+}
+
+impl SemaphorePublicInputs {
+  // Returns an error if there aren't 13 public inputs, so a malformed proof is rejected instead of panicking
+  pub fn from_public_inputs(public_inputs: &[GoldilocksField]) -> Result<Self> {
+    ensure!(public_inputs.len() == 13, "expected 13 public inputs, got {}", public_inputs.len());
+    Ok(SemaphorePublicInputs {
+      root: HashOut::from_partial(&public_inputs[0..4]),
+      nullifier: HashOut::from_partial(&public_inputs[4..8]),
+      signal_hash: HashOut::from_partial(&public_inputs[8..12]),
+      external_nullifier: public_inputs[12]
+    })
+  }
+}
+
+impl NullifierRegistry {
+  pub fn new() -> Self {
+    NullifierRegistry { used: HashSet::new() }
+  }
+
+  pub fn is_used(&self, nullifier: HashOut<GoldilocksField>) -> bool {
+    self.used.contains(&nullifier)
+  }
+
+  // Returns an error if the nullifier has been registered before
+  pub fn register(&mut self, nullifier: HashOut<GoldilocksField>) -> Result<()> {
+    ensure!(self.used.insert(nullifier), "nullifier has been used");
+    Ok(())
+  }
+
+  // Verifies a signal proof for the group with the given root in the epoch of external_nullifier, and registers its
+  // nullifier. Returns the public inputs of the accepted signal
+  pub fn verify_and_register(
+    &mut self,
+    verifier_data: &VerifierCircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+    proof: ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+    root: HashOut<GoldilocksField>,
+    external_nullifier: GoldilocksField) -> Result<SemaphorePublicInputs> {
+    let public_inputs = SemaphorePublicInputs::from_public_inputs(&proof.public_inputs)?;
+    ensure!(public_inputs.root == root, "proof is for another group");
+    ensure!(public_inputs.external_nullifier == external_nullifier, "proof is for another external nullifier");
+    ensure!(!self.is_used(public_inputs.nullifier), "nullifier has been used");
+    verifier_data.verify(proof)?;
+    self.register(public_inputs.nullifier)?;
+    Ok(public_inputs)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+  use plonky2::{hash::poseidon::PoseidonHash, plonk::config::Hasher};
+
+  use crate::{semaphore::semaphore::{Identity, NullifierRegistry}, simple_merkle_tree::simple_merkle_tree::{MerkleTree, verify_merkle_proof_for_hash}};
+
+  #[test]
+  fn test_commitment_is_leaf() {
+    let identities: Vec<Identity> = (0..4).map(|_| Identity::random()).collect();
+    let tree = MerkleTree::build_from_hashes(identities.iter().map(|identity| identity.commitment()).collect());
+    assert!(tree.root == MerkleTree::build_from_records(&identities).root);
+    assert!(verify_merkle_proof_for_hash(identities[2].commitment(), 2, tree.root, tree.get_merkle_proof(2)));
+  }
+
+  // The children of an internal node, taken as secret and trapdoor, don't commit to that node
+  #[test]
+  fn test_commitment_is_not_internal_node() {
+    let identities: Vec<Identity> = (0..4).map(|_| Identity::random()).collect();
+    let tree = MerkleTree::build_from_hashes(identities.iter().map(|identity| identity.commitment()).collect());
+    let children = tree.level(0);
+    let forged = Identity { secret: children[0].elements, trapdoor: children[1].elements };
+    assert!(PoseidonHash::two_to_one(children[0], children[1]) == tree.level(1)[0]);
+    assert!(forged.commitment() != tree.level(1)[0]);
+    // The nullifier is hashed with another tag than the commitment
+    let identity = Identity { secret: identities[0].secret, trapdoor: [GoldilocksField::ONE, GoldilocksField::ZERO, GoldilocksField::ZERO, GoldilocksField::ZERO] };
+    assert!(identity.nullifier(GoldilocksField::ONE) != identity.commitment());
+  }
+
+  #[test]
+  fn test_registry_rejects_double_use() {
+    let identity = Identity::random();
+    let epoch1 = GoldilocksField::from_canonical_u64(1);
+    let epoch2 = GoldilocksField::from_canonical_u64(2);
+    assert!(identity.nullifier(epoch1) != identity.nullifier(epoch2));
+
+    let mut registry = NullifierRegistry::new();
+    assert!(registry.register(identity.nullifier(epoch1)).is_ok());
+    assert!(registry.register(identity.nullifier(epoch1)).is_err());
+    assert!(registry.register(identity.nullifier(epoch2)).is_ok());
+    assert!(registry.is_used(identity.nullifier(epoch2)));
+  }
+}
@@ -0,0 +1,275 @@
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, leaf::LeafTarget, mmr::merkle_mountain_ranges::MMR_proof, semaphore::semaphore::{Identity, COMMITMENT_DOMAIN_TAG, NULLIFIER_DOMAIN_TAG}, simple_merkle_tree::circuit::{add_verify_merkle_proof_for_record, MerkleProofTargets}};
+
+/**
+ * Circuits that prove an identity commitment is in a group and output the nullifier of the identity.
+ *
+ * Public inputs are the root of the group, the nullifier, the signal hash and the external nullifier (see
+ * SemaphorePublicInputs). The identity, its position and the path stay private, but only when the circuit is built
+ * with ConfigProfile::ZeroKnowledge; the other profiles don't blind the witness.
+ */
+
+// Targets of the secret and trapdoor of an identity. As a leaf it hashes to the identity commitment, like Identity::hash
+pub struct IdentityTarget {
+  pub secret: [Target; 4],
+  pub trapdoor: [Target; 4]
+}
+
+impl LeafTarget for IdentityTarget {
+  type Value = Identity;
+
+  fn add_virtual(builder: &mut CircuitBuilder<GoldilocksField, 2>) -> Self {
+    IdentityTarget {
+      secret: builder.add_virtual_target_arr(),
+      trapdoor: builder.add_virtual_target_arr()
+    }
+  }
+
+  fn to_targets(&self) -> Vec<Target> {
+    [self.secret.to_vec(), self.trapdoor.to_vec()].concat()
+  }
+
+  fn hash(&self, builder: &mut CircuitBuilder<GoldilocksField, 2>) -> HashOutTarget {
+    let tag = builder.constant(GoldilocksField::from_canonical_u64(COMMITMENT_DOMAIN_TAG));
+    builder.hash_n_to_hash_no_pad::<PoseidonHash>([[tag].to_vec(), self.to_targets()].concat())
+  }
+}
+
+// Targets of the signal that need to be set in the witness
+pub struct SignalTargets {
+  pub external_nullifier: Target,
+  pub signal_hash: HashOutTarget
+}
+
+impl SignalTargets {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, external_nullifier: GoldilocksField, signal_hash: HashOut<GoldilocksField>) {
+    pw.set_target(self.external_nullifier, external_nullifier);
+    pw.set_hash_target(self.signal_hash, signal_hash);
+  }
+}
+
+// Targets of a signal in a group that is a MerkleTree
+pub struct SemaphoreTargets {
+  pub membership: MerkleProofTargets<IdentityTarget>,
+  pub signal: SignalTargets
+}
+
+impl SemaphoreTargets {
+  // siblings is the proof of the identity commitment as returned by MerkleTree::get_merkle_proof
+  pub fn set_witness(
+    &self,
+    pw: &mut PartialWitness<GoldilocksField>,
+    identity: &Identity,
+    leaf_index: usize,
+    siblings: &[HashOut<GoldilocksField>],
+    external_nullifier: GoldilocksField,
+    signal_hash: HashOut<GoldilocksField>) {
+    self.membership.set_record_witness(pw, identity, leaf_index, siblings);
+    self.signal.set_witness(pw, external_nullifier, signal_hash);
+  }
+}
+
+// Targets of a signal in a group that is an MMR
+pub struct SemaphoreMmrTargets {
+  pub identity: IdentityTarget,
+  // The merkle proof elements with indication whether that hash is on the left
+  pub merkle_proof: Vec<(HashOutTarget, BoolTarget)>,
+  pub peaks: Vec<HashOutTarget>,
+  // Index of the peak of the subtree the commitment is in
+  pub peak_index: Target,
+  pub signal: SignalTargets
+}
+
+impl SemaphoreMmrTargets {
+  // proof is the MMR proof of the identity commitment
+  pub fn set_witness(
+    &self,
+    pw: &mut PartialWitness<GoldilocksField>,
+    identity: &Identity,
+    proof: &MMR_proof,
+    external_nullifier: GoldilocksField,
+    signal_hash: HashOut<GoldilocksField>) {
+    assert!(proof.merkle_proof.len() == self.merkle_proof.len() && proof.peaks.len() == self.peaks.len());
+    self.identity.set_witness(pw, identity);
+    for ((hash_target, on_left_target), (hash, on_left)) in self.merkle_proof.iter().zip(&proof.merkle_proof) {
+      pw.set_hash_target(*hash_target, *hash);
+      pw.set_bool_target(*on_left_target, *on_left);
+    }
+    for (peak_target, peak) in self.peaks.iter().zip(&proof.peaks) {
+      pw.set_hash_target(*peak_target, *peak);
+    }
+    pw.set_target(self.peak_index, GoldilocksField::from_canonical_usize(proof.peak_index()));
+    self.signal.set_witness(pw, external_nullifier, signal_hash);
+  }
+}
+
+// Computes the nullifier of the identity and registers the public inputs: root, nullifier, signal hash and external nullifier
+fn add_signal(builder: &mut CircuitBuilder<GoldilocksField, 2>, identity: &IdentityTarget, root: HashOutTarget) -> SignalTargets {
+  let signal = SignalTargets {
+    external_nullifier: builder.add_virtual_target(),
+    signal_hash: builder.add_virtual_hash()
+  };
+  let tag = builder.constant(GoldilocksField::from_canonical_u64(NULLIFIER_DOMAIN_TAG));
+  let nullifier = builder.hash_n_to_hash_no_pad::<PoseidonHash>([[tag].to_vec(), identity.secret.to_vec(), [signal.external_nullifier].to_vec()].concat());
+  builder.register_public_inputs(&root.elements);
+  builder.register_public_inputs(&nullifier.elements);
+  // The signal hash is bound to the proof by being a public input
+  builder.register_public_inputs(&signal.signal_hash.elements);
+  builder.register_public_input(signal.external_nullifier);
+  signal
+}
+
+// Returns a circuit that proves an identity is in a MerkleTree of the given depth, and outputs its nullifier
+pub fn semaphore_circuit(depth: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, SemaphoreTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let (membership, root) = add_verify_merkle_proof_for_record::<IdentityTarget>(&mut builder, depth);
+  let signal = add_signal(&mut builder, &membership.leaf, root);
+
+  let targets = SemaphoreTargets {
+    membership: membership,
+    signal: signal
+  };
+  (builder.build::<C>(), targets)
+}
+
+// Returns a circuit that proves an identity is in an MMR, with a proof of [nr_merkle_proof_elms] hashes and [nr_peaks] peaks,
+// and outputs its nullifier
+pub fn semaphore_mmr_circuit(
+  nr_merkle_proof_elms: usize,
+  nr_peaks: usize,
+  profile: &ConfigProfile
+) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, SemaphoreMmrTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let identity = IdentityTarget::add_virtual(&mut builder);
+  let commitment = identity.hash(&mut builder);
+  let siblings = builder.add_virtual_hashes(nr_merkle_proof_elms);
+  let siblings_on_left: Vec<BoolTarget> = (0..nr_merkle_proof_elms).map(|_| builder.add_virtual_bool_target_safe()).collect();
+  let peaks = builder.add_virtual_hashes(nr_peaks);
+  let peak_index = builder.add_virtual_target();
+  let root = builder.verify_mmr_membership(commitment, &siblings, &siblings_on_left, &peaks, peak_index);
+  let signal = add_signal(&mut builder, &identity, root);
+
+  let targets = SemaphoreMmrTargets {
+    identity: identity,
+    merkle_proof: siblings.into_iter().zip(siblings_on_left).collect(),
+    peaks: peaks,
+    peak_index: peak_index,
+    signal: signal
+  };
+  (builder.build::<C>(), targets)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::iop::witness::PartialWitness;
+  use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+  use crate::{config_profile::ConfigProfile, mmr::merkle_mountain_ranges::MMR, semaphore::{semaphore::{hash_signal, Identity, NullifierRegistry, SemaphorePublicInputs}, semaphore_plonky2_verifier::{semaphore_circuit, semaphore_mmr_circuit}}, simple_merkle_tree::simple_merkle_tree::MerkleTree};
+
+  #[test]
+  fn test_signal_once_per_epoch() -> Result<()> {
+    let identities: Vec<Identity> = (0..8).map(|_| Identity::random()).collect();
+    let tree = MerkleTree::build_from_hashes(identities.iter().map(|identity| identity.commitment()).collect());
+    let (circuit_data, targets) = semaphore_circuit(tree.count_levels, &ConfigProfile::ZeroKnowledge);
+    let verifier_data = circuit_data.verifier_data();
+    let mut registry = NullifierRegistry::new();
+
+    let prove = |external_nullifier: GoldilocksField, vote: u64| {
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, &identities[5], 5, &tree.get_merkle_proof(5), external_nullifier,
+        hash_signal(&[GoldilocksField::from_canonical_u64(vote)]));
+      circuit_data.prove(pw)
+    };
+
+    let epoch1 = GoldilocksField::from_canonical_u64(1);
+    let public_inputs = registry.verify_and_register(&verifier_data, prove(epoch1, 1)?, tree.root, epoch1)?;
+    assert!(public_inputs.nullifier == identities[5].nullifier(epoch1));
+    assert!(public_inputs.signal_hash == hash_signal(&[GoldilocksField::from_canonical_u64(1)]));
+    // A second signal in the same epoch has the same nullifier
+    assert!(registry.verify_and_register(&verifier_data, prove(epoch1, 2)?, tree.root, epoch1).is_err());
+    // In the next epoch the identity can signal again
+    let epoch2 = GoldilocksField::from_canonical_u64(2);
+    registry.verify_and_register(&verifier_data, prove(epoch2, 2)?, tree.root, epoch2)?;
+
+    // A proof with public inputs missing is rejected, not a panic
+    let epoch3 = GoldilocksField::from_canonical_u64(3);
+    let mut malformed = prove(epoch3, 1)?;
+    malformed.public_inputs.truncate(12);
+    assert!(registry.verify_and_register(&verifier_data, malformed, tree.root, epoch3).is_err());
+    assert!(!registry.is_used(identities[5].nullifier(epoch3)));
+    Ok(())
+  }
+
+  #[test]
+  fn test_signal_in_mmr() -> Result<()> {
+    let identities: Vec<Identity> = (0..11).map(|_| Identity::random()).collect();
+    let mut mmr = MMR::new();
+    for identity in identities.iter() {
+      mmr.add_leaf_hash(identity.commitment());
+    }
+    let root = mmr.clone().bagging_the_peaks();
+    let proof = mmr.get_proof_normal_index(9);
+    let (circuit_data, targets) = semaphore_mmr_circuit(proof.merkle_proof.len(), proof.peaks.len(), &ConfigProfile::ZeroKnowledge);
+
+    let external_nullifier = GoldilocksField::from_canonical_u64(7);
+    let signal_hash = hash_signal(&[GoldilocksField::from_canonical_u64(42)]);
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &identities[9], &proof, external_nullifier, signal_hash);
+    let plonky2_proof = circuit_data.prove(pw)?;
+    let public_inputs = SemaphorePublicInputs::from_public_inputs(&plonky2_proof.public_inputs)?;
+    assert!(public_inputs == SemaphorePublicInputs {
+      root: root,
+      nullifier: identities[9].nullifier(external_nullifier),
+      signal_hash: signal_hash,
+      external_nullifier: external_nullifier
+    });
+    circuit_data.verify(plonky2_proof)
+  }
+
+  // An identity that isn't in the group can't signal, even with the proof of another commitment
+  #[test]
+  #[should_panic]
+  fn test_signal_unknown_identity() {
+    let identities: Vec<Identity> = (0..4).map(|_| Identity::random()).collect();
+    let tree = MerkleTree::build_from_hashes(identities.iter().map(|identity| identity.commitment()).collect());
+    let (circuit_data, targets) = semaphore_circuit(tree.count_levels, &ConfigProfile::default());
+
+    let outsider = Identity { secret: identities[1].secret, trapdoor: Identity::random().trapdoor };
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &outsider, 1, &tree.get_merkle_proof(1), GoldilocksField::ONE,
+      hash_signal(&[GoldilocksField::ONE]));
+    circuit_data.prove(pw).unwrap();
+  }
+
+  // The children of an internal node are no identity, so they can't signal with the proof of that node in the tree
+  // of the level above, which has the same root
+  #[test]
+  #[should_panic]
+  fn test_signal_internal_node() {
+    let identities: Vec<Identity> = (0..8).map(|_| Identity::random()).collect();
+    let tree = MerkleTree::build_from_hashes(identities.iter().map(|identity| identity.commitment()).collect());
+    let children = tree.level(0);
+    let forged = Identity { secret: children[2].elements, trapdoor: children[3].elements };
+    let upper_tree = MerkleTree::build_from_hashes(tree.level(1).to_vec());
+    assert!(upper_tree.root == tree.root);
+    let (circuit_data, targets) = semaphore_circuit(upper_tree.count_levels, &ConfigProfile::default());
+
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &forged, 1, &upper_tree.get_merkle_proof(1), GoldilocksField::ONE,
+      hash_signal(&[GoldilocksField::ONE]));
+    circuit_data.prove(pw).unwrap();
+  }
+}
@@ -0,0 +1,173 @@
+use plonky2::{hash::hash_types::{HashOut, HashOutTarget}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, leaf::LeafTarget};
+
+/**
+ * Circuits for Merkle proofs of the simple Merkle Tree.
+ *
+ * The leaf index is a target that is split into depth bits, which pick the direction at every level.
+ * This way a single circuit per depth verifies a proof for any leaf, instead of a circuit per leaf index.
+ */
+
+// Targets of a Merkle proof that need to be set in the witness
+// By default the leaf is a single field element; for other leaves see add_verify_merkle_proof_for_record
+pub struct MerkleProofTargets<L: LeafTarget = Target> {
+  pub leaf: L,
+  pub leaf_index: Target,
+  pub siblings: Vec<HashOutTarget>
+}
+
+impl MerkleProofTargets<Target> {
+  // siblings is the proof as returned by MerkleTree::get_merkle_proof
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, leaf: GoldilocksField, leaf_index: usize, siblings: &[HashOut<GoldilocksField>]) {
+    assert!(siblings.len() == self.siblings.len());
+    pw.set_target(self.leaf, leaf);
+    pw.set_target(self.leaf_index, GoldilocksField::from_canonical_usize(leaf_index));
+    for i in 0..self.siblings.len() {
+      pw.set_hash_target(self.siblings[i], siblings[i]);
+    }
+  }
+}
+
+impl<L: LeafTarget> MerkleProofTargets<L> {
+  pub fn set_record_witness(&self, pw: &mut PartialWitness<GoldilocksField>, leaf: &L::Value, leaf_index: usize, siblings: &[HashOut<GoldilocksField>]) {
+    assert!(siblings.len() == self.siblings.len());
+    self.leaf.set_witness(pw, leaf);
+    pw.set_target(self.leaf_index, GoldilocksField::from_canonical_usize(leaf_index));
+    for i in 0..self.siblings.len() {
+      pw.set_hash_target(self.siblings[i], siblings[i]);
+    }
+  }
+}
+
+// Hashes the leaf up to the root, where the index bits determine the direction at every level. Returns the root
+pub fn compute_root_circuit(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  leaf_hash: HashOutTarget,
+  index_bits: &[BoolTarget],
+  siblings: &[HashOutTarget]) -> HashOutTarget {
+  builder.verify_merkle_path(leaf_hash, siblings, index_bits)
+}
+
+// Adds the constraints of a Merkle proof of the given depth to the builder, to embed it in a larger circuit
+// Returns the targets and the root the proof leads to; registering or connecting the root is up to the caller
+pub fn add_verify_merkle_proof(builder: &mut CircuitBuilder<GoldilocksField, 2>, depth: usize) -> (MerkleProofTargets, HashOutTarget) {
+  add_verify_merkle_proof_for_record::<Target>(builder, depth)
+}
+
+// Same as add_verify_merkle_proof, for any kind of leaf. The targets of the leaf can be constrained further by the caller
+pub fn add_verify_merkle_proof_for_record<L: LeafTarget>(builder: &mut CircuitBuilder<GoldilocksField, 2>, depth: usize) -> (MerkleProofTargets<L>, HashOutTarget) {
+  let targets = MerkleProofTargets {
+    leaf: L::add_virtual(builder),
+    leaf_index: builder.add_virtual_target(),
+    siblings: builder.add_virtual_hashes(depth)
+  };
+  // Splitting the index in depth bits also checks it is within the tree
+  let index_bits = builder.split_le(targets.leaf_index, depth);
+  let leaf_hash = targets.leaf.hash(builder);
+  let root = compute_root_circuit(builder, leaf_hash, &index_bits, &targets.siblings);
+  (targets, root)
+}
+
+// Returns a circuit that verifies a Merkle proof for any leaf of a tree with the given depth
+// Public inputs are the root, followed by the leaf index if index_is_public is set
+// With ConfigProfile::ZeroKnowledge and a private index, the proof reveals nothing but the root
+pub fn verify_merkle_proof_circuit(depth: usize, index_is_public: bool, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MerkleProofTargets) {
+  verify_merkle_proof_circuit_for_record::<Target>(depth, index_is_public, profile, |_, _| {})
+}
+
+// Returns a circuit that verifies a Merkle proof for any leaf of the given kind, in a tree with the given depth
+// After the membership check, constrain_leaf can add constraints on the fields of the leaf, and register public inputs
+// Public inputs are the root, followed by the leaf index if index_is_public is set, followed by those of constrain_leaf
+pub fn verify_merkle_proof_circuit_for_record<L: LeafTarget>(
+  depth: usize,
+  index_is_public: bool,
+  profile: &ConfigProfile,
+  constrain_leaf: impl FnOnce(&mut CircuitBuilder<GoldilocksField, 2>, &L)) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MerkleProofTargets<L>) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let (targets, root) = add_verify_merkle_proof_for_record::<L>(&mut builder, depth);
+  builder.register_public_inputs(&root.elements);
+  if index_is_public {
+    builder.register_public_input(targets.leaf_index);
+  }
+  constrain_leaf(&mut builder, &targets.leaf);
+
+  (builder.build::<C>(), targets)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, iop::witness::PartialWitness};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::common::GOLDILOCKS_FIELD_ORDER, simple_merkle_tree::simple_merkle_tree::MerkleTree};
+  use super::verify_merkle_proof_circuit;
+
+  fn random_leaves(nr_leaves: usize) -> Vec<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    (0..nr_leaves).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect()
+  }
+
+  #[test]
+  fn test_one_circuit_for_all_leaves() -> Result<()> {
+    let leaves = random_leaves(16);
+    let tree = MerkleTree::build(leaves.clone());
+    let (circuit_data, targets) = verify_merkle_proof_circuit(tree.count_levels, false, &ConfigProfile::default());
+
+    for leaf_index in [0, 5, 10, 15] {
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, leaves[leaf_index], leaf_index, &tree.get_merkle_proof(leaf_index));
+      let proof = circuit_data.prove(pw)?;
+      assert!(proof.public_inputs == tree.root.elements.to_vec());
+      circuit_data.verify(proof)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_public_index() -> Result<()> {
+    let leaves = random_leaves(32);
+    let tree = MerkleTree::build(leaves.clone());
+    let (circuit_data, targets) = verify_merkle_proof_circuit(tree.count_levels, true, &ConfigProfile::default());
+
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, leaves[19], 19, &tree.get_merkle_proof(19));
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs == [tree.root.elements.to_vec(), [GoldilocksField::from_canonical_u64(19)].to_vec()].concat());
+    circuit_data.verify(proof)
+  }
+
+  #[test]
+  fn test_wrong_index() -> Result<()> {
+    let leaves = random_leaves(16);
+    let tree = MerkleTree::build(leaves.clone());
+    let (circuit_data, targets) = verify_merkle_proof_circuit(tree.count_levels, false, &ConfigProfile::default());
+
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, leaves[3], 2, &tree.get_merkle_proof(3));
+    // The proof is valid, but for a different root
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs != tree.root.elements.to_vec());
+    Ok(())
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_index_out_of_range() {
+    let leaves = random_leaves(16);
+    let tree = MerkleTree::build(leaves.clone());
+    let (circuit_data, targets) = verify_merkle_proof_circuit(tree.count_levels, false, &ConfigProfile::default());
+
+    let mut pw = PartialWitness::new();
+    // 16 + 3 has the same lower 4 bits as 3, but doesn't fit in 4 bits
+    targets.set_witness(&mut pw, leaves[3], 19, &tree.get_merkle_proof(3));
+    circuit_data.prove(pw).unwrap();
+  }
+}
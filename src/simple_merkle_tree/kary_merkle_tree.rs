@@ -0,0 +1,193 @@
+// k-ary Merkle Tree impl
+
+use itertools::Itertools;
+use rayon::prelude::*;
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+/**
+ * Merkle Tree where every node has arity children, with arity 2, 4 or 8.
+ *
+ * A node is the hash of the elements of all its children, in order, with a sponge of rate 8: a node of arity 2 takes
+ * 1 permutation, of arity 4 takes 2 and of arity 8 takes 4. Per binary level of the path that is 1 permutation for
+ * arity 2 and 4, but 4/3 for arity 8, so an octary path takes a third more permutations than a binary one. A wider
+ * tree does have fewer levels, and in a circuit every level only needs to place the node among its siblings once.
+ *
+ * For arity 2 the node hash is the same as PoseidonHash::two_to_one, so the tree equals MerkleTree over the same leaves.
+ * Leaves are padded with zero hashes up to a power of the arity.
+ *
+ * MMRs stay binary: their layout and peaks follow from the binary representation of the number of leaves.
+ */
+#[derive(Debug, Clone)]
+pub struct KaryMerkleTree {
+  pub arity: usize,
+  // Number of levels above the leaves, which is the number of sibling groups in a proof
+  pub depth: usize,
+  // levels[0] holds the hashed leaves, levels[depth] the root
+  levels: Vec<Vec<HashOut<GoldilocksField>>>,
+  pub root: HashOut<GoldilocksField>,
+  pub nr_leaves: usize
+}
+
+// The siblings of a node at one level, and the position of the node among its siblings
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiblingGroup {
+  // In 0..arity
+  pub position: usize,
+  // The arity - 1 other children of the parent, in order, leaving out the node itself
+  pub siblings: Vec<HashOut<GoldilocksField>>
+}
+
+// Levels with at least this many nodes are hashed in parallel
+const PARALLEL_LEVEL_THRESHOLD: usize = 1 << 8;
+
+pub fn is_supported_arity(arity: usize) -> bool {
+  arity == 2 || arity == 4 || arity == 8
+}
+
+// Hashes the children of a node, in order
+pub fn hash_children(children: &[HashOut<GoldilocksField>]) -> HashOut<GoldilocksField> {
+  let elements: Vec<GoldilocksField> = children.iter().flat_map(|child| child.elements).collect_vec();
+  PoseidonHash::hash_no_pad(&elements)
+}
+
+impl KaryMerkleTree {
+  // Create a k-ary Merkle Tree of the given arity. The leaves are padded with zero hashes up to a power of the arity
+  pub fn build(leaves: Vec<GoldilocksField>, arity: usize) -> Self {
+    let level0: Vec<HashOut<GoldilocksField>> = leaves.into_iter().map(|leaf| PoseidonHash::hash_or_noop(&[leaf])).collect();
+    Self::build_from_hashes(level0, arity)
+  }
+
+  // Create a k-ary Merkle Tree given the hashes of the leaves
+  pub fn build_from_hashes(level0: Vec<HashOut<GoldilocksField>>, arity: usize) -> Self {
+    assert!(is_supported_arity(arity), "arity must be 2, 4 or 8");
+    assert!(!level0.is_empty());
+    let nr_leaves = level0.len();
+
+    let mut depth = 0;
+    let mut padded_size = 1;
+    while padded_size < nr_leaves {
+      padded_size *= arity;
+      depth += 1;
+    }
+
+    let mut levels = [level0].to_vec();
+    levels[0].resize(padded_size, HashOut::ZERO);
+    for i in 0..depth {
+      let current_level = &levels[i];
+      let next_level: Vec<HashOut<GoldilocksField>> = if current_level.len() / arity >= PARALLEL_LEVEL_THRESHOLD {
+        current_level.par_chunks(arity).map(hash_children).collect()
+      } else {
+        current_level.chunks(arity).map(hash_children).collect()
+      };
+      levels.push(next_level);
+    }
+
+    KaryMerkleTree {
+      arity: arity,
+      depth: depth,
+      root: levels[depth][0],
+      levels: levels,
+      nr_leaves: nr_leaves
+    }
+  }
+
+  // Returns the hashes at level i, where level 0 holds the hashed leaves and level depth the root
+  pub fn level(&self, i: usize) -> &[HashOut<GoldilocksField>] {
+    &self.levels[i]
+  }
+
+  // Returns depth sibling groups that together with the leaf show that a leaf is part of this tree, given the root
+  // Starts at the lowest level and goes up
+  pub fn get_merkle_proof(&self, leaf_index: usize) -> Vec<SiblingGroup> {
+    assert!(leaf_index < self.nr_leaves);
+
+    let mut proof = Vec::new();
+    let mut updated_index = leaf_index;
+    for i in 0..self.depth {
+      let first_child = updated_index - updated_index % self.arity;
+      let position = updated_index % self.arity;
+      let siblings = (0..self.arity)
+        .filter(|j| *j != position)
+        .map(|j| self.levels[i][first_child + j])
+        .collect();
+      proof.push(SiblingGroup { position: position, siblings: siblings });
+      updated_index = updated_index / self.arity;
+    }
+    proof
+  }
+}
+
+// Returns true if the given proof leads to the root, where the positions of the proof must match the leaf index
+pub fn verify_kary_merkle_proof(leaf: GoldilocksField, leaf_index: usize, arity: usize, root: HashOut<GoldilocksField>, proof: &[SiblingGroup]) -> bool {
+  let mut next_hash: HashOut<GoldilocksField> = PoseidonHash::hash_or_noop(&[leaf]);
+  let mut updated_index = leaf_index;
+  for group in proof {
+    if group.position != updated_index % arity || group.siblings.len() != arity - 1 {
+      return false;
+    }
+    let mut children = group.siblings.clone();
+    children.insert(group.position, next_hash);
+    next_hash = hash_children(&children);
+    updated_index = updated_index / arity;
+  }
+  // The index must fit in the tree
+  updated_index == 0 && next_hash == root
+}
+
+#[cfg(test)]
+mod tests {
+  use plonky2::field::{goldilocks_field::GoldilocksField, types::Field};
+  use rand::Rng;
+
+  use crate::{mmr::common::GOLDILOCKS_FIELD_ORDER, simple_merkle_tree::simple_merkle_tree::MerkleTree};
+  use super::{KaryMerkleTree, verify_kary_merkle_proof};
+
+  fn random_leaves(nr_leaves: usize) -> Vec<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    (0..nr_leaves).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect()
+  }
+
+  #[test]
+  fn test_binary_is_merkle_tree() {
+    let leaves = random_leaves(16);
+    let tree = KaryMerkleTree::build(leaves.clone(), 2);
+    let binary_tree = MerkleTree::build(leaves);
+    assert!(tree.root == binary_tree.root);
+    assert!(tree.depth == binary_tree.count_levels);
+    for group in tree.get_merkle_proof(5).iter().zip(binary_tree.get_merkle_proof(5)) {
+      assert!(group.0.siblings == [group.1].to_vec());
+    }
+  }
+
+  #[test]
+  fn test_proofs() {
+    for arity in [2, 4, 8] {
+      // Not a power of the arity, so the leaves are padded
+      let leaves = random_leaves(100);
+      let tree = KaryMerkleTree::build(leaves.clone(), arity);
+      for i in 0..leaves.len() {
+        let proof = tree.get_merkle_proof(i);
+        assert!(proof.len() == tree.depth);
+        assert!(verify_kary_merkle_proof(leaves[i], i, arity, tree.root, &proof));
+      }
+      assert!(!verify_kary_merkle_proof(leaves[3], 4, arity, tree.root, &tree.get_merkle_proof(3)));
+      assert!(!verify_kary_merkle_proof(leaves[3], 3, arity, tree.root, &tree.get_merkle_proof(4)));
+    }
+  }
+
+  #[test]
+  fn test_depth() {
+    assert!(KaryMerkleTree::build(random_leaves(64), 4).depth == 3);
+    assert!(KaryMerkleTree::build(random_leaves(65), 8).depth == 3);
+    assert!(KaryMerkleTree::build(random_leaves(1), 8).depth == 0);
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_unsupported_arity() {
+    KaryMerkleTree::build(random_leaves(9), 3);
+  }
+}
@@ -0,0 +1,182 @@
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::HashOutTarget}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::{config_profile::ConfigProfile, simple_merkle_tree::kary_merkle_tree::{SiblingGroup, is_supported_arity}};
+
+/**
+ * Circuit for Merkle proofs of the k-ary Merkle Tree.
+ *
+ * Like the binary circuit, the leaf index is split into bits; every log2(arity) bits give the position of the node
+ * among its siblings. The children of a level are put in order with selects, after which they are hashed once.
+ */
+
+// Targets of a k-ary Merkle proof that need to be set in the witness
+pub struct KaryMerkleProofTargets {
+  pub arity: usize,
+  pub leaf: Target,
+  pub leaf_index: Target,
+  // Per level the arity - 1 siblings
+  pub siblings: Vec<Vec<HashOutTarget>>
+}
+
+impl KaryMerkleProofTargets {
+  // proof is the proof as returned by KaryMerkleTree::get_merkle_proof; its positions follow from the leaf index
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, leaf: GoldilocksField, leaf_index: usize, proof: &[SiblingGroup]) {
+    assert!(proof.len() == self.siblings.len());
+    pw.set_target(self.leaf, leaf);
+    pw.set_target(self.leaf_index, GoldilocksField::from_canonical_usize(leaf_index));
+    for (targets, group) in self.siblings.iter().zip(proof) {
+      assert!(group.siblings.len() == self.arity - 1);
+      for (target, sibling) in targets.iter().zip(&group.siblings) {
+        pw.set_hash_target(*target, *sibling);
+      }
+    }
+  }
+}
+
+// Returns the arity children of a node in order, with node at the position given by position_bits (little endian)
+fn order_children(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  node: HashOutTarget,
+  position_bits: &[BoolTarget],
+  siblings: &[HashOutTarget]) -> Vec<HashOutTarget> {
+  let arity = siblings.len() + 1;
+  let mut children = Vec::new();
+  // Whether the node is at a position before the current one, in which case the siblings are shifted by one
+  let mut passed = builder._false();
+  for j in 0..arity {
+    // The node is at position j if all bits match those of j
+    let mut is_at_j = builder._true();
+    for (k, bit) in position_bits.iter().enumerate() {
+      let matches = if (j >> k) & 1 == 1 { *bit } else { builder.not(*bit) };
+      is_at_j = builder.and(is_at_j, matches);
+    }
+
+    let sibling = if j == 0 {
+      siblings[0]
+    } else if j == arity - 1 {
+      siblings[arity - 2]
+    } else {
+      builder.select_hash(passed, siblings[j - 1], siblings[j])
+    };
+    children.push(builder.select_hash(is_at_j, node, sibling));
+    // At most one position matches, so adding is the same as or
+    passed = BoolTarget::new_unsafe(builder.add(passed.target, is_at_j.target));
+  }
+  children
+}
+
+// Hashes the leaf up to the root, where the index bits give the position at every level. Returns the root
+pub fn compute_kary_root_circuit(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  leaf_hash: HashOutTarget,
+  index_bits: &[BoolTarget],
+  siblings: &[Vec<HashOutTarget>]) -> HashOutTarget {
+  let arity = siblings.first().map_or(2, |group| group.len() + 1);
+  let bits_per_level = arity.trailing_zeros() as usize;
+  let mut next_hash = leaf_hash;
+  for (position_bits, group) in index_bits.chunks(bits_per_level).zip(siblings) {
+    let children = order_children(builder, next_hash, position_bits, group);
+    let elements: Vec<Target> = children.iter().flat_map(|child| child.elements).collect();
+    next_hash = builder.hash_n_to_hash_no_pad::<PoseidonHash>(elements);
+  }
+  next_hash
+}
+
+// Adds the constraints of a k-ary Merkle proof of the given arity and depth to the builder
+// Returns the targets and the root the proof leads to; registering or connecting the root is up to the caller
+pub fn add_verify_kary_merkle_proof(builder: &mut CircuitBuilder<GoldilocksField, 2>, arity: usize, depth: usize) -> (KaryMerkleProofTargets, HashOutTarget) {
+  assert!(is_supported_arity(arity), "arity must be 2, 4 or 8");
+  let targets = KaryMerkleProofTargets {
+    arity: arity,
+    leaf: builder.add_virtual_target(),
+    leaf_index: builder.add_virtual_target(),
+    siblings: (0..depth).map(|_| builder.add_virtual_hashes(arity - 1)).collect()
+  };
+  // Splitting the index in bits also checks it is within the tree
+  let index_bits = builder.split_le(targets.leaf_index, depth * arity.trailing_zeros() as usize);
+  let leaf_hash = builder.hash_or_noop::<PoseidonHash>([targets.leaf].to_vec());
+  let root = compute_kary_root_circuit(builder, leaf_hash, &index_bits, &targets.siblings);
+  (targets, root)
+}
+
+// Returns a circuit that verifies a Merkle proof for any leaf of a k-ary tree with the given arity and depth
+// The circuit is built with the CircuitConfig of [profile]
+// Public inputs are the root, followed by the leaf index if index_is_public is set
+pub fn verify_kary_merkle_proof_circuit(arity: usize, depth: usize, index_is_public: bool, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, KaryMerkleProofTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let (targets, root) = add_verify_kary_merkle_proof(&mut builder, arity, depth);
+  builder.register_public_inputs(&root.elements);
+  if index_is_public {
+    builder.register_public_input(targets.leaf_index);
+  }
+
+  (builder.build::<C>(), targets)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, iop::witness::PartialWitness};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, gadgets::count_poseidon_permutations, mmr::common::GOLDILOCKS_FIELD_ORDER, simple_merkle_tree::{kary_merkle_tree::KaryMerkleTree, circuit::verify_merkle_proof_circuit}};
+  use super::verify_kary_merkle_proof_circuit;
+
+  fn random_leaves(nr_leaves: usize) -> Vec<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    (0..nr_leaves).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect()
+  }
+
+  #[test]
+  fn test_all_arities() -> Result<()> {
+    for arity in [2, 4, 8] {
+      let leaves = random_leaves(64);
+      let tree = KaryMerkleTree::build(leaves.clone(), arity);
+      let (circuit_data, targets) = verify_kary_merkle_proof_circuit(arity, tree.depth, true, &ConfigProfile::default());
+
+      for leaf_index in [0, 13, 42, 63] {
+        let mut pw = PartialWitness::new();
+        targets.set_witness(&mut pw, leaves[leaf_index], leaf_index, &tree.get_merkle_proof(leaf_index));
+        let proof = circuit_data.prove(pw)?;
+        assert!(proof.public_inputs[0..4] == tree.root.elements);
+        assert!(proof.public_inputs[4] == GoldilocksField::from_canonical_usize(leaf_index));
+        circuit_data.verify(proof)?;
+      }
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_permutations_per_arity() {
+    // 2^12 leaves: depth 12 in a binary tree, 6 for arity 4 and 4 for arity 8
+    let (binary_data, _) = verify_merkle_proof_circuit(12, false, &ConfigProfile::default());
+    let (quaternary_data, _) = verify_kary_merkle_proof_circuit(4, 6, false, &ConfigProfile::default());
+    let (octary_data, _) = verify_kary_merkle_proof_circuit(8, 4, false, &ConfigProfile::default());
+
+    // A node takes 1, 2 or 4 permutations, and hashing the public inputs one more
+    assert!(count_poseidon_permutations(&binary_data) == 12 + 1);
+    assert!(count_poseidon_permutations(&quaternary_data) == 6 * 2 + 1);
+    assert!(count_poseidon_permutations(&octary_data) == 4 * 4 + 1);
+    assert!(quaternary_data.common.degree() <= binary_data.common.degree());
+    assert!(octary_data.common.degree() <= binary_data.common.degree());
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_wrong_index() {
+    let leaves = random_leaves(64);
+    let tree = KaryMerkleTree::build(leaves.clone(), 4);
+    let (circuit_data, targets) = verify_kary_merkle_proof_circuit(4, tree.depth, false, &ConfigProfile::default());
+
+    let mut pw = PartialWitness::new();
+    // 64 + 3 doesn't fit in the bits of the tree
+    targets.set_witness(&mut pw, leaves[3], 67, &tree.get_merkle_proof(3));
+    circuit_data.prove(pw).unwrap();
+  }
+}
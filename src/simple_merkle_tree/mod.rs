@@ -1 +1,8 @@
-pub mod simple_merkle_tree;
\ No newline at end of file
+pub mod simple_merkle_tree;
+pub mod circuit;
+pub mod root_update_plonky2_verifier;
+pub mod multiproof_plonky2_verifier;
+pub mod plonky2_interop;
+pub mod recursive_merkle_prover;
+pub mod kary_merkle_tree;
+pub mod kary_plonky2_verifier;
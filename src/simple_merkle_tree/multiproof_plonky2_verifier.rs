@@ -0,0 +1,267 @@
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, simple_merkle_tree::simple_merkle_tree::MerkleMultiproof};
+
+/**
+ * Circuit for multiproofs of the simple Merkle Tree.
+ *
+ * The leaf indices are targets that are split into depth bits, like in the circuit for a single Merkle proof, so one
+ * circuit per depth and number of leaves serves every set of indices. The indices must be strictly increasing.
+ *
+ * Every level holds a fixed number of slots for the known nodes, in order of their index: the k leaves at the bottom,
+ * and min(k, 2^(depth - l)) nodes at level l above them, since a level has no more nodes. A node is hashed with the
+ * next node when that is its sibling, and with a helper otherwise; the parents that are computed are moved to the front
+ * of the next level with selects. So every slot takes a single hash, which is fewer Poseidon permutations than k
+ * separate paths of depth hashes each.
+ */
+
+// Targets of a multiproof that need to be set in the witness
+pub struct MultiproofTargets {
+  pub leaves: Vec<Target>,
+  pub leaf_indices: Vec<Target>,
+  // Per level a helper for every slot; slots that are hashed with the next node, or are empty, get a zero hash
+  pub helpers: Vec<Vec<HashOutTarget>>
+}
+
+impl MultiproofTargets {
+  // The leaves are given in the order of proof.leaf_indices
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, leaves: &[GoldilocksField], proof: &MerkleMultiproof) {
+    assert!(leaves.len() == self.leaves.len() && proof.leaf_indices.len() == self.leaves.len());
+    assert!(proof.depth == self.helpers.len());
+    for i in 0..self.leaves.len() {
+      pw.set_target(self.leaves[i], leaves[i]);
+      pw.set_target(self.leaf_indices[i], GoldilocksField::from_canonical_usize(proof.leaf_indices[i]));
+    }
+
+    // The known nodes of a level are in the slots in order, so the helper of the i-th node goes in slot i
+    let mut flags = proof.flags.iter();
+    let mut helpers = proof.helpers.iter();
+    let mut indices = proof.leaf_indices.clone();
+    for level in 0..proof.depth {
+      let mut level_helpers = [HashOut::ZERO].repeat(self.helpers[level].len());
+      let mut i = 0;
+      while i < indices.len() {
+        if *flags.next().unwrap() {
+          level_helpers[i] = *helpers.next().unwrap();
+        } else {
+          i += 1;
+        }
+        i += 1;
+      }
+      for (target, helper) in self.helpers[level].iter().zip(level_helpers) {
+        pw.set_hash_target(*target, helper);
+      }
+      indices = indices.iter().map(|index| index / 2).collect();
+      indices.dedup();
+    }
+  }
+}
+
+// A slot of a level in the circuit: the bits of the index of the node within its level, its hash, and whether the
+// slot holds a node
+struct Slot {
+  index_bits: Vec<BoolTarget>,
+  hash: HashOutTarget,
+  active: BoolTarget
+}
+
+// Returns a circuit that verifies that nr_leaves leaves, at strictly increasing indices, are in a Merkle tree of the given depth
+// The circuit is built with the CircuitConfig of [profile]
+// Public inputs are the root, followed by the leaves in order of their indices, followed by the indices if indices_are_public is set
+pub fn verify_multiproof_circuit(depth: usize, nr_leaves: usize, indices_are_public: bool, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MultiproofTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+  assert!(nr_leaves > 0 && nr_leaves <= 1 << depth);
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let leaves = builder.add_virtual_targets(nr_leaves);
+  let leaf_indices = builder.add_virtual_targets(nr_leaves);
+
+  // Splitting the indices in depth bits also checks they are within the tree
+  let mut slots: Vec<Slot> = leaves.iter().zip(&leaf_indices).map(|(leaf, index)| Slot {
+    index_bits: builder.split_le(*index, depth),
+    hash: builder.hash_or_noop::<PoseidonHash>([*leaf].to_vec()),
+    active: builder._true()
+  }).collect();
+  for i in 1..nr_leaves {
+    let difference = builder.sub(leaf_indices[i], leaf_indices[i - 1]);
+    let one = builder.one();
+    let gap = builder.sub(difference, one);
+    builder.range_check(gap, depth);
+  }
+
+  let mut helpers = Vec::new();
+  for level in 0..depth {
+    let level_helpers = builder.add_virtual_hashes(slots.len());
+    let nr_next_slots = nr_leaves.min(1 << (depth - level - 1));
+
+    // 1. For every slot, the children of its parent, and whether the parent has to be computed from this slot:
+    //  not when the slot is empty, or when its node is the right sibling of the node in the previous slot
+    let mut children = Vec::new();
+    let mut keep = Vec::new();
+    let mut previous_starts_pair = builder._false();
+    for i in 0..slots.len() {
+      let slot = &slots[i];
+      let starts_pair = if i + 1 < slots.len() {
+        let index = builder.le_sum(slot.index_bits.iter());
+        let next_index = builder.le_sum(slots[i + 1].index_bits.iter());
+        let one = builder.one();
+        let index_plus_one = builder.add(index, one);
+        let is_next = builder.is_equal(next_index, index_plus_one);
+        let is_left = builder.not(slot.index_bits[0]);
+        let is_sibling = builder.and(is_next, is_left);
+        builder.and(is_sibling, slots[i + 1].active)
+      } else {
+        builder._false()
+      };
+      let sibling = if i + 1 < slots.len() {
+        builder.select_hash(starts_pair, slots[i + 1].hash, level_helpers[i])
+      } else {
+        level_helpers[i]
+      };
+      children.push(builder.conditional_swap_hash(slot.index_bits[0], slot.hash, sibling));
+      let not_second = builder.not(previous_starts_pair);
+      keep.push(builder.and(slot.active, not_second));
+      previous_starts_pair = starts_pair;
+    }
+
+    // 2. Move the slots that are kept to the front, and hash their children once per slot of the next level
+    let mut ranks = Vec::new();
+    let mut rank = builder.zero();
+    for i in 0..slots.len() {
+      ranks.push(rank);
+      rank = builder.add(rank, keep[i].target);
+    }
+    let mut moved: Vec<Target> = [builder.zero()].repeat(slots.len());
+    let mut next_slots = Vec::new();
+    for j in 0..nr_next_slots {
+      let mut left = [builder.zero(); 4];
+      let mut right = [builder.zero(); 4];
+      let mut index_bits = [builder.zero()].repeat(depth - level - 1);
+      let mut active = builder.zero();
+      // A slot can only move to the front
+      for i in j..slots.len() {
+        let position = builder.constant(GoldilocksField::from_canonical_usize(j));
+        let at_position = builder.is_equal(ranks[i], position);
+        let selected = builder.and(keep[i], at_position);
+        for e in 0..4 {
+          left[e] = builder.mul_add(selected.target, children[i].0.elements[e], left[e]);
+          right[e] = builder.mul_add(selected.target, children[i].1.elements[e], right[e]);
+        }
+        for b in 0..index_bits.len() {
+          index_bits[b] = builder.mul_add(selected.target, slots[i].index_bits[b + 1].target, index_bits[b]);
+        }
+        active = builder.add(active, selected.target);
+        moved[i] = builder.add(moved[i], selected.target);
+      }
+      next_slots.push(Slot {
+        index_bits: index_bits.into_iter().map(BoolTarget::new_unsafe).collect(),
+        hash: builder.hash_or_noop::<PoseidonHash>([left, right].concat()),
+        active: BoolTarget::new_unsafe(active)
+      });
+    }
+    // Every kept slot must have been moved, so no parent is left out
+    for i in 0..slots.len() {
+      builder.connect(moved[i], keep[i].target);
+    }
+
+    helpers.push(level_helpers);
+    slots = next_slots;
+  }
+
+  builder.register_public_inputs(&slots[0].hash.elements);
+  builder.register_public_inputs(&leaves);
+  if indices_are_public {
+    builder.register_public_inputs(&leaf_indices);
+  }
+
+  let targets = MultiproofTargets {
+    leaves: leaves,
+    leaf_indices: leaf_indices,
+    helpers: helpers
+  };
+  (builder.build::<C>(), targets)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, iop::witness::PartialWitness};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, gadgets::count_poseidon_permutations, mmr::common::GOLDILOCKS_FIELD_ORDER, simple_merkle_tree::{circuit::verify_merkle_proof_circuit, simple_merkle_tree::MerkleTree}};
+  use super::verify_multiproof_circuit;
+
+  fn random_leaves(nr_leaves: usize) -> Vec<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    (0..nr_leaves).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect()
+  }
+
+  #[test]
+  fn test_multiproof_circuit() -> Result<()> {
+    let leaves = random_leaves(64);
+    let tree = MerkleTree::build(leaves.clone());
+    let (circuit_data, targets) = verify_multiproof_circuit(tree.count_levels, 6, true, &ConfigProfile::default());
+
+    // The same circuit for different sets of indices
+    for indices in [[3, 7, 8, 40, 41, 63], [0, 1, 2, 3, 4, 5], [10, 11, 20, 21, 30, 31]] {
+      let multiproof = tree.get_multiproof(&indices);
+      let proven_leaves: Vec<GoldilocksField> = multiproof.leaf_indices.iter().map(|i| leaves[*i]).collect();
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, &proven_leaves, &multiproof);
+      let proof = circuit_data.prove(pw)?;
+      let public_indices: Vec<GoldilocksField> = indices.iter().map(|i| GoldilocksField::from_canonical_usize(*i)).collect();
+      assert!(proof.public_inputs == [tree.root.elements.to_vec(), proven_leaves, public_indices].concat());
+      circuit_data.verify(proof)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_fewer_permutations_than_paths() {
+    let (circuit_data, _) = verify_multiproof_circuit(6, 6, false, &ConfigProfile::default());
+    let (path_data, _) = verify_merkle_proof_circuit(6, false, &ConfigProfile::default());
+
+    // At most 6 nodes per level, and fewer than 6 in the top 3 levels. Hashing the 10 public inputs takes 2 more
+    let multiproof_permutations = count_poseidon_permutations(&circuit_data) - 2;
+    assert!(multiproof_permutations == 6 + 6 + 6 + 4 + 2 + 1);
+    // A single path takes a permutation per level, and 1 for its public inputs
+    let path_permutations = count_poseidon_permutations(&path_data) - 1;
+    assert!(path_permutations == 6);
+    assert!(multiproof_permutations < 6 * path_permutations);
+  }
+
+  #[test]
+  fn test_multiproof_circuit_wrong_leaf() -> Result<()> {
+    let leaves = random_leaves(16);
+    let tree = MerkleTree::build(leaves.clone());
+    let indices = [1, 2, 11];
+    let (circuit_data, targets) = verify_multiproof_circuit(tree.count_levels, 3, false, &ConfigProfile::default());
+
+    let multiproof = tree.get_multiproof(&indices);
+    let wrong_leaves = [leaves[1], leaves[3], leaves[11]];
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &wrong_leaves, &multiproof);
+    // The proof is valid, but for a different root
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs[0..4] != tree.root.elements);
+    Ok(())
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_unsorted_indices() {
+    let leaves = random_leaves(16);
+    let tree = MerkleTree::build(leaves.clone());
+    let (circuit_data, targets) = verify_multiproof_circuit(tree.count_levels, 2, false, &ConfigProfile::default());
+
+    let mut multiproof = tree.get_multiproof(&[4, 9]);
+    multiproof.leaf_indices.reverse();
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &[leaves[9], leaves[4]], &multiproof);
+    circuit_data.prove(pw).unwrap();
+  }
+}
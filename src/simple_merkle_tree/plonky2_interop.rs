@@ -0,0 +1,214 @@
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, MerkleCapTarget}, merkle_proofs::{MerkleProof, MerkleProofTarget}, merkle_tree::{MerkleCap, MerkleTree as Plonky2Tree}}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig, Hasher}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, iop::{target::Target, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::{config_profile::ConfigProfile, simple_merkle_tree::simple_merkle_tree::{MerkleTree, PaddingPolicy}};
+
+/**
+ * Interoperability with the Merkle Tree of plonky2 (plonky2::hash::merkle_tree::MerkleTree).
+ *
+ * Plonky2 hashes a leaf of any number of field elements with hash_or_noop and hashes nodes with two_to_one,
+ * which is the same as in this crate for leaves of a single element. Instead of a single root, plonky2 commits to a
+ * cap: all nodes at cap_height levels below the root, so proofs are cap_height siblings shorter.
+ * The nodes are stored in a different layout, but proofs contain the same siblings in the same order.
+ */
+pub type Plonky2MerkleTree = Plonky2Tree<GoldilocksField, PoseidonHash>;
+
+impl MerkleTree {
+  // Create a Merkle Tree with leaves that consist of several field elements, hashed the way plonky2 does
+  // Leaves of a single element give the same tree as MerkleTree::build
+  pub fn build_from_leaf_data(leaves: &[Vec<GoldilocksField>]) -> Self {
+    let level0: Vec<HashOut<GoldilocksField>> = leaves.iter().map(|leaf| PoseidonHash::hash_or_noop(leaf)).collect();
+    Self::build_from_hashes(level0)
+  }
+
+  // Create a Merkle Tree with the same leaves as a plonky2 Merkle Tree, for example one of a FRI commitment
+  pub fn from_plonky2(tree: &Plonky2MerkleTree) -> Self {
+    Self::build_from_leaf_data(&tree.leaves)
+  }
+
+  // Create a plonky2 Merkle Tree with the same nodes and a cap of the given height. This tree only has the leaf hashes,
+  // so those are the leaves of the plonky2 tree: hash_or_noop keeps a leaf of 4 elements as is
+  pub fn to_plonky2(&self, cap_height: usize) -> Plonky2MerkleTree {
+    self.assert_complete();
+    assert!(cap_height <= self.count_levels);
+    let leaves: Vec<Vec<GoldilocksField>> = self.level(0).iter().map(|hash| hash.elements.to_vec()).collect();
+    Plonky2MerkleTree::new(leaves, cap_height)
+  }
+
+  fn assert_complete(&self) {
+    assert!(self.padding == PaddingPolicy::ZeroHash || self.nr_leaves.is_power_of_two(), "caps need a complete tree");
+  }
+
+  // Returns the 2^cap_height nodes at cap_height levels below the root. A cap of height 0 is the root
+  pub fn cap(&self, cap_height: usize) -> MerkleCap<GoldilocksField, PoseidonHash> {
+    self.assert_complete();
+    assert!(cap_height <= self.count_levels);
+    if cap_height == 0 {
+      return MerkleCap([self.root].to_vec());
+    }
+    MerkleCap(self.level(self.count_levels - cap_height).to_vec())
+  }
+
+  // Returns a proof for the leaf that leads to the cap of the given height, which plonky2's verify_merkle_proof_to_cap accepts
+  pub fn get_merkle_proof_to_cap(&self, leaf_index: usize, cap_height: usize) -> MerkleProof<GoldilocksField, PoseidonHash> {
+    self.assert_complete();
+    assert!(cap_height <= self.count_levels);
+    let mut siblings = self.get_merkle_proof(leaf_index);
+    siblings.truncate(self.count_levels - cap_height);
+    MerkleProof { siblings: siblings }
+  }
+}
+
+// Targets of a Merkle proof to a cap that need to be set in the witness
+pub struct MerkleProofToCapTargets {
+  pub leaf_data: Vec<Target>,
+  pub leaf_index: Target,
+  pub cap: MerkleCapTarget,
+  pub proof: MerkleProofTarget
+}
+
+impl MerkleProofToCapTargets {
+  pub fn set_witness(
+    &self,
+    pw: &mut PartialWitness<GoldilocksField>,
+    leaf_data: &[GoldilocksField],
+    leaf_index: usize,
+    cap: &MerkleCap<GoldilocksField, PoseidonHash>,
+    proof: &MerkleProof<GoldilocksField, PoseidonHash>) {
+    for i in 0..self.leaf_data.len() {
+      pw.set_target(self.leaf_data[i], leaf_data[i]);
+    }
+    pw.set_target(self.leaf_index, GoldilocksField::from_canonical_usize(leaf_index));
+    for i in 0..self.cap.0.len() {
+      pw.set_hash_target(self.cap.0[i], cap.0[i]);
+    }
+    for i in 0..self.proof.siblings.len() {
+      pw.set_hash_target(self.proof.siblings[i], proof.siblings[i]);
+    }
+  }
+}
+
+// Returns a circuit that verifies a proof to a cap with plonky2's own gadget, for a tree of the given depth
+// and leaves of leaf_len elements. Works for proofs of this crate's trees and of plonky2 Merkle Trees alike
+// The circuit is built with the CircuitConfig of [profile]
+// Public inputs are the elements of the cap
+pub fn verify_merkle_proof_to_cap_circuit(depth: usize, cap_height: usize, leaf_len: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, MerkleProofToCapTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+  assert!(cap_height <= depth);
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let targets = MerkleProofToCapTargets {
+    leaf_data: builder.add_virtual_targets(leaf_len),
+    leaf_index: builder.add_virtual_target(),
+    cap: builder.add_virtual_cap(cap_height),
+    proof: MerkleProofTarget { siblings: builder.add_virtual_hashes(depth - cap_height) }
+  };
+
+  // The lower bits of the index pick the directions, the upper cap_height bits pick the node in the cap
+  let index_bits = builder.split_le(targets.leaf_index, depth);
+  builder.verify_merkle_proof_to_cap::<PoseidonHash>(targets.leaf_data.clone(), &index_bits, &targets.cap, &targets.proof);
+  for hash in targets.cap.0.iter() {
+    builder.register_public_inputs(&hash.elements);
+  }
+
+  (builder.build::<C>(), targets)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, hash::merkle_proofs::verify_merkle_proof_to_cap, iop::witness::PartialWitness};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::common::GOLDILOCKS_FIELD_ORDER, simple_merkle_tree::simple_merkle_tree::MerkleTree};
+  use super::{Plonky2MerkleTree, verify_merkle_proof_to_cap_circuit};
+
+  fn random_leaf_data(nr_leaves: usize, leaf_len: usize) -> Vec<Vec<GoldilocksField>> {
+    let mut rng = rand::thread_rng();
+    (0..nr_leaves).map(|_| (0..leaf_len).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect()).collect()
+  }
+
+  #[test]
+  fn test_same_as_plonky2() {
+    for leaf_len in [1, 3, 7] {
+      let leaves = random_leaf_data(32, leaf_len);
+      for cap_height in 0..=5 {
+        let plonky2_tree = Plonky2MerkleTree::new(leaves.clone(), cap_height);
+        let tree = MerkleTree::from_plonky2(&plonky2_tree);
+        assert!(tree.cap(cap_height) == plonky2_tree.cap);
+
+        for leaf_index in [0, 13, 31] {
+          let proof = tree.get_merkle_proof_to_cap(leaf_index, cap_height);
+          assert!(proof.siblings == plonky2_tree.prove(leaf_index).siblings);
+          assert!(verify_merkle_proof_to_cap(leaves[leaf_index].clone(), leaf_index, &tree.cap(cap_height), &proof).is_ok());
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_round_trip() {
+    // Padded with zero hashes to 16 leaves
+    let leaves = random_leaf_data(13, 1);
+    let tree = MerkleTree::build(leaves.iter().map(|leaf| leaf[0]).collect());
+    for cap_height in 0..=4 {
+      let plonky2_tree = tree.to_plonky2(cap_height);
+      assert!(plonky2_tree.cap == tree.cap(cap_height));
+      for leaf_index in [0, 5, 15] {
+        assert!(plonky2_tree.prove(leaf_index).siblings == tree.get_merkle_proof_to_cap(leaf_index, cap_height).siblings);
+      }
+      let round_trip = MerkleTree::from_plonky2(&plonky2_tree);
+      assert!(round_trip.root == tree.root);
+      assert!(round_trip.nodes == tree.nodes);
+    }
+
+    // From plonky2 and back gives the leaf hashes as leaves, with the same cap
+    let plonky2_tree = Plonky2MerkleTree::new(random_leaf_data(32, 6), 3);
+    let round_trip = MerkleTree::from_plonky2(&plonky2_tree).to_plonky2(3);
+    assert!(round_trip.cap == plonky2_tree.cap);
+    assert!(round_trip.digests == plonky2_tree.digests);
+  }
+
+  #[test]
+  fn test_single_element_leaves() {
+    // Leaves of a single element give the same tree as the standard build
+    let leaves = random_leaf_data(16, 1);
+    let tree = MerkleTree::build(leaves.iter().map(|leaf| leaf[0]).collect());
+    assert!(tree.root == MerkleTree::build_from_leaf_data(&leaves).root);
+    assert!(tree.cap(0).0 == [tree.root].to_vec());
+  }
+
+  #[test]
+  fn test_proof_to_cap_circuit() -> Result<()> {
+    let leaves = random_leaf_data(64, 4);
+    let tree = MerkleTree::build_from_leaf_data(&leaves);
+    let cap_height = 2;
+    let cap = tree.cap(cap_height);
+    let (circuit_data, targets) = verify_merkle_proof_to_cap_circuit(tree.count_levels, cap_height, 4, &ConfigProfile::default());
+
+    for leaf_index in [0, 22, 63] {
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, &leaves[leaf_index], leaf_index, &cap, &tree.get_merkle_proof_to_cap(leaf_index, cap_height));
+      let proof = circuit_data.prove(pw)?;
+      assert!(proof.public_inputs == cap.0.iter().flat_map(|hash| hash.elements).collect::<Vec<GoldilocksField>>());
+      circuit_data.verify(proof)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_proof_to_cap_circuit_wrong_leaf() {
+    let leaves = random_leaf_data(16, 2);
+    let tree = MerkleTree::build_from_leaf_data(&leaves);
+    let cap = tree.cap(1);
+    let (circuit_data, targets) = verify_merkle_proof_to_cap_circuit(tree.count_levels, 1, 2, &ConfigProfile::default());
+
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &leaves[4], 5, &cap, &tree.get_merkle_proof_to_cap(5, 1));
+    circuit_data.prove(pw).unwrap();
+  }
+}
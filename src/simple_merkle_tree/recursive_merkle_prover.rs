@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::{HashOut, HashOutTarget}}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig, Hasher}, circuit_data::{CircuitData, CommonCircuitData, VerifierCircuitTarget}, circuit_builder::CircuitBuilder, proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget}}, iop::{target::BoolTarget, witness::{PartialWitness, WitnessWrite}}, recursion::{cyclic_recursion::check_cyclic_proof_verifier_data, dummy_circuit::cyclic_base_proof}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::PrimeField64};
+
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, recursion::{common_data_for_recursion, fits_in_degree}};
+
+/**
+ * Recursive prover for Merkle proofs of any depth, that ends up with a single proof of constant size.
+ *
+ * A single step circuit hashes up to layers_per_step levels of the path, and verifies the proof of the previous step
+ * (cyclic recursion). The direction at every level is a witnessed bit, and every level has an enable bit, so the same
+ * circuit serves every leaf and every depth; the last step only enables the levels that are left.
+ *
+ * Public inputs of every proof (before the verifier data that cyclic recursion adds):
+ *  leaf hash (4), current hash (4), number of levels hashed so far, leaf index so far, 2^(number of levels)
+ * After the last step the current hash is the root and the index is the index of the leaf.
+ */
+
+// Public inputs of a proof of the recursive Merkle prover
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MerklePathPublicInputs {
+  pub leaf_hash: HashOut<GoldilocksField>,
+  pub root: HashOut<GoldilocksField>,
+  pub depth: usize,
+  pub leaf_index: usize
+}
+
+impl MerklePathPublicInputs {
+  pub fn from_proof(proof: &ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> Self {
+    let pis = &proof.public_inputs;
+    MerklePathPublicInputs {
+      leaf_hash: HashOut::from_partial(&pis[0..4]),
+      root: HashOut::from_partial(&pis[4..8]),
+      depth: pis[8].to_canonical_u64() as usize,
+      leaf_index: pis[9].to_canonical_u64() as usize
+    }
+  }
+}
+
+// Targets of a step that need to be set in the witness
+struct StepTargets {
+  leaf_hash: HashOutTarget,
+  siblings: Vec<HashOutTarget>,
+  directions: Vec<BoolTarget>,
+  enabled: Vec<BoolTarget>,
+  // false for the first step, which has no previous proof
+  has_previous: BoolTarget,
+  previous_proof: ProofWithPublicInputsTarget<2>,
+  verifier_data: VerifierCircuitTarget
+}
+
+pub struct RecursiveMerkleProver {
+  pub layers_per_step: usize,
+  pub circuit_data: CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>,
+  common_data: CommonCircuitData<GoldilocksField, 2>,
+  targets: StepTargets
+}
+
+// Adds the step circuit to a new builder. Returns the builder before it is built, since the caller needs to check its size
+fn step_circuit(layers_per_step: usize, mut common_data: CommonCircuitData<GoldilocksField, 2>, profile: &ConfigProfile)
+  -> Result<(CircuitBuilder<GoldilocksField, 2>, StepTargets, CommonCircuitData<GoldilocksField, 2>)> {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let one = builder.one();
+  let zero = builder.zero();
+
+  let leaf_hash = builder.add_virtual_hash();
+  builder.register_public_inputs(&leaf_hash.elements);
+  let current_hash_out = builder.add_virtual_hash();
+  builder.register_public_inputs(&current_hash_out.elements);
+  let depth_out = builder.add_virtual_public_input();
+  let index_out = builder.add_virtual_public_input();
+  let weight_out = builder.add_virtual_public_input();
+
+  let verifier_data = builder.add_verifier_data_public_inputs();
+  common_data.num_public_inputs = builder.num_public_inputs();
+
+  // Public inputs of the previous step
+  let has_previous = builder.add_virtual_bool_target_safe();
+  let previous_proof = builder.add_virtual_proof_with_pis(&common_data);
+  let previous_pis = &previous_proof.public_inputs;
+  let previous_leaf_hash = HashOutTarget::from_vec(previous_pis[0..4].to_vec());
+  let previous_hash = HashOutTarget::from_vec(previous_pis[4..8].to_vec());
+  let previous_depth = previous_pis[8];
+  let previous_index = previous_pis[9];
+  let previous_weight = previous_pis[10];
+
+  // The whole chain is about the same leaf. The first step starts at the leaf, at depth 0
+  builder.connect_hashes(leaf_hash, previous_leaf_hash);
+  let mut current_hash = builder.select_hash(has_previous, previous_hash, leaf_hash);
+  let mut depth = builder.select(has_previous, previous_depth, zero);
+  let mut index = builder.select(has_previous, previous_index, zero);
+  let mut weight = builder.select(has_previous, previous_weight, one);
+
+  let siblings = builder.add_virtual_hashes(layers_per_step);
+  let directions: Vec<BoolTarget> = (0..layers_per_step).map(|_| builder.add_virtual_bool_target_safe()).collect();
+  let enabled: Vec<BoolTarget> = (0..layers_per_step).map(|_| builder.add_virtual_bool_target_safe()).collect();
+  for i in 0..layers_per_step {
+    let next_hash = builder.verify_merkle_path(current_hash, &siblings[i..i + 1], &directions[i..i + 1]);
+    current_hash = builder.select_hash(enabled[i], next_hash, current_hash);
+
+    // A disabled level doesn't count, and its direction doesn't either
+    let direction_counts = builder.and(directions[i], enabled[i]);
+    index = builder.mul_add(direction_counts.target, weight, index);
+    depth = builder.add(depth, enabled[i].target);
+    let doubled_weight = builder.add(weight, weight);
+    weight = builder.select(enabled[i], doubled_weight, weight);
+  }
+
+  builder.connect_hashes(current_hash, current_hash_out);
+  builder.connect(depth, depth_out);
+  builder.connect(index, index_out);
+  builder.connect(weight, weight_out);
+
+  builder.conditionally_verify_cyclic_proof_or_dummy::<C>(has_previous, &previous_proof, &common_data)?;
+
+  let targets = StepTargets {
+    leaf_hash: leaf_hash,
+    siblings: siblings,
+    directions: directions,
+    enabled: enabled,
+    has_previous: has_previous,
+    previous_proof: previous_proof,
+    verifier_data: verifier_data
+  };
+  Ok((builder, targets, common_data))
+}
+
+impl RecursiveMerkleProver {
+  // Builds the step circuit that hashes layers_per_step levels per recursion step
+  // The circuit has the smallest size, starting at 2^12 gates, that holds both the levels and the recursive verifier
+  // The circuit is built with the CircuitConfig of profile
+  pub fn new(layers_per_step: usize, profile: &ConfigProfile) -> Result<Self> {
+    type C = PoseidonGoldilocksConfig;
+    assert!(layers_per_step > 0);
+
+    let mut degree_bits = 12;
+    loop {
+      let (builder, targets, common_data) = step_circuit(layers_per_step, common_data_for_recursion(degree_bits, profile), profile)?;
+      if fits_in_degree(builder.num_gates(), degree_bits) {
+        let circuit_data = builder.build::<C>();
+        return Ok(RecursiveMerkleProver {
+          layers_per_step: layers_per_step,
+          circuit_data: circuit_data,
+          common_data: common_data,
+          targets: targets
+        });
+      }
+      degree_bits += 1;
+    }
+  }
+
+  // Returns a single proof that the leaf hashes up to the root with the given Merkle proof, as returned by MerkleTree::get_merkle_proof
+  // The public inputs can be read with MerklePathPublicInputs::from_proof
+  pub fn prove(&self, leaf: GoldilocksField, leaf_index: usize, siblings: &[HashOut<GoldilocksField>]) -> Result<ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>> {
+    let leaf_hash: HashOut<GoldilocksField> = PoseidonHash::hash_or_noop(&[leaf]);
+    // The dummy proof of the first step has to be about the same leaf
+    let leaf_hash_pis: HashMap<usize, GoldilocksField> = leaf_hash.elements.into_iter().enumerate().collect();
+    let mut previous_proof = cyclic_base_proof(&self.common_data, &self.circuit_data.verifier_only, leaf_hash_pis);
+
+    // A path of depth 0 still takes a step
+    let nr_steps = ((siblings.len() + self.layers_per_step - 1) / self.layers_per_step).max(1);
+    for step in 0..nr_steps {
+      let mut pw = PartialWitness::new();
+      pw.set_hash_target(self.targets.leaf_hash, leaf_hash);
+      pw.set_bool_target(self.targets.has_previous, step > 0);
+      pw.set_proof_with_pis_target(&self.targets.previous_proof, &previous_proof);
+      pw.set_verifier_data_target(&self.targets.verifier_data, &self.circuit_data.verifier_only);
+
+      for i in 0..self.layers_per_step {
+        let level = step * self.layers_per_step + i;
+        let enabled = level < siblings.len();
+        pw.set_bool_target(self.targets.enabled[i], enabled);
+        pw.set_bool_target(self.targets.directions[i], enabled && (leaf_index >> level) & 1 == 1);
+        pw.set_hash_target(self.targets.siblings[i], if enabled { siblings[level] } else { HashOut::ZERO });
+      }
+      previous_proof = self.circuit_data.prove(pw)?;
+    }
+    Ok(previous_proof)
+  }
+
+  // Verifies the proof, and that it was made with this step circuit all the way down
+  pub fn verify(&self, proof: ProofWithPublicInputs<GoldilocksField, PoseidonGoldilocksConfig, 2>) -> Result<()> {
+    check_cyclic_proof_verifier_data(&proof, &self.circuit_data.verifier_only, &self.circuit_data.common)?;
+    self.circuit_data.verify(proof)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, hash::{hash_types::HashOut, poseidon::PoseidonHash}, plonk::config::Hasher};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::common::GOLDILOCKS_FIELD_ORDER, simple_merkle_tree::simple_merkle_tree::MerkleTree};
+  use super::{MerklePathPublicInputs, RecursiveMerkleProver};
+
+  fn random_leaves(nr_leaves: usize) -> Vec<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    (0..nr_leaves).map(|_| GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))).collect()
+  }
+
+  #[test]
+  fn test_paths_of_different_depths() -> Result<()> {
+    // A single circuit for all depths, also when the depth is not a multiple of the layers per step
+    let prover = RecursiveMerkleProver::new(4, &ConfigProfile::default())?;
+    for (nr_leaves, leaf_index) in [(8, 5), (1 << 10, 777)] {
+      let leaves = random_leaves(nr_leaves);
+      let tree = MerkleTree::build(leaves.clone());
+      let proof = prover.prove(leaves[leaf_index], leaf_index, &tree.get_merkle_proof(leaf_index))?;
+
+      let public_inputs = MerklePathPublicInputs::from_proof(&proof);
+      assert!(public_inputs == MerklePathPublicInputs {
+        leaf_hash: PoseidonHash::hash_or_noop(&[leaves[leaf_index]]),
+        root: tree.root,
+        depth: tree.count_levels,
+        leaf_index: leaf_index
+      });
+      prover.verify(proof)?;
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn test_wrong_sibling() -> Result<()> {
+    let prover = RecursiveMerkleProver::new(3, &ConfigProfile::default())?;
+    let leaves = random_leaves(16);
+    let tree = MerkleTree::build(leaves.clone());
+    let mut siblings = tree.get_merkle_proof(9);
+    siblings[2] = HashOut::ZERO;
+
+    // The proof is valid, but for a different root
+    let proof = prover.prove(leaves[9], 9, &siblings)?;
+    assert!(MerklePathPublicInputs::from_proof(&proof).root != tree.root);
+    Ok(())
+  }
+
+  #[test]
+  fn test_zero_knowledge() -> Result<()> {
+    let prover = RecursiveMerkleProver::new(4, &ConfigProfile::ZeroKnowledge)?;
+    assert!(prover.circuit_data.common.config.zero_knowledge);
+    let leaves = random_leaves(64);
+    let tree = MerkleTree::build(leaves.clone());
+    let proof = prover.prove(leaves[42], 42, &tree.get_merkle_proof(42))?;
+    assert!(MerklePathPublicInputs::from_proof(&proof).root == tree.root);
+    prover.verify(proof)
+  }
+}
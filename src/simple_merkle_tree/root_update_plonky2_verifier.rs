@@ -0,0 +1,151 @@
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::HashOutTarget}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, iop::{target::Target, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::{config_profile::ConfigProfile, simple_merkle_tree::{circuit::compute_root_circuit, simple_merkle_tree::RootUpdateProof}};
+
+// Targets of a single leaf update that need to be set in the witness
+pub struct RootUpdateTargets {
+  pub leaf_index: Target,
+  pub old_leaf_hash: HashOutTarget,
+  pub new_leaf: Target,
+  pub siblings: Vec<HashOutTarget>
+}
+
+impl RootUpdateTargets {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &RootUpdateProof) {
+    pw.set_target(self.leaf_index, GoldilocksField::from_canonical_usize(proof.leaf_index));
+    pw.set_hash_target(self.old_leaf_hash, proof.old_leaf_hash);
+    pw.set_target(self.new_leaf, proof.new_leaf);
+    for i in 0..self.siblings.len() {
+      pw.set_hash_target(self.siblings[i], proof.siblings[i]);
+    }
+  }
+}
+
+// Adds the constraints for a single update to the builder. Returns the targets, the old root and the new root
+fn add_root_update(builder: &mut CircuitBuilder<GoldilocksField, 2>, depth: usize) -> (RootUpdateTargets, HashOutTarget, HashOutTarget) {
+  let targets = RootUpdateTargets {
+    leaf_index: builder.add_virtual_target(),
+    old_leaf_hash: builder.add_virtual_hash(),
+    new_leaf: builder.add_virtual_target(),
+    siblings: builder.add_virtual_hashes(depth)
+  };
+
+  // Splitting the index in depth bits also checks it is within the tree
+  let index_bits = builder.split_le(targets.leaf_index, depth);
+  let old_root = compute_root_circuit(builder, targets.old_leaf_hash, &index_bits, &targets.siblings);
+  let new_leaf_hash = builder.hash_or_noop::<PoseidonHash>([targets.new_leaf].to_vec());
+  let new_root = compute_root_circuit(builder, new_leaf_hash, &index_bits, &targets.siblings);
+  (targets, old_root, new_root)
+}
+
+// Returns a circuit that verifies nr_updates consecutive leaf updates in a Merkle tree of the given depth,
+// where the old root of every update is the new root of the previous one
+// Public inputs are the old root before the first update and the new root after the last update,
+// followed by the leaf index and new leaf of every update
+// The circuit is built with the CircuitConfig of [profile]
+pub fn verify_root_updates_circuit(depth: usize, nr_updates: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, Vec<RootUpdateTargets>) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+  assert!(nr_updates > 0);
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+
+  let mut all_targets = Vec::new();
+  let mut first_root = None;
+  let mut last_root: Option<HashOutTarget> = None;
+  for _ in 0..nr_updates {
+    let (targets, old_root, new_root) = add_root_update(&mut builder, depth);
+    match last_root {
+      Some(previous_new_root) => builder.connect_hashes(old_root, previous_new_root),
+      None => first_root = Some(old_root)
+    }
+    last_root = Some(new_root);
+    all_targets.push(targets);
+  }
+
+  builder.register_public_inputs(&first_root.unwrap().elements);
+  builder.register_public_inputs(&last_root.unwrap().elements);
+  for targets in all_targets.iter() {
+    builder.register_public_input(targets.leaf_index);
+    builder.register_public_input(targets.new_leaf);
+  }
+
+  (builder.build::<C>(), all_targets)
+}
+
+// Returns a circuit that verifies a single leaf update in a Merkle tree of the given depth
+// Public inputs are the old root, the new root, the leaf index and the new leaf
+pub fn verify_root_update_circuit(depth: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, RootUpdateTargets) {
+  let (circuit_data, mut targets) = verify_root_updates_circuit(depth, 1, profile);
+  (circuit_data, targets.remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, iop::witness::PartialWitness};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::common::GOLDILOCKS_FIELD_ORDER, simple_merkle_tree::simple_merkle_tree::MerkleTree};
+  use super::{verify_root_update_circuit, verify_root_updates_circuit};
+
+  fn random_element() -> GoldilocksField {
+    let mut rng = rand::thread_rng();
+    GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER))
+  }
+
+  fn test_tree(nr_leaves: usize) -> MerkleTree {
+    MerkleTree::build((0..nr_leaves).map(|_| random_element()).collect())
+  }
+
+  #[test]
+  fn test_single_update() -> Result<()> {
+    let mut tree = test_tree(16);
+    let (circuit_data, targets) = verify_root_update_circuit(tree.count_levels, &ConfigProfile::default());
+
+    let update = tree.update_leaf(11, random_element());
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &update);
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs == [
+      update.old_root.elements.to_vec(),
+      update.new_root.elements.to_vec(),
+      [GoldilocksField::from_canonical_u64(11), update.new_leaf].to_vec()
+    ].concat());
+    circuit_data.verify(proof)
+  }
+
+  #[test]
+  fn test_chained_updates() -> Result<()> {
+    let mut tree = test_tree(32);
+    let old_root = tree.root;
+    let (circuit_data, targets) = verify_root_updates_circuit(tree.count_levels, 3, &ConfigProfile::default());
+
+    let updates = tree.update_leaves(&[(0, random_element()), (31, random_element()), (0, random_element())]);
+    let mut pw = PartialWitness::new();
+    for (targets, update) in targets.iter().zip(updates.iter()) {
+      targets.set_witness(&mut pw, update);
+    }
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs[0..4] == old_root.elements);
+    assert!(proof.public_inputs[4..8] == tree.root.elements);
+    circuit_data.verify(proof)
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_broken_chain_fails() {
+    let mut tree = test_tree(16);
+    let (circuit_data, targets) = verify_root_updates_circuit(tree.count_levels, 2, &ConfigProfile::default());
+
+    let updates = tree.update_leaves(&[(2, random_element()), (3, random_element())]);
+    let mut pw = PartialWitness::new();
+    // The second update is given twice, so the first one doesn't lead to its old root
+    targets[0].set_witness(&mut pw, &updates[1]);
+    targets[1].set_witness(&mut pw, &updates[1]);
+    circuit_data.prove(pw).unwrap();
+  }
+}
@@ -2,83 +2,286 @@
 
 use itertools::Itertools;
 use num::Integer;
+use rayon::prelude::*;
 use plonky2::field::goldilocks_field::GoldilocksField;
 use plonky2::hash::hash_types::HashOut;
 use plonky2::hash::poseidon::PoseidonHash;
 use plonky2::plonk::config::Hasher;
-use plonky2_util::log2_strict;
+
+use crate::leaf::Leaf;
+
+// Determines how a tree is completed when the number of leaves is not a power of 2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaddingPolicy {
+  // Pad the leaves up to the next power of 2 with the zero hash, which is also the hash of a leaf with value 0
+  ZeroHash,
+  // At every level with an odd number of nodes, the last node is hashed together with itself
+  DuplicateLast,
+  // At every level with an odd number of nodes, the last node moves up a level as is.
+  //   Merkle proofs of leaves on that path are shorter, since there is no sibling at that level
+  Promote
+}
+
+// Levels with fewer nodes are hashed in a single thread, since splitting up the work costs more than it saves
+const PARALLEL_LEVEL_THRESHOLD: usize = 1 << 10;
 
 #[derive(Debug, Clone)]
 pub struct MerkleTree {
     pub count_levels: usize,
-    pub tree: Vec<Vec<HashOut<GoldilocksField>>>, // contains vectors of hashes for the levels in the tree (count_levels-1 vectors)
-    pub root: HashOut<GoldilocksField>
+    // All hashes of the tree in a single array, level by level: first the hashed leaves, last the root
+    pub nodes: Vec<HashOut<GoldilocksField>>,
+    // Level i consists of nodes[level_offsets[i]..level_offsets[i+1]]
+    level_offsets: Vec<usize>,
+    pub root: HashOut<GoldilocksField>,
+    // number of leaves the tree was built with, without padding
+    pub nr_leaves: usize,
+    pub padding: PaddingPolicy
+}
+
+// Proof that changing a single leaf changed the root from old_root into new_root
+// The siblings are the same before and after the update, since only the nodes on the path of the leaf change
+#[derive(Debug, Clone)]
+pub struct RootUpdateProof {
+  pub leaf_index: usize,
+  // The tree only stores hashes, so the old leaf is given as it is stored in the tree
+  pub old_leaf_hash: HashOut<GoldilocksField>,
+  pub new_leaf: GoldilocksField,
+  pub siblings: Vec<HashOut<GoldilocksField>>,
+  pub old_root: HashOut<GoldilocksField>,
+  pub new_root: HashOut<GoldilocksField>
+}
+
+// Proof that a set of leaves is part of the tree, sharing the nodes that the paths of the leaves have in common
+// Going up level by level, every known node is either hashed with the next known node, which is its sibling,
+//  or with the next helper node. flags holds true when a helper is used and false otherwise, in that order
+#[derive(Debug, Clone)]
+pub struct MerkleMultiproof {
+  pub depth: usize,
+  // Sorted and without duplicates. The leaves that are proven have to be given in the same order
+  pub leaf_indices: Vec<usize>,
+  pub helpers: Vec<HashOut<GoldilocksField>>,
+  pub flags: Vec<bool>
 }
 
 impl MerkleTree {
 
-  // From list of hashes with length len, take each pair and hash them, resulting in a new vector of hashes of length len/2
-  fn next_level_hashes(current_level: Vec<HashOut<GoldilocksField>>) -> Vec<HashOut<GoldilocksField>> {
-    let temp: Vec<&[HashOut<GoldilocksField>]> = current_level.chunks(2).into_iter().collect_vec();
-    let next_level: Vec<HashOut<GoldilocksField>> = temp.into_iter().map(|x| PoseidonHash::two_to_one(x[0], x[1])).collect();
-    next_level
+  // Returns the hash at index of the next level, given the current level
+  // If the current level has an odd number of nodes, the last hash is handled according to the padding policy
+  fn parent_hash(current_level: &[HashOut<GoldilocksField>], index: usize, padding: PaddingPolicy) -> HashOut<GoldilocksField> {
+    let left = current_level[2 * index];
+    if 2 * index + 1 < current_level.len() {
+      PoseidonHash::two_to_one(left, current_level[2 * index + 1])
+    } else {
+      match padding {
+        PaddingPolicy::DuplicateLast => PoseidonHash::two_to_one(left, left),
+        PaddingPolicy::Promote => left,
+        PaddingPolicy::ZeroHash => panic!("levels are padded to a power of 2 with ZeroHash")
+      }
+    }
   }
 
   // Create a Merkle Tree given 2^n leaves. 
+  // Any other number of leaves is padded with zero hashes, see build_with_padding for the other options
   pub fn build(leaves: Vec<GoldilocksField>) -> Self {
-    // This panics if length is not a power of 2
-    let count_levels = log2_strict(leaves.len());
+    Self::build_with_padding(leaves, PaddingPolicy::ZeroHash)
+  }
 
+  // Create a Merkle Tree for any number of leaves, using the given padding policy when it is not a power of 2
+  // For 2^n leaves the padding policy doesn't matter
+  pub fn build_with_padding(leaves: Vec<GoldilocksField>, padding: PaddingPolicy) -> Self {
     // To get the first level, hash all leaves
     let level0: Vec<HashOut<GoldilocksField>> = leaves.into_iter().map(|leaf| { PoseidonHash::hash_or_noop(&[leaf])}).collect();
+    Self::build_from_hashes_with_padding(level0, padding)
+  }
+
+  // Create a Merkle Tree given 2^n hashes of leaves. Useful for leaves that don't consist of a single field element
+  pub fn build_from_hashes(level0: Vec<HashOut<GoldilocksField>>) -> Self {
+    Self::build_from_hashes_with_padding(level0, PaddingPolicy::ZeroHash)
+  }
 
-    let mut levels = Vec::new();
-    levels.push(level0);
-    // For next levels, hash every hashes. Ends at 2 hashes.
-    for i in 0..(count_levels-1) {
-      let next_level = Self::next_level_hashes(levels[i].clone());
-      levels.push(next_level);
+  // Create a Merkle Tree given 2^n structured records. Each leaf is the hash of all field elements of the record
+  pub fn build_from_records<L: Leaf>(records: &[L]) -> Self {
+    Self::build_from_hashes(records.iter().map(|record| record.hash()).collect())
+  }
+
+  // The levels are hashed one after the other, the hashes within a level in parallel
+  pub fn build_from_hashes_with_padding(level0: Vec<HashOut<GoldilocksField>>, padding: PaddingPolicy) -> Self {
+    let nr_leaves = level0.len();
+    // An empty tree has the zero hash as root
+    if nr_leaves == 0 {
+      return MerkleTree {
+        count_levels: 0,
+        nodes: level0,
+        level_offsets: [0, 0].to_vec(),
+        root: HashOut::ZERO,
+        nr_leaves: 0,
+        padding: padding
+      };
+    }
+
+    let mut level_size = if padding == PaddingPolicy::ZeroHash { nr_leaves.next_power_of_two() } else { nr_leaves };
+    let mut level_offsets = [0, level_size].to_vec();
+    while level_size > 1 {
+      level_size = (level_size + 1) / 2;
+      level_offsets.push(level_offsets[level_offsets.len() - 1] + level_size);
+    }
+    let count_levels = level_offsets.len() - 2;
+
+    // The leaves stay where they are; all other hashes are written in place. For ZeroHash this also adds the padding
+    let mut nodes = level0;
+    nodes.resize(level_offsets[count_levels + 1], HashOut::ZERO);
+    for i in 0..count_levels {
+      let (lower, upper) = nodes.split_at_mut(level_offsets[i + 1]);
+      let current_level = &lower[level_offsets[i]..];
+      let next_level = &mut upper[..level_offsets[i + 2] - level_offsets[i + 1]];
+      if next_level.len() >= PARALLEL_LEVEL_THRESHOLD {
+        next_level.par_iter_mut().enumerate().for_each(|(j, hash)| *hash = Self::parent_hash(current_level, j, padding));
+      } else {
+        next_level.iter_mut().enumerate().for_each(|(j, hash)| *hash = Self::parent_hash(current_level, j, padding));
+      }
     }
 
-    // Final hash for root.
-    let last_hashes: Vec<HashOut<GoldilocksField>> = levels.clone().last().unwrap().to_vec();
-    let root = PoseidonHash::two_to_one(last_hashes[0], last_hashes[1]);
     MerkleTree { 
       count_levels: count_levels, 
-      tree: levels.clone(), 
-      root: root
+      root: nodes[level_offsets[count_levels]],
+      nodes: nodes,
+      level_offsets: level_offsets,
+      nr_leaves: nr_leaves,
+      padding: padding
+    }
+  }
+
+  // Returns the hashes at level i, where level 0 holds the hashed leaves and level count_levels the root
+  pub fn level(&self, i: usize) -> &[HashOut<GoldilocksField>] {
+    &self.nodes[self.level_offsets[i]..self.level_offsets[i + 1]]
+  }
+
+  fn level_mut(&mut self, i: usize) -> &mut [HashOut<GoldilocksField>] {
+    &mut self.nodes[self.level_offsets[i]..self.level_offsets[i + 1]]
+  }
+
+  // Returns the sibling of the node at the given level and index, if it has one
+  fn get_sibling(&self, level: usize, index: usize) -> Option<HashOut<GoldilocksField>> {
+    let level_i = self.level(level);
+    let sibling_index = if index.is_odd() { index - 1 } else { index + 1 };
+    if sibling_index < level_i.len() {
+      Some(level_i[sibling_index])
+    } else {
+      // Only the last node of a level with an odd number of nodes has no sibling
+      match self.padding {
+        PaddingPolicy::DuplicateLast => Some(level_i[index]),
+        PaddingPolicy::Promote => None,
+        PaddingPolicy::ZeroHash => panic!("levels are padded to a power of 2 with ZeroHash")
+      }
     }
   }
 
   // Returns count_levels elements that together with the leaf show that a leaf is part of this Merkle Tree, given the root
   // starts at the element at the lowest level and goes up
-  pub fn get_merkle_proof(self, leaf_index: usize) -> Vec<HashOut<GoldilocksField>> {
-    assert!(leaf_index < self.tree[0].len());
+  // With PaddingPolicy::Promote, levels at which the node was promoted have no element in the proof
+  pub fn get_merkle_proof(&self, leaf_index: usize) -> Vec<HashOut<GoldilocksField>> {
+    assert!(leaf_index < self.nr_leaves);
 
     let mut proof_hashes = Vec::new();
     let mut updated_index = leaf_index;
 
     // Grab the correct hash per level
     for i in 0..(self.count_levels) {
-      let level_i: &Vec<HashOut<GoldilocksField>> = &self.tree[i];  
-      let selected_hash = if updated_index.is_odd() {
-        level_i[updated_index-1]
-      } else {
-        level_i[updated_index+1]
-      };
-      proof_hashes.push(selected_hash);
+      if let Some(selected_hash) = self.get_sibling(i, updated_index) {
+        proof_hashes.push(selected_hash);
+      }
       updated_index = updated_index/2;
     }
 
     proof_hashes
   }
 
-  pub fn get_in_between_hashes(self, leaf_index: usize) -> Vec<HashOut<GoldilocksField>>{
-    assert!(leaf_index < self.tree[0].len());
+  // Sets the leaf at leaf_index to value, recomputing only the hashes on the path to the root
+  // Returns the proof of the change of root
+  // Only supported when every node has a real sibling: trees with 2^n leaves, or padded with zero hashes (the default)
+  pub fn update_leaf(&mut self, leaf_index: usize, value: GoldilocksField) -> RootUpdateProof {
+    let old_leaf_hash = self.level(0)[leaf_index];
+    let old_root = self.root;
+    let siblings = self.update_leaf_hash(leaf_index, PoseidonHash::hash_or_noop(&[value]));
+
+    RootUpdateProof {
+      leaf_index: leaf_index,
+      old_leaf_hash: old_leaf_hash,
+      new_leaf: value,
+      siblings: siblings,
+      old_root: old_root,
+      new_root: self.root
+    }
+  }
+
+  // Same as update_leaf, for a leaf that is already hashed. Returns the siblings of the leaf, which didn't change
+  pub fn update_leaf_hash(&mut self, leaf_index: usize, leaf_hash: HashOut<GoldilocksField>) -> Vec<HashOut<GoldilocksField>> {
+    assert!(leaf_index < self.nr_leaves);
+    assert!(self.padding == PaddingPolicy::ZeroHash || self.nr_leaves.is_power_of_two(), "updates need a complete tree");
+
+    // Every node has a sibling, so this is the full Merkle proof
+    let siblings: Vec<HashOut<GoldilocksField>> = (0..self.count_levels).map(|i| self.get_sibling(i, leaf_index >> i).unwrap()).collect();
+
+    let mut next_hash = leaf_hash;
+    let mut updated_index = leaf_index;
+    self.level_mut(0)[updated_index] = next_hash;
+    for i in 0..self.count_levels {
+      if updated_index.is_even() {
+        next_hash = PoseidonHash::two_to_one(next_hash, siblings[i]);
+      } else {
+        next_hash = PoseidonHash::two_to_one(siblings[i], next_hash);
+      }
+      updated_index = updated_index/2;
+      self.level_mut(i + 1)[updated_index] = next_hash;
+    }
+    self.root = next_hash;
+    siblings
+  }
+
+  // Applies the updates one after the other. The old root of every proof is the new root of the previous one
+  pub fn update_leaves(&mut self, updates: &[(usize, GoldilocksField)]) -> Vec<RootUpdateProof> {
+    updates.iter().map(|(leaf_index, value)| self.update_leaf(*leaf_index, *value)).collect()
+  }
+
+  // Returns a multiproof for the given leaves. The indices are sorted and duplicates are removed
+  // Only supported when every path has the full length, so not for PaddingPolicy::Promote with a number of leaves that is not a power of 2
+  pub fn get_multiproof(&self, leaf_indices: &[usize]) -> MerkleMultiproof {
+    assert!(self.padding != PaddingPolicy::Promote || self.nr_leaves.is_power_of_two(), "multiproofs need full paths");
+    let leaf_indices: Vec<usize> = leaf_indices.iter().cloned().sorted().dedup().collect();
+    assert!(!leaf_indices.is_empty());
+    assert!(leaf_indices.iter().all(|i| *i < self.nr_leaves));
+
+    let flags = multiproof_flags(self.count_levels, &leaf_indices);
+    let mut helpers = Vec::new();
+    let mut flags_iter = flags.iter();
+    let mut indices = leaf_indices.clone();
+    for level in 0..self.count_levels {
+      let mut i = 0;
+      while i < indices.len() {
+        if *flags_iter.next().unwrap() {
+          helpers.push(self.get_sibling(level, indices[i]).unwrap());
+          i += 1;
+        } else {
+          i += 2;
+        }
+      }
+      indices = indices.into_iter().map(|index| index / 2).dedup().collect();
+    }
+
+    MerkleMultiproof {
+      depth: self.count_levels,
+      leaf_indices: leaf_indices,
+      helpers: helpers,
+      flags: flags
+    }
+  }
+
+  pub fn get_in_between_hashes(&self, leaf_index: usize) -> Vec<HashOut<GoldilocksField>>{
+    assert!(leaf_index < self.nr_leaves);
     let mut index = leaf_index / 2;
     let mut hashes = Vec::new();
     for i in 1..self.count_levels {
-      hashes.push(self.tree[i][index]);
+      hashes.push(self.level(i)[index]);
       index = index / 2;
     }
     hashes.push(self.root);
@@ -91,7 +294,16 @@ impl MerkleTree {
 pub fn verify_merkle_proof(leaf: GoldilocksField, leaf_index: usize, root: HashOut<GoldilocksField>, hashes: Vec<HashOut<GoldilocksField>>) -> bool {
   // Step 1: hash leaf
   let leaf_hashed: HashOut<GoldilocksField> = PoseidonHash::hash_or_noop(&[leaf]);
+  verify_merkle_proof_for_hash(leaf_hashed, leaf_index, root, hashes)
+}
+
+// Same as verify_merkle_proof, for a structured record in a tree built with MerkleTree::build_from_records
+pub fn verify_record_proof<L: Leaf>(record: &L, leaf_index: usize, root: HashOut<GoldilocksField>, hashes: Vec<HashOut<GoldilocksField>>) -> bool {
+  verify_merkle_proof_for_hash(record.hash(), leaf_index, root, hashes)
+}
 
+// Same as verify_merkle_proof, for a leaf that has already been hashed
+pub fn verify_merkle_proof_for_hash(leaf_hashed: HashOut<GoldilocksField>, leaf_index: usize, root: HashOut<GoldilocksField>, hashes: Vec<HashOut<GoldilocksField>>) -> bool {
   // Repeat: take 1 hash from list and current hash, hash together
   let mut next_hash: HashOut<GoldilocksField> = leaf_hashed;
   let mut updated_index = leaf_index;
@@ -108,11 +320,129 @@ pub fn verify_merkle_proof(leaf: GoldilocksField, leaf_index: usize, root: HashO
   next_hash == root
 }
 
+impl RootUpdateProof {
+  // Returns true if the old leaf leads to the old root and the new leaf to the new root, with the same siblings
+  pub fn verify(&self) -> bool {
+    let new_leaf_hash: HashOut<GoldilocksField> = PoseidonHash::hash_or_noop(&[self.new_leaf]);
+    verify_merkle_proof_for_hash(self.old_leaf_hash, self.leaf_index, self.old_root, self.siblings.clone())
+      && verify_merkle_proof_for_hash(new_leaf_hash, self.leaf_index, self.new_root, self.siblings.clone())
+  }
+}
+
+// Returns the flags of a multiproof for the given sorted leaf indices, in a tree of the given depth
+// Per level and from left to right, a known node is paired with the next known node when that is its sibling (false),
+//  otherwise its sibling has to come from the helpers (true)
+pub fn multiproof_flags(depth: usize, leaf_indices: &[usize]) -> Vec<bool> {
+  let mut flags = Vec::new();
+  let mut indices = leaf_indices.to_vec();
+  for _ in 0..depth {
+    let mut i = 0;
+    while i < indices.len() {
+      if i + 1 < indices.len() && indices[i] ^ 1 == indices[i + 1] {
+        flags.push(false);
+        i += 2;
+      } else {
+        flags.push(true);
+        i += 1;
+      }
+    }
+    indices = indices.into_iter().map(|index| index / 2).dedup().collect();
+  }
+  flags
+}
+
+// Returns true if the leaves, given in the order of proof.leaf_indices, lead to the root using the helpers of the multiproof
+pub fn verify_multiproof(leaves: &[GoldilocksField], root: HashOut<GoldilocksField>, proof: &MerkleMultiproof) -> bool {
+  if leaves.is_empty() || leaves.len() != proof.leaf_indices.len() {
+    return false;
+  }
+  // The indices have to be strictly increasing and within the tree
+  if !proof.leaf_indices.windows(2).all(|w| w[0] < w[1]) || proof.leaf_indices[proof.leaf_indices.len() - 1] >> proof.depth != 0 {
+    return false;
+  }
+
+  let mut nodes: Vec<(usize, HashOut<GoldilocksField>)> = proof.leaf_indices.iter().zip(leaves)
+    .map(|(index, leaf)| (*index, PoseidonHash::hash_or_noop(&[*leaf]))).collect();
+  let mut flags = proof.flags.iter();
+  let mut helpers = proof.helpers.iter();
+  for _ in 0..proof.depth {
+    let mut next_nodes = Vec::new();
+    let mut i = 0;
+    while i < nodes.len() {
+      let (index, hash) = nodes[i];
+      let sibling = match flags.next() {
+        Some(true) => match helpers.next() {
+          Some(helper) => *helper,
+          None => return false
+        },
+        Some(false) => {
+          // The next node has to be the sibling
+          if i + 1 >= nodes.len() || nodes[i + 1].0 != index ^ 1 {
+            return false;
+          }
+          i += 1;
+          nodes[i].1
+        },
+        None => return false
+      };
+      if index.is_even() {
+        next_nodes.push((index / 2, PoseidonHash::two_to_one(hash, sibling)));
+      } else {
+        next_nodes.push((index / 2, PoseidonHash::two_to_one(sibling, hash)));
+      }
+      i += 1;
+    }
+    nodes = next_nodes;
+  }
+
+  // All flags and helpers must have been used
+  flags.next().is_none() && helpers.next().is_none() && nodes.len() == 1 && nodes[0].1 == root
+}
+
+// Returns true if the given proof leads to the root, for a tree that was built from nr_leaves leaves with the given padding policy
+// Unlike verify_merkle_proof, this also works for PaddingPolicy::Promote, where some levels have no element in the proof
+pub fn verify_merkle_proof_with_padding(
+  leaf: GoldilocksField,
+  leaf_index: usize,
+  nr_leaves: usize,
+  padding: PaddingPolicy,
+  root: HashOut<GoldilocksField>,
+  hashes: Vec<HashOut<GoldilocksField>>) -> bool {
+  if leaf_index >= nr_leaves {
+    return false;
+  }
+  let mut next_hash: HashOut<GoldilocksField> = PoseidonHash::hash_or_noop(&[leaf]);
+  let mut updated_index = leaf_index;
+  let mut level_size = if padding == PaddingPolicy::ZeroHash { nr_leaves.next_power_of_two() } else { nr_leaves };
+  let mut proof_elms = hashes.into_iter();
+
+  while level_size > 1 {
+    // A promoted node is the last node on an odd level; it has no sibling
+    let promoted = padding == PaddingPolicy::Promote && updated_index.is_even() && updated_index + 1 == level_size;
+    if !promoted {
+      let sibling = match proof_elms.next() {
+        Some(sibling) => sibling,
+        None => return false
+      };
+      if updated_index.is_even() {
+        next_hash = PoseidonHash::two_to_one(next_hash, sibling);
+      } else {
+        next_hash = PoseidonHash::two_to_one(sibling, next_hash);
+      }
+    }
+    updated_index = updated_index/2;
+    level_size = (level_size + 1) / 2;
+  }
+
+  // All elements of the proof must have been used
+  proof_elms.next().is_none() && next_hash == root
+}
+
 #[cfg(test)]
 mod tests {
   use anyhow::Result;
-  use plonky2::{plonk::config::{GenericConfig, PoseidonGoldilocksConfig}, hash::hash_types::HashOut, field::{goldilocks_field::GoldilocksField, types::Field}};
-  use crate::simple_merkle_tree::simple_merkle_tree::{MerkleTree, verify_merkle_proof};
+  use plonky2::{plonk::config::{GenericConfig, PoseidonGoldilocksConfig, Hasher}, hash::{hash_types::HashOut, poseidon::PoseidonHash}, field::{goldilocks_field::GoldilocksField, types::Field}};
+  use crate::simple_merkle_tree::simple_merkle_tree::{MerkleTree, PaddingPolicy, verify_merkle_proof, verify_merkle_proof_with_padding, verify_multiproof};
 
   #[test]
   fn test_build_merkle_tree_4_leaves() -> Result<()> {
@@ -126,7 +456,7 @@ mod tests {
     let _tree: MerkleTree = MerkleTree::build(leaves);
     
     // println!( "{:?}", tree.count_levels);
-    // println!( "{:?}", tree.tree);
+    // println!( "{:?}", tree.nodes);
     // println!( "{:?}", tree.root);
     /*
     Output looks something like this:
@@ -169,7 +499,7 @@ mod tests {
     let _tree: MerkleTree  = MerkleTree::build(leaves);
     
     // println!( "{:?}", tree.count_levels);
-    // println!( "{:?}", tree.tree);
+    // println!( "{:?}", tree.nodes);
     // println!( "{:?}", tree.root);
     /*
     Output looks something like this:
@@ -206,7 +536,7 @@ mod tests {
       ].to_vec();
     let tree: MerkleTree  = MerkleTree::build(leaves);
     
-    let res_leaf_0 = tree.clone().get_merkle_proof(0);
+    let res_leaf_0 = tree.get_merkle_proof(0);
     assert!(res_leaf_0[0] == HashOut { elements: [F::from_canonical_u64(156728478), F::default(), F::default(), F::default()] });
     assert!(res_leaf_0[1] == HashOut { elements: [F::from_canonical_u64(6698018865469624861), F::from_canonical_u64(12486244005715193285), F::from_canonical_u64(11330639022572315007), F::from_canonical_u64(6059804404595156248)] });
     Ok(())
@@ -226,8 +556,8 @@ mod tests {
       ].to_vec();
     let tree: MerkleTree = MerkleTree::build(leaves.clone());
     
-    let res_leaf_0 = tree.clone().get_merkle_proof(0);
-    let res_leaf_3 = tree.clone().get_merkle_proof(3);
+    let res_leaf_0 = tree.get_merkle_proof(0);
+    let res_leaf_3 = tree.get_merkle_proof(3);
 
     assert!(verify_merkle_proof(leaves[0], 0, tree.root, res_leaf_0));
     assert!(verify_merkle_proof(leaves[3], 3, tree.root, res_leaf_3));
@@ -260,22 +590,22 @@ mod tests {
       ].to_vec();
     let tree: MerkleTree = MerkleTree::build(leaves.clone());
     
-    let res_leaf_0 = tree.clone().get_merkle_proof(0);
-    let res_leaf_1 = tree.clone().get_merkle_proof(1);
-    let res_leaf_2 = tree.clone().get_merkle_proof(2);
-    let res_leaf_3 = tree.clone().get_merkle_proof(3);
-    let res_leaf_4 = tree.clone().get_merkle_proof(4);
-    let res_leaf_5 = tree.clone().get_merkle_proof(5);
-    let res_leaf_6 = tree.clone().get_merkle_proof(6);
-    let res_leaf_7 = tree.clone().get_merkle_proof(7);
-    let res_leaf_8 = tree.clone().get_merkle_proof(8);
-    let res_leaf_9 = tree.clone().get_merkle_proof(9);
-    let res_leaf_10 = tree.clone().get_merkle_proof(10);
-    let res_leaf_11 = tree.clone().get_merkle_proof(11);
-    let res_leaf_12 = tree.clone().get_merkle_proof(12);
-    let res_leaf_13 = tree.clone().get_merkle_proof(13);
-    let res_leaf_14 = tree.clone().get_merkle_proof(14);
-    let res_leaf_15 = tree.clone().get_merkle_proof(15);
+    let res_leaf_0 = tree.get_merkle_proof(0);
+    let res_leaf_1 = tree.get_merkle_proof(1);
+    let res_leaf_2 = tree.get_merkle_proof(2);
+    let res_leaf_3 = tree.get_merkle_proof(3);
+    let res_leaf_4 = tree.get_merkle_proof(4);
+    let res_leaf_5 = tree.get_merkle_proof(5);
+    let res_leaf_6 = tree.get_merkle_proof(6);
+    let res_leaf_7 = tree.get_merkle_proof(7);
+    let res_leaf_8 = tree.get_merkle_proof(8);
+    let res_leaf_9 = tree.get_merkle_proof(9);
+    let res_leaf_10 = tree.get_merkle_proof(10);
+    let res_leaf_11 = tree.get_merkle_proof(11);
+    let res_leaf_12 = tree.get_merkle_proof(12);
+    let res_leaf_13 = tree.get_merkle_proof(13);
+    let res_leaf_14 = tree.get_merkle_proof(14);
+    let res_leaf_15 = tree.get_merkle_proof(15);
 
     // Assert correct proofs
     assert!(verify_merkle_proof(leaves[0], 0, tree.root, res_leaf_0.clone()));
@@ -303,9 +633,175 @@ mod tests {
     // wrong proof
     assert!(!verify_merkle_proof(leaves[0], 0, tree.root, res_leaf_1.clone()));
     // wrong root
-    assert!(!verify_merkle_proof(leaves[0], 0, tree.tree[0][0], res_leaf_0.clone()));
+    assert!(!verify_merkle_proof(leaves[0], 0, tree.level(0)[0], res_leaf_0.clone()));
 
     Ok(())
   }
 
+  #[test]
+  fn test_empty_and_single_leaf_tree() {
+    for padding in [PaddingPolicy::ZeroHash, PaddingPolicy::DuplicateLast, PaddingPolicy::Promote] {
+      let empty = MerkleTree::build_with_padding(Vec::new(), padding);
+      assert!(empty.root == HashOut::ZERO);
+      assert!(empty.count_levels == 0);
+
+      let leaf = GoldilocksField::from_canonical_u64(42);
+      let tree = MerkleTree::build_with_padding([leaf].to_vec(), padding);
+      assert!(tree.root == PoseidonHash::hash_or_noop(&[leaf]));
+      let proof = tree.get_merkle_proof(0);
+      assert!(proof.is_empty());
+      assert!(verify_merkle_proof(leaf, 0, tree.root, proof.clone()));
+      assert!(verify_merkle_proof_with_padding(leaf, 0, 1, padding, tree.root, proof));
+    }
+  }
+
+  #[test]
+  fn test_padding_policies_all_sizes() {
+    for nr_leaves in 1..40 {
+      let leaves: Vec<GoldilocksField> = (0..nr_leaves).map(|i| GoldilocksField::from_canonical_u64(1000 + i)).collect();
+
+      for padding in [PaddingPolicy::ZeroHash, PaddingPolicy::DuplicateLast, PaddingPolicy::Promote] {
+        let tree = MerkleTree::build_with_padding(leaves.clone(), padding);
+        for i in 0..leaves.len() {
+          let proof = tree.get_merkle_proof(i);
+          assert!(verify_merkle_proof_with_padding(leaves[i], i, leaves.len(), padding, tree.root, proof.clone()));
+          // Proofs for the padded trees are full paths, so they also verify with the standard function
+          if padding != PaddingPolicy::Promote {
+            assert!(proof.len() == tree.count_levels);
+            assert!(verify_merkle_proof(leaves[i], i, tree.root, proof.clone()));
+          }
+          // wrong leaf
+          assert!(!verify_merkle_proof_with_padding(leaves[(i + 1) % leaves.len()], i, leaves.len(), padding, tree.root, proof));
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn test_padding_policies() {
+    let leaves: Vec<GoldilocksField> = (0..5).map(|i| GoldilocksField::from_canonical_u64(1000 + i)).collect();
+    let hashes: Vec<HashOut<GoldilocksField>> = leaves.iter().map(|leaf| PoseidonHash::hash_or_noop(&[*leaf])).collect();
+
+    // Zero hash padding equals a tree of 8 leaves where the last 3 are zero
+    let mut padded_leaves = leaves.clone();
+    padded_leaves.resize(8, GoldilocksField::ZERO);
+    assert!(MerkleTree::build_with_padding(leaves.clone(), PaddingPolicy::ZeroHash).root == MerkleTree::build(padded_leaves).root);
+
+    // Duplicate last: ((h0 h1) (h2 h3)) ((h4 h4) (h4 h4))
+    let h01 = PoseidonHash::two_to_one(hashes[0], hashes[1]);
+    let h23 = PoseidonHash::two_to_one(hashes[2], hashes[3]);
+    let h44 = PoseidonHash::two_to_one(hashes[4], hashes[4]);
+    let h4444 = PoseidonHash::two_to_one(h44, h44);
+    let expected = PoseidonHash::two_to_one(PoseidonHash::two_to_one(h01, h23), h4444);
+    assert!(MerkleTree::build_with_padding(leaves.clone(), PaddingPolicy::DuplicateLast).root == expected);
+
+    // Promote: ((h0 h1) (h2 h3)) h4
+    let expected = PoseidonHash::two_to_one(PoseidonHash::two_to_one(h01, h23), hashes[4]);
+    let tree = MerkleTree::build_with_padding(leaves.clone(), PaddingPolicy::Promote);
+    assert!(tree.root == expected);
+    // The proof of leaf 4 only holds the left subtree
+    assert!(tree.get_merkle_proof(4) == [PoseidonHash::two_to_one(h01, h23)].to_vec());
+  }
+
+  #[test]
+  fn test_update_leaf() {
+    for nr_leaves in [1, 2, 5, 16] {
+      let mut leaves: Vec<GoldilocksField> = (0..nr_leaves).map(|i| GoldilocksField::from_canonical_u64(1000 + i)).collect();
+      let mut tree = MerkleTree::build(leaves.clone());
+
+      for i in 0..leaves.len() {
+        let value = GoldilocksField::from_canonical_u64(5000 + i as u64);
+        let old_root = tree.root;
+        let proof = tree.update_leaf(i, value);
+        leaves[i] = value;
+
+        // The tree is the same as when it would be built with the new leaves
+        let rebuilt = MerkleTree::build(leaves.clone());
+        assert!(tree.root == rebuilt.root);
+        assert!(tree.nodes == rebuilt.nodes);
+
+        assert!(proof.old_root == old_root);
+        assert!(proof.new_root == tree.root);
+        assert!(proof.verify());
+      }
+    }
+  }
+
+  #[test]
+  fn test_update_leaves_chain() {
+    let leaves: Vec<GoldilocksField> = (0..8).map(|i| GoldilocksField::from_canonical_u64(1000 + i)).collect();
+    let mut tree = MerkleTree::build(leaves);
+    let first_root = tree.root;
+    let proofs = tree.update_leaves(&[
+      (3, GoldilocksField::from_canonical_u64(1)),
+      (5, GoldilocksField::from_canonical_u64(2)),
+      (3, GoldilocksField::from_canonical_u64(3))
+    ]);
+
+    assert!(proofs[0].old_root == first_root);
+    for i in 1..proofs.len() {
+      assert!(proofs[i].old_root == proofs[i - 1].new_root);
+    }
+    assert!(proofs[2].new_root == tree.root);
+    assert!(proofs.iter().all(|proof| proof.verify()));
+
+    // A proof with the wrong new leaf doesn't verify
+    let mut wrong = proofs[1].clone();
+    wrong.new_leaf = GoldilocksField::from_canonical_u64(4);
+    assert!(!wrong.verify());
+  }
+
+  #[test]
+  fn test_multiproof() {
+    let leaves: Vec<GoldilocksField> = (0..32).map(|i| GoldilocksField::from_canonical_u64(1000 + i)).collect();
+    let tree = MerkleTree::build(leaves.clone());
+
+    for indices in [[0].to_vec(), [0, 1].to_vec(), [3, 4, 17, 30].to_vec(), (0..32).collect(), [31, 2, 2, 9].to_vec()] {
+      let proof = tree.get_multiproof(&indices);
+      let proven_leaves: Vec<GoldilocksField> = proof.leaf_indices.iter().map(|i| leaves[*i]).collect();
+      assert!(verify_multiproof(&proven_leaves, tree.root, &proof));
+      // Never more helpers than independent proofs would need
+      assert!(proof.helpers.len() <= proof.leaf_indices.len() * tree.count_levels);
+    }
+
+    // All leaves need no helpers at all
+    assert!(tree.get_multiproof(&(0..32).collect::<Vec<usize>>()).helpers.is_empty());
+    // Neighbouring leaves share all helpers above the first level
+    assert!(tree.get_multiproof(&[6, 7]).helpers.len() == tree.count_levels - 1);
+  }
+
+  #[test]
+  fn test_multiproof_padding() {
+    let leaves: Vec<GoldilocksField> = (0..13).map(|i| GoldilocksField::from_canonical_u64(1000 + i)).collect();
+    for padding in [PaddingPolicy::ZeroHash, PaddingPolicy::DuplicateLast] {
+      let tree = MerkleTree::build_with_padding(leaves.clone(), padding);
+      let proof = tree.get_multiproof(&[1, 5, 12]);
+      assert!(verify_multiproof(&[leaves[1], leaves[5], leaves[12]], tree.root, &proof));
+    }
+  }
+
+  #[test]
+  fn test_wrong_multiproof() {
+    let leaves: Vec<GoldilocksField> = (0..16).map(|i| GoldilocksField::from_canonical_u64(1000 + i)).collect();
+    let tree = MerkleTree::build(leaves.clone());
+    let proof = tree.get_multiproof(&[2, 3, 9]);
+    let proven_leaves = [leaves[2], leaves[3], leaves[9]];
+    assert!(verify_multiproof(&proven_leaves, tree.root, &proof));
+
+    // wrong leaf order
+    assert!(!verify_multiproof(&[leaves[3], leaves[2], leaves[9]], tree.root, &proof));
+    // wrong flags
+    let mut wrong = proof.clone();
+    wrong.flags[0] = true;
+    assert!(!verify_multiproof(&proven_leaves, tree.root, &wrong));
+    // missing helper
+    let mut wrong = proof.clone();
+    wrong.helpers.pop();
+    assert!(!verify_multiproof(&proven_leaves, tree.root, &wrong));
+    // wrong index
+    let mut wrong = proof.clone();
+    wrong.leaf_indices[2] = 8;
+    assert!(!verify_multiproof(&proven_leaves, tree.root, &wrong));
+  }
+
 }
\ No newline at end of file
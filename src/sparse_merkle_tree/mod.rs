@@ -0,0 +1,2 @@
+pub mod sparse_merkle_tree;
+pub mod smt_plonky2_verifier;
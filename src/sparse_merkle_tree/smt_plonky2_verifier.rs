@@ -0,0 +1,307 @@
+use plonky2::{hash::{poseidon::PoseidonHash, hash_types::HashOutTarget}, plonk::{config::{PoseidonGoldilocksConfig, GenericConfig}, circuit_data::CircuitData, circuit_builder::CircuitBuilder}, iop::{target::{BoolTarget, Target}, witness::{PartialWitness, WitnessWrite}}};
+use plonky2_field::{goldilocks_field::GoldilocksField, types::Field};
+
+use crate::{config_profile::ConfigProfile, gadgets::MerkleGadgets, sparse_merkle_tree::sparse_merkle_tree::{path_bits_of_element, SmtProof, SmtTransitionProof, MAX_DEPTH}};
+
+// Targets of an SMT inclusion or exclusion proof that need to be set in the witness
+pub struct SmtProofTargets {
+  pub key: HashOutTarget,
+  // Only used for inclusion proofs; for exclusion proofs the value is the constant zero
+  pub value: Target,
+  pub siblings: Vec<HashOutTarget>
+}
+
+// Targets of an SMT state transition that need to be set in the witness
+pub struct SmtTransitionTargets {
+  pub key: HashOutTarget,
+  pub old_value: Target,
+  pub new_value: Target,
+  pub siblings: Vec<HashOutTarget>
+}
+
+impl SmtProofTargets {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &SmtProof) {
+    pw.set_hash_target(self.key, proof.key);
+    pw.set_target(self.value, proof.value);
+    for i in 0..self.siblings.len() {
+      pw.set_hash_target(self.siblings[i], proof.siblings[i]);
+    }
+  }
+}
+
+impl SmtTransitionTargets {
+  pub fn set_witness(&self, pw: &mut PartialWitness<GoldilocksField>, proof: &SmtTransitionProof) {
+    pw.set_hash_target(self.key, proof.key);
+    pw.set_target(self.old_value, proof.old_value);
+    pw.set_target(self.new_value, proof.new_value);
+    for i in 0..self.siblings.len() {
+      pw.set_hash_target(self.siblings[i], proof.siblings[i]);
+    }
+  }
+}
+
+// Returns the depth bits of the key, in the same order as key_to_path, and checks the key is below 2^depth
+// An element of the key that is entirely in the path is split into 64 bits. Since 2^64 is larger than the field order,
+//  the split is also checked to be canonical: if the upper 32 bits are all set, the lower 32 bits must be zero.
+// An element that is partly in the path is split into its bits in the path, which also checks it is below 2^bits;
+//  elements above the depth must be zero. Otherwise keys that only differ above the depth would share a leaf.
+pub fn key_to_path_circuit(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  key: HashOutTarget,
+  depth: usize) -> Vec<BoolTarget> {
+  assert!(depth <= MAX_DEPTH);
+  let mut path = Vec::new();
+  for (i, elm) in key.elements.into_iter().enumerate() {
+    match path_bits_of_element(i, depth) {
+      0 => builder.assert_zero(elm),
+      64 => {
+        let bits = builder.split_le(elm, 64);
+        let high_sum = builder.add_many(bits[32..].iter().map(|b| b.target));
+        let all_ones = builder.constant(GoldilocksField::from_canonical_u64(32));
+        let high_all_set = builder.is_equal(high_sum, all_ones);
+        let low = builder.le_sum(bits[..32].iter());
+        let should_be_zero = builder.mul(high_all_set.target, low);
+        builder.assert_zero(should_be_zero);
+        path.extend(bits);
+      },
+      nr_bits => path.extend(builder.split_le(elm, nr_bits))
+    }
+  }
+  path
+}
+
+// Hashes the leaf value up to the root, following the path. Returns the root
+pub fn compute_root_circuit(
+  builder: &mut CircuitBuilder<GoldilocksField, 2>,
+  path: &[BoolTarget],
+  value: Target,
+  siblings: &[HashOutTarget]) -> HashOutTarget {
+  let leaf_hash = builder.hash_or_noop::<PoseidonHash>([value].to_vec());
+  builder.verify_merkle_path(leaf_hash, siblings, path)
+}
+
+fn add_proof_targets(builder: &mut CircuitBuilder<GoldilocksField, 2>, depth: usize) -> SmtProofTargets {
+  SmtProofTargets {
+    key: builder.add_virtual_hash(),
+    value: builder.add_virtual_target(),
+    siblings: builder.add_virtual_hashes(depth)
+  }
+}
+
+// Returns a circuit that verifies that a key is in an SMT of the given depth, with a non-zero value
+// Public inputs are the root, the key and the value
+// The circuit is built with the CircuitConfig of [profile]
+pub fn verify_smt_inclusion_circuit(depth: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, SmtProofTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let targets = add_proof_targets(&mut builder, depth);
+
+  // Zero is the value of empty leaves, so it can't be included
+  let zero = builder.zero();
+  let value_is_zero = builder.is_equal(targets.value, zero);
+  builder.connect(value_is_zero.target, zero);
+
+  let path = key_to_path_circuit(&mut builder, targets.key, depth);
+  let root = compute_root_circuit(&mut builder, &path, targets.value, &targets.siblings);
+  builder.register_public_inputs(&root.elements);
+  builder.register_public_inputs(&targets.key.elements);
+  builder.register_public_input(targets.value);
+
+  (builder.build::<C>(), targets)
+}
+
+// Returns a circuit that verifies that a key is not in an SMT of the given depth, meaning its leaf is empty
+// Public inputs are the root and the key. The value of the returned targets has to be set to zero
+pub fn verify_smt_exclusion_circuit(depth: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, SmtProofTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let targets = add_proof_targets(&mut builder, depth);
+
+  // The value has to be set in the witness, but it has to be zero
+  let zero = builder.zero();
+  builder.connect(targets.value, zero);
+
+  let path = key_to_path_circuit(&mut builder, targets.key, depth);
+  let root = compute_root_circuit(&mut builder, &path, zero, &targets.siblings);
+  builder.register_public_inputs(&root.elements);
+  builder.register_public_inputs(&targets.key.elements);
+
+  (builder.build::<C>(), targets)
+}
+
+// Returns a circuit that verifies that changing the value of a single key changes the old root into the new root
+// This covers insertions (old value is zero), updates and deletions (new value is zero)
+// Public inputs are the old root, the new root, the key, the old value and the new value
+pub fn verify_smt_transition_circuit(depth: usize, profile: &ConfigProfile) -> (CircuitData<GoldilocksField, PoseidonGoldilocksConfig, 2>, SmtTransitionTargets) {
+  const D: usize = 2;
+  type C = PoseidonGoldilocksConfig;
+  type F = <C as GenericConfig<D>>::F;
+
+  let config = profile.circuit_config();
+  let mut builder: CircuitBuilder<plonky2::field::goldilocks_field::GoldilocksField, 2> = CircuitBuilder::<F, D>::new(config);
+  let targets = SmtTransitionTargets {
+    key: builder.add_virtual_hash(),
+    old_value: builder.add_virtual_target(),
+    new_value: builder.add_virtual_target(),
+    siblings: builder.add_virtual_hashes(depth)
+  };
+
+  // Both roots are computed with the same path and siblings; only the leaf differs
+  let path = key_to_path_circuit(&mut builder, targets.key, depth);
+  let old_root = compute_root_circuit(&mut builder, &path, targets.old_value, &targets.siblings);
+  let new_root = compute_root_circuit(&mut builder, &path, targets.new_value, &targets.siblings);
+  builder.register_public_inputs(&old_root.elements);
+  builder.register_public_inputs(&new_root.elements);
+  builder.register_public_inputs(&targets.key.elements);
+  builder.register_public_input(targets.old_value);
+  builder.register_public_input(targets.new_value);
+
+  (builder.build::<C>(), targets)
+}
+
+#[cfg(test)]
+mod tests {
+  use anyhow::Result;
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, hash::hash_types::HashOut, iop::witness::{PartialWitness, WitnessWrite}};
+  use rand::Rng;
+
+  use crate::{config_profile::ConfigProfile, mmr::common::GOLDILOCKS_FIELD_ORDER, sparse_merkle_tree::sparse_merkle_tree::{path_bits_of_element, SparseMerkleTree}};
+  use super::{verify_smt_inclusion_circuit, verify_smt_exclusion_circuit, verify_smt_transition_circuit};
+
+  fn random_element() -> GoldilocksField {
+    let mut rng = rand::thread_rng();
+    GoldilocksField::from_canonical_u64(rng.gen_range(1..GOLDILOCKS_FIELD_ORDER))
+  }
+
+  // A random key below 2^depth
+  fn random_key(depth: usize) -> HashOut<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    HashOut { elements: [0, 1, 2, 3].map(|i| match path_bits_of_element(i, depth) {
+      64 => random_element(),
+      nr_bits => GoldilocksField::from_canonical_u64(rng.gen_range(0..1u64 << nr_bits))
+    }) }
+  }
+
+  fn test_tree(depth: usize, keys: &[HashOut<GoldilocksField>]) -> SparseMerkleTree {
+    let mut tree = SparseMerkleTree::new(depth);
+    for key in keys {
+      tree.insert(*key, random_element()).unwrap();
+    }
+    tree
+  }
+
+  fn test_inclusion(depth: usize) -> Result<()> {
+    let keys: Vec<HashOut<GoldilocksField>> = (0..5).map(|_| random_key(depth)).collect();
+    let tree = test_tree(depth, &keys);
+    let (circuit_data, targets) = verify_smt_inclusion_circuit(depth, &ConfigProfile::default());
+
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &tree.get_proof(keys[2]));
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs == [tree.root.elements.to_vec(), keys[2].elements.to_vec(), [tree.get(keys[2])].to_vec()].concat());
+    circuit_data.verify(proof)
+  }
+
+  fn test_exclusion(depth: usize) -> Result<()> {
+    let keys: Vec<HashOut<GoldilocksField>> = (0..5).map(|_| random_key(depth)).collect();
+    let tree = test_tree(depth, &keys);
+    let (circuit_data, targets) = verify_smt_exclusion_circuit(depth, &ConfigProfile::default());
+
+    let absent = random_key(depth);
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &tree.get_proof(absent));
+    let proof = circuit_data.prove(pw)?;
+    assert!(proof.public_inputs == [tree.root.elements, absent.elements].concat());
+    circuit_data.verify(proof)
+  }
+
+  #[test]
+  fn test_inclusion_depth_64() -> Result<()> {
+    test_inclusion(64)
+  }
+
+  #[test]
+  fn test_inclusion_depth_256() -> Result<()> {
+    test_inclusion(256)
+  }
+
+  #[test]
+  fn test_exclusion_depth_64() -> Result<()> {
+    test_exclusion(64)
+  }
+
+  #[test]
+  fn test_exclusion_depth_256() -> Result<()> {
+    test_exclusion(256)
+  }
+
+  // A key that is in the tree has no exclusion proof for its root
+  #[test]
+  #[should_panic]
+  fn test_exclusion_of_present_key_fails() {
+    let keys: Vec<HashOut<GoldilocksField>> = (0..5).map(|_| random_key(64)).collect();
+    let tree = test_tree(64, &keys);
+    let (circuit_data, targets) = verify_smt_exclusion_circuit(64, &ConfigProfile::default());
+
+    let mut proof = tree.get_proof(keys[0]);
+    proof.value = GoldilocksField::ZERO;
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &proof);
+    let public_inputs = circuit_data.prover_only.public_inputs.clone();
+    for i in 0..4 {
+      pw.set_target(public_inputs[i], tree.root.elements[i]);
+    }
+    circuit_data.prove(pw).unwrap();
+  }
+
+  #[test]
+  fn test_transitions() -> Result<()> {
+    let depth = 64;
+    let keys: Vec<HashOut<GoldilocksField>> = (0..3).map(|_| random_key(depth)).collect();
+    let mut tree = test_tree(depth, &keys);
+    let (circuit_data, targets) = verify_smt_transition_circuit(depth, &ConfigProfile::default());
+
+    let new_key = random_key(depth);
+    let transitions = [
+      tree.insert(new_key, random_element())?,
+      tree.update(keys[1], random_element())?,
+      tree.delete(keys[0])?
+    ];
+    for transition in transitions {
+      let mut pw = PartialWitness::new();
+      targets.set_witness(&mut pw, &transition);
+      let proof = circuit_data.prove(pw)?;
+      assert!(proof.public_inputs == [
+        transition.old_root.elements.to_vec(),
+        transition.new_root.elements.to_vec(),
+        transition.key.elements.to_vec(),
+        [transition.old_value, transition.new_value].to_vec()
+      ].concat());
+      circuit_data.verify(proof)?;
+    }
+    Ok(())
+  }
+
+  // The proof for a key is no proof for a larger key that has the same first depth bits
+  #[test]
+  #[should_panic]
+  fn test_inclusion_of_colliding_key_fails() {
+    let keys: Vec<HashOut<GoldilocksField>> = (0..5).map(|_| random_key(64)).collect();
+    let tree = test_tree(64, &keys);
+    let (circuit_data, targets) = verify_smt_inclusion_circuit(64, &ConfigProfile::default());
+
+    let mut proof = tree.get_proof(keys[0]);
+    proof.key.elements[1] = GoldilocksField::ONE;
+    let mut pw = PartialWitness::new();
+    targets.set_witness(&mut pw, &proof);
+    circuit_data.prove(pw).unwrap();
+  }
+}
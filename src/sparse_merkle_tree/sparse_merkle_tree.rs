@@ -0,0 +1,367 @@
+// Sparse Merkle Tree impl
+
+use std::collections::HashMap;
+
+use anyhow::{ensure, Result};
+use plonky2::field::goldilocks_field::GoldilocksField;
+use plonky2::field::types::{Field, PrimeField64};
+use plonky2::hash::hash_types::HashOut;
+use plonky2::hash::poseidon::PoseidonHash;
+use plonky2::plonk::config::Hasher;
+
+// A key consists of 4 field elements, which gives at most 256 bits to pick a path with
+pub const MAX_DEPTH: usize = 256;
+
+/**
+ * Fixed-depth Sparse Merkle Tree, used as a key-value commitment.
+ *
+ * Every possible key has its own leaf; the bits of the key determine the path from the root to that leaf.
+ * Keys must be below 2^depth: a larger key would share its leaf with the key of only its first depth bits, since the
+ * leaf doesn't commit to the key. Such keys are rejected, natively and in the circuits.
+ * A leaf holding the value zero is empty. Since almost all leaves are empty, only the nodes that differ from
+ * the hash of an empty subtree are stored. The hashes of the empty subtrees are precomputed per height.
+ *
+ * Hashing is the same as for the simple Merkle Tree: leaf values are hashed with hash_or_noop, nodes with two_to_one.
+ * An empty leaf therefore hashes to the zero hash.
+ */
+#[derive(Debug, Clone)]
+pub struct SparseMerkleTree {
+  pub depth: usize,
+  // default_hashes[h] is the root of an empty subtree of height h; default_hashes[depth] is the root of the empty tree
+  pub default_hashes: Vec<HashOut<GoldilocksField>>,
+  // Nodes that are not equal to the default hash at their height, keyed by (height, bits of the path from that height up)
+  nodes: HashMap<(usize, Vec<bool>), HashOut<GoldilocksField>>,
+  // Values of all non-empty leaves, keyed by the path of the leaf
+  values: HashMap<Vec<bool>, GoldilocksField>,
+  pub root: HashOut<GoldilocksField>
+}
+
+// Proof that a key has a certain value in the tree. If the value is zero, it proves the key is not in the tree
+#[derive(Debug, Clone)]
+pub struct SmtProof {
+  pub key: HashOut<GoldilocksField>,
+  pub value: GoldilocksField,
+  // Siblings of the nodes on the path, starting at the leaf level and going up
+  pub siblings: Vec<HashOut<GoldilocksField>>
+}
+
+// Proof that changing the value of a single key changed the root from old_root to new_root
+// Only the leaf changes, so the siblings are the same before and after
+#[derive(Debug, Clone)]
+pub struct SmtTransitionProof {
+  pub key: HashOut<GoldilocksField>,
+  pub old_value: GoldilocksField,
+  pub new_value: GoldilocksField,
+  pub siblings: Vec<HashOut<GoldilocksField>>,
+  pub old_root: HashOut<GoldilocksField>,
+  pub new_root: HashOut<GoldilocksField>
+}
+
+// Number of bits of the key element at position i that are part of the path in a tree of the given depth
+pub fn path_bits_of_element(i: usize, depth: usize) -> usize {
+  depth.saturating_sub(64 * i).min(64)
+}
+
+// Returns whether the key is below 2^depth, meaning all its bits above the first depth are zero
+pub fn key_fits(key: HashOut<GoldilocksField>, depth: usize) -> bool {
+  key.elements.iter().enumerate().all(|(i, elm)| {
+    let nr_bits = path_bits_of_element(i, depth);
+    nr_bits == 64 || elm.to_canonical_u64() >> nr_bits == 0
+  })
+}
+
+// Returns the depth bits of the key, starting with the lowest bit of the first element.
+// Bit i decides whether the node at height i is a left (false) or right (true) child
+pub fn key_to_path(key: HashOut<GoldilocksField>, depth: usize) -> Vec<bool> {
+  assert!(depth <= MAX_DEPTH);
+  assert!(key_fits(key, depth), "key is not below 2^depth");
+  key.elements.iter()
+    .flat_map(|elm| {
+      let value = elm.to_canonical_u64();
+      (0..64).map(move |i| (value >> i) & 1 == 1)
+    })
+    .take(depth)
+    .collect()
+}
+
+// Empty leaves hold zero, which hashes to the zero hash
+pub fn hash_leaf(value: GoldilocksField) -> HashOut<GoldilocksField> {
+  PoseidonHash::hash_or_noop(&[value])
+}
+
+// Hashes from the leaf up to the root, given the siblings along the path
+pub fn compute_root(path: &[bool], value: GoldilocksField, siblings: &[HashOut<GoldilocksField>]) -> HashOut<GoldilocksField> {
+  assert!(path.len() == siblings.len());
+  let mut next_hash = hash_leaf(value);
+  for (is_right, sibling) in path.iter().zip(siblings) {
+    if *is_right {
+      next_hash = PoseidonHash::two_to_one(*sibling, next_hash);
+    } else {
+      next_hash = PoseidonHash::two_to_one(next_hash, *sibling);
+    }
+  }
+  next_hash
+}
+
+impl SparseMerkleTree {
+  // Create an empty tree of the given depth
+  pub fn new(depth: usize) -> Self {
+    assert!(depth > 0 && depth <= MAX_DEPTH);
+    let mut default_hashes = vec![hash_leaf(GoldilocksField::ZERO)];
+    for h in 0..depth {
+      default_hashes.push(PoseidonHash::two_to_one(default_hashes[h], default_hashes[h]));
+    }
+    SparseMerkleTree {
+      depth: depth,
+      root: default_hashes[depth],
+      default_hashes: default_hashes,
+      nodes: HashMap::new(),
+      values: HashMap::new()
+    }
+  }
+
+  // Returns the value for the key, zero if the key is not in the tree. Panics if the key is not below 2^depth
+  pub fn get(&self, key: HashOut<GoldilocksField>) -> GoldilocksField {
+    let path = key_to_path(key, self.depth);
+    *self.values.get(&path).unwrap_or(&GoldilocksField::ZERO)
+  }
+
+  pub fn contains(&self, key: HashOut<GoldilocksField>) -> bool {
+    self.get(key) != GoldilocksField::ZERO
+  }
+
+  // Add a key that is not in the tree yet
+  pub fn insert(&mut self, key: HashOut<GoldilocksField>, value: GoldilocksField) -> Result<SmtTransitionProof> {
+    ensure!(key_fits(key, self.depth), "key is not below 2^depth");
+    ensure!(!self.contains(key), "key is already in the tree");
+    ensure!(value != GoldilocksField::ZERO, "zero is reserved for empty leaves");
+    Ok(self.set(key, value))
+  }
+
+  // Change the value of a key that is in the tree
+  pub fn update(&mut self, key: HashOut<GoldilocksField>, value: GoldilocksField) -> Result<SmtTransitionProof> {
+    ensure!(key_fits(key, self.depth), "key is not below 2^depth");
+    ensure!(self.contains(key), "key is not in the tree");
+    ensure!(value != GoldilocksField::ZERO, "zero is reserved for empty leaves, use delete instead");
+    Ok(self.set(key, value))
+  }
+
+  // Remove a key from the tree, which empties its leaf
+  pub fn delete(&mut self, key: HashOut<GoldilocksField>) -> Result<SmtTransitionProof> {
+    ensure!(key_fits(key, self.depth), "key is not below 2^depth");
+    ensure!(self.contains(key), "key is not in the tree");
+    Ok(self.set(key, GoldilocksField::ZERO))
+  }
+
+  // Returns the proof for the key. This is an inclusion proof if the key is in the tree and an exclusion proof otherwise
+  // Panics if the key is not below 2^depth
+  pub fn get_proof(&self, key: HashOut<GoldilocksField>) -> SmtProof {
+    let path = key_to_path(key, self.depth);
+    SmtProof {
+      key: key,
+      value: self.get(key),
+      siblings: self.get_siblings(&path)
+    }
+  }
+
+  fn get_node(&self, height: usize, node_path: &[bool]) -> HashOut<GoldilocksField> {
+    *self.nodes.get(&(height, node_path.to_vec())).unwrap_or(&self.default_hashes[height])
+  }
+
+  fn get_siblings(&self, path: &[bool]) -> Vec<HashOut<GoldilocksField>> {
+    (0..self.depth).map(|h| {
+      // The sibling shares the path above height h, but sits on the other side
+      let mut sibling_path = path[h..].to_vec();
+      sibling_path[0] = !sibling_path[0];
+      self.get_node(h, &sibling_path)
+    }).collect()
+  }
+
+  // Set the leaf for the key and recompute the nodes on its path
+  fn set(&mut self, key: HashOut<GoldilocksField>, value: GoldilocksField) -> SmtTransitionProof {
+    let path = key_to_path(key, self.depth);
+    let siblings = self.get_siblings(&path);
+    let old_value = self.get(key);
+    let old_root = self.root;
+
+    if value == GoldilocksField::ZERO {
+      self.values.remove(&path);
+    } else {
+      self.values.insert(path.clone(), value);
+    }
+
+    let mut next_hash = hash_leaf(value);
+    for h in 0..self.depth {
+      // Keep the tree sparse: nodes equal to the default hash are not stored
+      if next_hash == self.default_hashes[h] {
+        self.nodes.remove(&(h, path[h..].to_vec()));
+      } else {
+        self.nodes.insert((h, path[h..].to_vec()), next_hash);
+      }
+      if path[h] {
+        next_hash = PoseidonHash::two_to_one(siblings[h], next_hash);
+      } else {
+        next_hash = PoseidonHash::two_to_one(next_hash, siblings[h]);
+      }
+    }
+    self.root = next_hash;
+
+    SmtTransitionProof {
+      key: key,
+      old_value: old_value,
+      new_value: value,
+      siblings: siblings,
+      old_root: old_root,
+      new_root: self.root
+    }
+  }
+}
+
+impl SmtProof {
+  // Returns true if the key is in the tree with the root, with the value of this proof
+  pub fn verify_inclusion(&self, root: HashOut<GoldilocksField>) -> bool {
+    if !key_fits(self.key, self.siblings.len()) {
+      return false;
+    }
+    let path = key_to_path(self.key, self.siblings.len());
+    self.value != GoldilocksField::ZERO && compute_root(&path, self.value, &self.siblings) == root
+  }
+
+  // Returns true if the key is not in the tree with the root
+  pub fn verify_exclusion(&self, root: HashOut<GoldilocksField>) -> bool {
+    if !key_fits(self.key, self.siblings.len()) {
+      return false;
+    }
+    let path = key_to_path(self.key, self.siblings.len());
+    self.value == GoldilocksField::ZERO && compute_root(&path, GoldilocksField::ZERO, &self.siblings) == root
+  }
+}
+
+impl SmtTransitionProof {
+  // Returns true if both the old and the new value lead to their respective roots with the same siblings
+  pub fn verify(&self) -> bool {
+    if !key_fits(self.key, self.siblings.len()) {
+      return false;
+    }
+    let path = key_to_path(self.key, self.siblings.len());
+    compute_root(&path, self.old_value, &self.siblings) == self.old_root
+      && compute_root(&path, self.new_value, &self.siblings) == self.new_root
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use plonky2::{field::{goldilocks_field::GoldilocksField, types::Field}, hash::hash_types::HashOut};
+  use rand::Rng;
+
+  use crate::{mmr::common::GOLDILOCKS_FIELD_ORDER, sparse_merkle_tree::sparse_merkle_tree::{path_bits_of_element, SparseMerkleTree}};
+
+  // A random key below 2^depth
+  fn random_key(depth: usize) -> HashOut<GoldilocksField> {
+    let mut rng = rand::thread_rng();
+    HashOut { elements: [0, 1, 2, 3].map(|i| match path_bits_of_element(i, depth) {
+      64 => GoldilocksField::from_canonical_u64(rng.gen_range(0..GOLDILOCKS_FIELD_ORDER)),
+      nr_bits => GoldilocksField::from_canonical_u64(rng.gen_range(0..1u64 << nr_bits))
+    }) }
+  }
+
+  #[test]
+  fn test_empty_tree() {
+    let tree = SparseMerkleTree::new(64);
+    assert!(tree.root == tree.default_hashes[64]);
+    let proof = tree.get_proof(random_key(64));
+    assert!(proof.verify_exclusion(tree.root));
+    assert!(!proof.verify_inclusion(tree.root));
+  }
+
+  #[test]
+  fn test_insert_update_delete() {
+    for depth in [64, 256] {
+      let mut tree = SparseMerkleTree::new(depth);
+      let empty_root = tree.root;
+      let keys: Vec<HashOut<GoldilocksField>> = (0..10).map(|_| random_key(depth)).collect();
+
+      for (i, key) in keys.iter().enumerate() {
+        let transition = tree.insert(*key, GoldilocksField::from_canonical_usize(i + 1)).unwrap();
+        assert!(transition.verify());
+        assert!(transition.new_root == tree.root);
+      }
+      // Inserting twice is not allowed
+      assert!(tree.insert(keys[0], GoldilocksField::ONE).is_err());
+
+      for (i, key) in keys.iter().enumerate() {
+        let proof = tree.get_proof(*key);
+        assert!(proof.value == GoldilocksField::from_canonical_usize(i + 1));
+        assert!(proof.verify_inclusion(tree.root));
+        assert!(!proof.verify_exclusion(tree.root));
+      }
+
+      let transition = tree.update(keys[3], GoldilocksField::from_canonical_u64(1000)).unwrap();
+      assert!(transition.verify());
+      assert!(tree.get_proof(keys[3]).verify_inclusion(tree.root));
+
+      // Absent keys have exclusion proofs
+      let absent = random_key(depth);
+      assert!(tree.get_proof(absent).verify_exclusion(tree.root));
+      assert!(tree.update(absent, GoldilocksField::ONE).is_err());
+      assert!(tree.delete(absent).is_err());
+
+      // Deleting all keys brings back the empty tree
+      for key in &keys {
+        let transition = tree.delete(*key).unwrap();
+        assert!(transition.verify());
+        assert!(tree.get_proof(*key).verify_exclusion(tree.root));
+      }
+      assert!(tree.root == empty_root);
+    }
+  }
+
+  #[test]
+  fn test_wrong_proofs_fail() {
+    let mut tree = SparseMerkleTree::new(64);
+    let key0 = random_key(64);
+    let key1 = random_key(64);
+    tree.insert(key0, GoldilocksField::from_canonical_u64(5)).unwrap();
+    tree.insert(key1, GoldilocksField::from_canonical_u64(6)).unwrap();
+
+    // wrong value
+    let mut proof = tree.get_proof(key0);
+    proof.value = GoldilocksField::from_canonical_u64(6);
+    assert!(!proof.verify_inclusion(tree.root));
+    // claiming a present key is absent
+    proof.value = GoldilocksField::ZERO;
+    assert!(!proof.verify_exclusion(tree.root));
+    // wrong key
+    let mut proof = tree.get_proof(key0);
+    proof.key = key1;
+    assert!(!proof.verify_inclusion(tree.root));
+  }
+
+  // Keys that only differ above the depth would share a leaf, so the larger one is rejected
+  #[test]
+  fn test_colliding_keys() {
+    let mut tree = SparseMerkleTree::new(64);
+    let key = HashOut { elements: [GoldilocksField::from_canonical_u64(5), GoldilocksField::ZERO, GoldilocksField::ZERO, GoldilocksField::ZERO] };
+    let colliding_key = HashOut { elements: [GoldilocksField::from_canonical_u64(5), GoldilocksField::ONE, GoldilocksField::ZERO, GoldilocksField::ZERO] };
+    tree.insert(key, GoldilocksField::from_canonical_u64(7)).unwrap();
+    assert!(tree.insert(colliding_key, GoldilocksField::ONE).is_err());
+    assert!(tree.update(colliding_key, GoldilocksField::ONE).is_err());
+    assert!(tree.delete(colliding_key).is_err());
+
+    // The proof for the key can't be passed off as one for the colliding key
+    let mut proof = tree.get_proof(key);
+    assert!(proof.verify_inclusion(tree.root));
+    proof.key = colliding_key;
+    assert!(!proof.verify_inclusion(tree.root));
+    let mut transition = tree.update(key, GoldilocksField::from_canonical_u64(8)).unwrap();
+    transition.key = colliding_key;
+    assert!(!transition.verify());
+  }
+
+  #[test]
+  #[should_panic]
+  fn test_get_colliding_key() {
+    let tree = SparseMerkleTree::new(64);
+    tree.get(HashOut { elements: [GoldilocksField::ONE; 4] });
+  }
+}